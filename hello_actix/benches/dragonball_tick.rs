@@ -0,0 +1,54 @@
+//! `GameState::tick` with crowded arenas. Run with `cargo bench --bench dragonball_tick`.
+use app_template_backend::dragonballgame::{
+    Fireball, FireballKind, FireballState, GameConfig, GameState, CANVAS_HEIGHT, CANVAS_WIDTH, DEFAULT_TICK_RATE,
+};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+
+const FIREBALL_COUNTS: [usize; 4] = [10, 100, 1_000, 10_000];
+
+/// A state with `count` active fireballs of every kind spread over the left half of the arena,
+/// so none reach the line during a tick, and `players` defenders spread along it
+fn crowded(count: usize, players: usize) -> GameState {
+    let config = GameConfig::default();
+    let mut state = GameState::with_seed(1, DEFAULT_TICK_RATE, config.clone());
+    for seat in 1..players {
+        state.add_player();
+        state.players[seat].y = CANVAS_HEIGHT * seat as f64 / players as f64;
+    }
+    let kinds = [FireballKind::Normal, FireballKind::Curving, FireballKind::Splitting];
+    for i in 0..count {
+        state.fireball_id_counter += 1;
+        state.fireballs.push(Fireball {
+            id: state.fireball_id_counter,
+            x: (i % 400) as f64 * CANVAS_WIDTH / 2.0 / 400.0,
+            y: (i * 37 % 600) as f64 * CANVAS_HEIGHT / 600.0,
+            vx: config.fireball_speed_base,
+            vy: 0.0,
+            ay: 0.0,
+            kind: kinds[i % kinds.len()].clone(),
+            state: FireballState::Active,
+            extinguish_timer: 1.0,
+        });
+    }
+    state
+}
+
+fn tick(c: &mut Criterion) {
+    for players in [1, 4] {
+        let mut group = c.benchmark_group(format!("tick/{}_defenders", players));
+        for count in FIREBALL_COUNTS {
+            let state = crowded(count, players);
+            group.throughput(Throughput::Elements(count as u64));
+            group.bench_with_input(BenchmarkId::from_parameter(count), &state, |b, state| {
+                b.iter_batched(|| state.clone(), |mut state| {
+                    state.tick();
+                    state
+                }, BatchSize::SmallInput);
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, tick);
+criterion_main!(benches);
@@ -0,0 +1,319 @@
+use actix_web::{web, HttpResponse, Responder};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use crate::{AppState, leaderboard, spectate, validate_token};
+use crate::dragonballgame::GameState;
+use crate::xandzero::{GameMode, XandZeroGame};
+use crate::xandzero_ai::Difficulty;
+
+/// Leaderboard places that unlock `top_ten`
+const TOP_RANKS: i64 = 10;
+
+/// What progress is counted in. Most counters add up; a few keep the best value seen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Counter {
+    FireballsExtinguished,
+    BossesDefeated,
+    HighestWave, // Best wave a dragonball run ended in
+    CoopRuns,    // Co-op runs played to the end
+    XandzeroWins,
+    HardAiWins,
+    SuddenDeathCleanWins, // Sudden-death wins without using a power-up
+    TopTen,               // 1 once the player held a top ten place on a game leaderboard
+}
+
+impl Counter {
+    /// Name used in database rows
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Counter::FireballsExtinguished => "fireballs_extinguished",
+            Counter::BossesDefeated => "bosses_defeated",
+            Counter::HighestWave => "highest_wave",
+            Counter::CoopRuns => "coop_runs",
+            Counter::XandzeroWins => "xandzero_wins",
+            Counter::HardAiWins => "hard_ai_wins",
+            Counter::SuddenDeathCleanWins => "sudden_death_clean_wins",
+            Counter::TopTen => "top_ten",
+        }
+    }
+
+    fn keeps_best(self) -> bool {
+        matches!(self, Counter::HighestWave | Counter::TopTen)
+    }
+}
+
+/// An achievement unlocks once its counter reaches `goal`
+pub struct Achievement {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+    pub counter: Counter,
+    pub goal: i64,
+}
+
+pub const ACHIEVEMENTS: [Achievement; 11] = [
+    Achievement { id: "first_flame", name: "First Flame", description: "Extinguish a fireball", counter: Counter::FireballsExtinguished, goal: 1 },
+    Achievement { id: "firefighter", name: "Firefighter", description: "Extinguish 100 fireballs", counter: Counter::FireballsExtinguished, goal: 100 },
+    Achievement { id: "fire_brigade", name: "Fire Brigade", description: "Extinguish 1,000 fireballs", counter: Counter::FireballsExtinguished, goal: 1000 },
+    Achievement { id: "dragon_slayer", name: "Dragon Slayer", description: "Defeat a boss dragon", counter: Counter::BossesDefeated, goal: 1 },
+    Achievement { id: "wave_rider", name: "Wave Rider", description: "Reach wave 10 in Dragonball", counter: Counter::HighestWave, goal: 10 },
+    Achievement { id: "team_player", name: "Team Player", description: "Finish a co-op Dragonball run", counter: Counter::CoopRuns, goal: 1 },
+    Achievement { id: "first_win", name: "First Win", description: "Win a game of X and Zero", counter: Counter::XandzeroWins, goal: 1 },
+    Achievement { id: "veteran", name: "Veteran", description: "Win 50 games of X and Zero", counter: Counter::XandzeroWins, goal: 50 },
+    Achievement { id: "giant_slayer", name: "Giant Slayer", description: "Beat the hard AI", counter: Counter::HardAiWins, goal: 1 },
+    Achievement { id: "pure_instinct", name: "Pure Instinct", description: "Win sudden death without power-ups", counter: Counter::SuddenDeathCleanWins, goal: 1 },
+    Achievement { id: "top_ten", name: "Top Ten", description: "Reach the top 10 of a game leaderboard", counter: Counter::TopTen, goal: 1 },
+];
+
+/// A freshly unlocked achievement, as sent to the player
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Unlocked {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub unlocked_at: chrono::NaiveDateTime,
+}
+
+/// Messages published on a player's notification channel and relayed to their websockets
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Notification {
+    AchievementUnlocked { achievement: Unlocked },
+}
+
+/// Valkey pub/sub channel carrying `Notification`s for one player, whichever instance they are connected to
+pub fn notifications_channel(user_id: &str) -> String {
+    format!("user:{}:notifications", user_id)
+}
+
+/// Relays the player's notifications into a connection's channel
+pub fn relay(redis_client: redis::Client, user_id: &str, tx: mpsc::Sender<String>) -> JoinHandle<()> {
+    spectate::relay_channel(redis_client, notifications_channel(user_id), tx)
+}
+
+/// Counters a finished X and Zero game moves for the side playing `mark`
+pub fn xandzero_progress(game: &XandZeroGame, mark: &str) -> Vec<(Counter, i64)> {
+    if game.winner.as_deref() != Some(mark) {
+        return Vec::new();
+    }
+    let mut progress = vec![(Counter::XandzeroWins, 1)];
+    if !game.pvp && game.difficulty == Difficulty::Hard {
+        progress.push((Counter::HardAiWins, 1));
+    }
+    if game.game_mode == GameMode::SuddenDeath && !game.power_up_uses.iter().any(|u| u.mark == mark) {
+        progress.push((Counter::SuddenDeathCleanWins, 1));
+    }
+    progress
+}
+
+/// Counters a dragonball run moves for the defender in `seat`. Bosses and waves are shared.
+pub fn dragonball_progress(state: &GameState, seat: usize, coop: bool) -> Vec<(Counter, i64)> {
+    let mut progress = vec![
+        (Counter::FireballsExtinguished, state.extinguished.get(seat).copied().unwrap_or(0) as i64),
+        (Counter::BossesDefeated, state.bosses_defeated() as i64),
+        (Counter::HighestWave, state.wave as i64),
+    ];
+    if coop && state.game_over {
+        progress.push((Counter::CoopRuns, 1));
+    }
+    progress
+}
+
+/// Moves one counter and returns its new value
+async fn bump(data: &AppState, user_id: &str, counter: Counter, value: i64) -> Result<i64, sqlx::Error> {
+    let merge = if counter.keeps_best() { "GREATEST(achievement_progress.value, EXCLUDED.value)" } else { "achievement_progress.value + EXCLUDED.value" };
+    sqlx::query_scalar(&format!(
+        "INSERT INTO achievement_progress (user_id, counter, value) VALUES ($1, $2, $3)
+         ON CONFLICT (user_id, counter) DO UPDATE SET value = {}, updated_at = NOW()
+         RETURNING value",
+        merge
+    ))
+    .bind(user_id)
+    .bind(counter.as_str())
+    .bind(value)
+    .fetch_one(&data.db)
+    .await
+}
+
+/// Adds a player's progress, unlocks every achievement whose goal it reached and notifies the player
+/// of each new one. Unlocking is idempotent, so an achievement is announced once.
+pub async fn record(data: &AppState, user_id: &str, progress: &[(Counter, i64)]) {
+    let mut unlocked = Vec::new();
+    for &(counter, value) in progress.iter().filter(|(_, value)| *value > 0) {
+        let total = match bump(data, user_id, counter, value).await {
+            Ok(total) => total,
+            Err(e) => {
+                eprintln!("Failed to record {} progress of {}: {}", counter.as_str(), user_id, e);
+                continue;
+            }
+        };
+        for achievement in ACHIEVEMENTS.iter().filter(|a| a.counter == counter && a.goal <= total) {
+            let inserted: Result<Option<chrono::NaiveDateTime>, sqlx::Error> = sqlx::query_scalar(
+                "INSERT INTO achievements (user_id, achievement_id) VALUES ($1, $2)
+                 ON CONFLICT (user_id, achievement_id) DO NOTHING
+                 RETURNING unlocked_at"
+            )
+            .bind(user_id)
+            .bind(achievement.id)
+            .fetch_optional(&data.db)
+            .await;
+            match inserted {
+                Ok(Some(unlocked_at)) => unlocked.push(Unlocked {
+                    id: achievement.id.to_string(),
+                    name: achievement.name.to_string(),
+                    description: achievement.description.to_string(),
+                    unlocked_at,
+                }),
+                Ok(None) => {}
+                Err(e) => eprintln!("Failed to unlock {} for {}: {}", achievement.id, user_id, e),
+            }
+        }
+    }
+
+    if unlocked.is_empty() {
+        return;
+    }
+    let Ok(mut con) = data.redis_client.get_async_connection().await else { return };
+    for achievement in unlocked {
+        let json = serde_json::to_string(&Notification::AchievementUnlocked { achievement }).unwrap_or_default();
+        let _: Result<(), redis::RedisError> = con.publish(notifications_channel(user_id), json).await;
+    }
+}
+
+/// Counts a top ten place on `game`'s leaderboard, if the player holds one.
+/// Only boards the server scores count; anyone can open a fresh client-submitted board and sit at the top of it.
+pub async fn record_rank(data: &AppState, game: &str, user_id: &str) {
+    if !leaderboard::server_scored(game) {
+        return;
+    }
+    if let Ok(Some((_, rank))) = leaderboard::best_and_rank(&data.db, game, user_id).await {
+        if rank <= TOP_RANKS {
+            record(data, user_id, &[(Counter::TopTen, 1)]).await;
+        }
+    }
+}
+
+/// One achievement with the player's progress towards it
+#[derive(Serialize)]
+pub struct AchievementStatus {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+    pub progress: i64, // Capped at the goal
+    pub goal: i64,
+    pub unlocked_at: Option<chrono::NaiveDateTime>,
+}
+
+/// Every achievement with the authenticated player's progress and unlock time
+#[actix_web::get("/achievements/me")]
+pub async fn get_my_achievements(
+    data: web::Data<AppState>,
+    auth: BearerAuth,
+) -> impl Responder {
+    let claims = match validate_token(auth.token(), &data.oidc_jwks_uri).await {
+        Ok(c) => c,
+        Err(r) => return r,
+    };
+
+    let counters: Vec<(String, i64)> = match sqlx::query_as("SELECT counter, value FROM achievement_progress WHERE user_id = $1")
+        .bind(&claims.sub)
+        .fetch_all(&data.db)
+        .await
+    {
+        Ok(rows) => rows,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("DB error: {}", e)),
+    };
+    let unlocks: Vec<(String, chrono::NaiveDateTime)> = match sqlx::query_as("SELECT achievement_id, unlocked_at FROM achievements WHERE user_id = $1")
+        .bind(&claims.sub)
+        .fetch_all(&data.db)
+        .await
+    {
+        Ok(rows) => rows,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("DB error: {}", e)),
+    };
+
+    HttpResponse::Ok().json(statuses(&counters.into_iter().collect(), &unlocks.into_iter().collect()))
+}
+
+/// The catalogue in order, with progress from `counters` and unlock times from `unlocks`
+fn statuses(counters: &HashMap<String, i64>, unlocks: &HashMap<String, chrono::NaiveDateTime>) -> Vec<AchievementStatus> {
+    ACHIEVEMENTS.iter()
+        .map(|a| {
+            let unlocked_at = unlocks.get(a.id).copied();
+            let progress = counters.get(a.counter.as_str()).copied().unwrap_or(0);
+            AchievementStatus {
+                id: a.id,
+                name: a.name,
+                description: a.description,
+                // A goal reached before the achievement existed still shows as reached
+                progress: if unlocked_at.is_some() { a.goal } else { progress.min(a.goal) },
+                goal: a.goal,
+                unlocked_at,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dragonballgame::{GameConfig, DEFAULT_TICK_RATE};
+    use crate::xandzero::BoardSpec;
+    use crate::xandzero_powerups::{PowerUp, PowerUpUse};
+
+    fn won_game(game_mode: GameMode, difficulty: Difficulty) -> XandZeroGame {
+        let mut game = XandZeroGame::new("u1".to_string(), "alice".to_string(), BoardSpec::default(), game_mode, difficulty);
+        game.winner = Some("X".to_string());
+        game
+    }
+
+    #[test]
+    fn achievement_ids_are_unique() {
+        for (i, a) in ACHIEVEMENTS.iter().enumerate() {
+            assert!(ACHIEVEMENTS[..i].iter().all(|b| b.id != a.id), "{}", a.id);
+        }
+    }
+
+    #[test]
+    fn xandzero_wins_count_their_conditions() {
+        let normal = won_game(GameMode::Normal, Difficulty::Medium);
+        assert_eq!(xandzero_progress(&normal, "X"), vec![(Counter::XandzeroWins, 1)]);
+        assert!(xandzero_progress(&normal, "O").is_empty());
+
+        let hard = won_game(GameMode::Normal, Difficulty::Hard);
+        assert!(xandzero_progress(&hard, "X").contains(&(Counter::HardAiWins, 1)));
+
+        let mut sudden_death = won_game(GameMode::SuddenDeath, Difficulty::Medium);
+        assert!(xandzero_progress(&sudden_death, "X").contains(&(Counter::SuddenDeathCleanWins, 1)));
+        sudden_death.power_up_uses.push(PowerUpUse { mark: "X".to_string(), power_up: PowerUp::Erase, placements: 2 });
+        assert!(!xandzero_progress(&sudden_death, "X").contains(&(Counter::SuddenDeathCleanWins, 1)));
+    }
+
+    #[test]
+    fn dragonball_runs_count_each_defenders_fireballs() {
+        let mut state = GameState::with_seed(3, DEFAULT_TICK_RATE, GameConfig::default());
+        state.add_player();
+        state.extinguished = vec![4, 9];
+        state.wave = 7;
+        state.game_over = true;
+        let progress = dragonball_progress(&state, 1, true);
+        assert!(progress.contains(&(Counter::FireballsExtinguished, 9)));
+        assert!(progress.contains(&(Counter::BossesDefeated, 1)));
+        assert!(progress.contains(&(Counter::HighestWave, 7)));
+        assert!(progress.contains(&(Counter::CoopRuns, 1)));
+    }
+
+    #[test]
+    fn statuses_cap_progress_and_show_unlocks() {
+        let counters = HashMap::from([("fireballs_extinguished".to_string(), 150)]);
+        let unlocks = HashMap::from([("firefighter".to_string(), chrono::NaiveDateTime::default())]);
+        let statuses = statuses(&counters, &unlocks);
+        let find = |id: &str| statuses.iter().find(|s| s.id == id).unwrap();
+        assert_eq!((find("firefighter").progress, find("firefighter").unlocked_at.is_some()), (100, true));
+        assert_eq!((find("fire_brigade").progress, find("fire_brigade").unlocked_at), (150, None));
+    }
+}
@@ -0,0 +1,116 @@
+//! Headless Dragonball simulator. Plays many runs with scripted bots against a difficulty
+//! preset and prints score distributions and simulation speed.
+//!
+//! cargo run --release --bin dragonball_sim -- --games 2000 --difficulty hard --bot lagging
+use app_template_backend::dragonball_bots::{self, BotKind, ALL_BOTS};
+use app_template_backend::dragonballgame::{GameConfig, BUILTIN_PRESETS, DEFAULT_DIFFICULTY, DEFAULT_TICK_RATE, TICK_RATES};
+use std::time::Instant;
+
+const USAGE: &str = "Usage: dragonball_sim [--games N] [--difficulty easy|normal|hard|insane] [--bot perfect|lagging|random|all]
+                     [--players 1-4] [--tick-rate 30|60|120] [--seed N] [--max-minutes N]";
+
+struct Options {
+    games: u64,
+    difficulty: String,
+    bots: Vec<BotKind>,
+    players: usize,
+    tick_rate: u32,
+    seed: u64,        // Run i is played with seed + i
+    max_minutes: u64, // Game time after which a run is cut off
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        games: 1000,
+        difficulty: DEFAULT_DIFFICULTY.to_string(),
+        bots: ALL_BOTS.to_vec(),
+        players: 1,
+        tick_rate: DEFAULT_TICK_RATE,
+        seed: 1,
+        max_minutes: 30,
+    };
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or_else(|| format!("{} needs a value", flag))?;
+        let number = || value.parse::<u64>().map_err(|_| format!("{} must be a number", flag));
+        match flag.as_str() {
+            "--games" => options.games = number()?,
+            "--difficulty" if BUILTIN_PRESETS.contains(&value.as_str()) => options.difficulty = value.clone(),
+            "--difficulty" => return Err(format!("Unknown difficulty {}", value)),
+            "--bot" if value == "all" => options.bots = ALL_BOTS.to_vec(),
+            "--bot" => options.bots = vec![BotKind::parse(value).ok_or_else(|| format!("Unknown bot {}", value))?],
+            "--players" => options.players = number()? as usize,
+            "--tick-rate" => options.tick_rate = number()? as u32,
+            "--seed" => options.seed = number()?,
+            "--max-minutes" => options.max_minutes = number()?,
+            _ => return Err(format!("Unknown option {} {}", flag, value)),
+        }
+    }
+    if !(1..=4).contains(&options.players) {
+        return Err("--players must be 1 to 4".to_string());
+    }
+    if !TICK_RATES.contains(&options.tick_rate) {
+        return Err("--tick-rate must be 30, 60 or 120".to_string());
+    }
+    Ok(options)
+}
+
+/// Value at quantile `q` of sorted values
+fn quantile(sorted: &[u32], q: f64) -> u32 {
+    sorted[((sorted.len() - 1) as f64 * q).round() as usize]
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|a| a == "--help" || a == "-h") {
+        println!("{}", USAGE);
+        return;
+    }
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let config = GameConfig::preset(&options.difficulty).expect("built-in preset");
+    let max_ticks = options.max_minutes * 60 * options.tick_rate as u64;
+
+    println!(
+        "{} games per bot on {} at {} Hz, {} defender(s), seeds {}..{}",
+        options.games, options.difficulty, options.tick_rate, options.players, options.seed, options.seed + options.games
+    );
+    println!("{:<8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>6} {:>8} {:>12}", "bot", "mean", "min", "p10", "p50", "p90", "max", "wave", "cut off", "ticks/s");
+    for bot in &options.bots {
+        let team = vec![*bot; options.players];
+        let mut scores = Vec::with_capacity(options.games as usize);
+        let (mut waves, mut ticks, mut cut_off) = (0u64, 0u64, 0u64);
+        let started = Instant::now();
+        for i in 0..options.games {
+            let run = dragonball_bots::play_run(options.seed + i, options.tick_rate, config.clone(), &team, max_ticks);
+            scores.push(run.score);
+            waves += run.wave as u64;
+            ticks += run.tick_count;
+            cut_off += u64::from(!run.game_over);
+        }
+        let secs = started.elapsed().as_secs_f64();
+        if scores.is_empty() {
+            continue;
+        }
+        scores.sort_unstable();
+        let mean = scores.iter().map(|&s| s as f64).sum::<f64>() / scores.len() as f64;
+        println!(
+            "{:<8} {:>8.0} {:>8} {:>8} {:>8} {:>8} {:>8} {:>6.1} {:>8} {:>12.0}",
+            bot.as_str(),
+            mean,
+            scores[0],
+            quantile(&scores, 0.1),
+            quantile(&scores, 0.5),
+            quantile(&scores, 0.9),
+            scores[scores.len() - 1],
+            waves as f64 / scores.len() as f64,
+            cut_off,
+            ticks as f64 / secs,
+        );
+    }
+}
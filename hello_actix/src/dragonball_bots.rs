@@ -0,0 +1,149 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::collections::VecDeque;
+use crate::dragonballgame::{FireballState, GameConfig, GameState, CANVAS_HEIGHT};
+
+/// How long the lagging tracker takes to react to what it sees
+const LAG_SECS: f64 = 0.25;
+/// How often the lagging tracker and the random bot send a move, like a throttled client
+const MOVES_PER_SEC: u32 = 10;
+/// Chance per move that the random bot activates a stored power-up
+const RANDOM_POWER_UP_CHANCE: f64 = 0.2;
+
+/// Scripted players for tuning difficulty presets without a browser
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BotKind {
+    Perfect, // Heads for the boss or the foremost fireball every tick and fires power-ups at once
+    Lagging, // The same, reacting to what it saw `LAG_SECS` ago and moving `MOVES_PER_SEC` times a second
+    Random,  // Wanders to random heights and uses power-ups now and then
+}
+
+pub const ALL_BOTS: [BotKind; 3] = [BotKind::Perfect, BotKind::Lagging, BotKind::Random];
+
+impl BotKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BotKind::Perfect => "perfect",
+            BotKind::Lagging => "lagging",
+            BotKind::Random => "random",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        ALL_BOTS.into_iter().find(|bot| bot.as_str() == name)
+    }
+}
+
+/// One bot defending one seat. It only uses what a client sees and sends what a client may send.
+pub struct Bot {
+    kind: BotKind,
+    seat: usize,
+    rng: ChaCha8Rng, // Seeded, so a bot run is as reproducible as the game
+    seen: VecDeque<Option<f64>>, // Lagging: targets of the last `LAG_SECS`, oldest first
+}
+
+impl Bot {
+    pub fn new(kind: BotKind, seat: usize, seed: u64) -> Self {
+        Self { kind, seat, rng: ChaCha8Rng::seed_from_u64(seed), seen: VecDeque::new() }
+    }
+
+    /// Sends this tick's moves and power-ups, before `state` is ticked
+    pub fn play(&mut self, state: &mut GameState) {
+        let every = (state.tick_rate / MOVES_PER_SEC).max(1) as u64;
+        let moves_now = state.tick_count.is_multiple_of(every);
+        match self.kind {
+            BotKind::Perfect => {
+                if let Some(y) = target(state) {
+                    state.update_player_pos(self.seat, y);
+                }
+                if let Some(&kind) = state.power_ups.first() {
+                    state.use_power_up(self.seat, kind);
+                }
+            }
+            BotKind::Lagging => {
+                let lag_ticks = (LAG_SECS * state.tick_rate as f64).round() as usize;
+                self.seen.push_back(target(state));
+                if self.seen.len() <= lag_ticks {
+                    return;
+                }
+                let seen = self.seen.pop_front().flatten();
+                if !moves_now {
+                    return;
+                }
+                if let Some(y) = seen {
+                    state.update_player_pos(self.seat, y);
+                }
+                if let Some(&kind) = state.power_ups.first() {
+                    state.use_power_up(self.seat, kind);
+                }
+            }
+            BotKind::Random => {
+                if !moves_now {
+                    return;
+                }
+                let y = self.rng.gen_range(0.0..=CANVAS_HEIGHT);
+                state.update_player_pos(self.seat, y);
+                if !state.power_ups.is_empty() && self.rng.gen_bool(RANDOM_POWER_UP_CHANCE) {
+                    let kind = state.power_ups[self.rng.gen_range(0..state.power_ups.len())];
+                    state.use_power_up(self.seat, kind);
+                }
+            }
+        }
+    }
+}
+
+/// Where a tracker wants to be: level with the boss, else with the active fireball closest to the line
+fn target(state: &GameState) -> Option<f64> {
+    state.boss.as_ref().map(|b| b.y).or_else(|| state.fireballs.iter()
+        .filter(|f| f.state == FireballState::Active)
+        .max_by(|a, b| a.x.total_cmp(&b.x))
+        .map(|f| f.y))
+}
+
+/// Plays a whole run with one bot per defender, stopping at game over or after `max_ticks`.
+/// The same arguments always give the same run.
+pub fn play_run(seed: u64, tick_rate: u32, config: GameConfig, bots: &[BotKind], max_ticks: u64) -> GameState {
+    let mut state = GameState::with_seed(seed, tick_rate, config);
+    for _ in 1..bots.len() {
+        state.add_player();
+    }
+    let mut bots: Vec<Bot> = bots.iter().enumerate()
+        .map(|(seat, &kind)| Bot::new(kind, seat, seed.wrapping_add(seat as u64)))
+        .collect();
+    while !state.game_over && state.tick_count < max_ticks {
+        for bot in &mut bots {
+            bot.play(&mut state);
+        }
+        state.tick();
+    }
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dragonballgame::DEFAULT_TICK_RATE;
+
+    const MAX_TICKS: u64 = 100_000;
+
+    #[test]
+    fn bot_runs_are_reproducible_and_replay() {
+        for kind in ALL_BOTS {
+            let run = play_run(7, DEFAULT_TICK_RATE, GameConfig::default(), &[kind], MAX_TICKS);
+            let again = play_run(7, DEFAULT_TICK_RATE, GameConfig::default(), &[kind], MAX_TICKS);
+            assert_eq!((run.score, run.game_over_tick), (again.score, again.game_over_tick), "{:?}", kind);
+            let replayed = GameState::replay(run.seed, DEFAULT_TICK_RATE, GameConfig::default(), 1, &run.inputs, MAX_TICKS);
+            assert_eq!(replayed.score, run.score, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn better_bots_score_more() {
+        let total = |kind| -> u32 {
+            (1..=5).map(|seed| play_run(seed, DEFAULT_TICK_RATE, GameConfig::default(), &[kind], MAX_TICKS).score).sum()
+        };
+        let (perfect, lagging, random) = (total(BotKind::Perfect), total(BotKind::Lagging), total(BotKind::Random));
+        assert!(perfect > lagging, "perfect {} vs lagging {}", perfect, lagging);
+        assert!(lagging > random, "lagging {} vs random {}", lagging, random);
+    }
+}
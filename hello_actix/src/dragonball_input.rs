@@ -0,0 +1,149 @@
+use std::time::{Duration, Instant};
+
+/// Largest client frame accepted. Real messages are a few dozen bytes.
+const MAX_FRAME_BYTES: usize = 256;
+/// Sustained client messages per second; mouse moves beyond this are dropped
+const MESSAGES_PER_SEC: f64 = 120.0;
+const MESSAGE_BURST: f64 = 30.0;
+/// Dropped messages within one second that mark a connection as flooding
+const FLOOD_DROPS_PER_SEC: u32 = 240;
+const MAX_MALFORMED_FRAMES: u32 = 20;
+const MAX_OVERSIZED_FRAMES: u32 = 3;
+
+/// Why a connection was cut off. Stored with the run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AbuseReason {
+    MessageFlood,
+    MalformedFrames,
+    OversizedFrames,
+}
+
+impl AbuseReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AbuseReason::MessageFlood => "message_flood",
+            AbuseReason::MalformedFrames => "malformed_frames",
+            AbuseReason::OversizedFrames => "oversized_frames",
+        }
+    }
+}
+
+/// What to do with an inbound frame
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Accept,
+    Drop,
+    Disconnect(AbuseReason),
+}
+
+/// Frames of one connection that never reached the game
+#[derive(Default, Clone, Copy, Debug)]
+pub struct DroppedFrames {
+    pub rate_limited: u32,
+    pub malformed: u32,
+    pub oversized: u32,
+}
+
+impl DroppedFrames {
+    pub fn total(&self) -> u32 {
+        self.rate_limited + self.malformed + self.oversized
+    }
+}
+
+/// Per-connection token bucket and drop counters for client frames
+pub struct InputGuard {
+    tokens: f64,
+    last_refill: Instant,
+    window_start: Instant,
+    window_drops: u32, // Rate-limited drops since `window_start`
+    pub dropped: DroppedFrames,
+}
+
+impl InputGuard {
+    pub fn new(now: Instant) -> Self {
+        Self { tokens: MESSAGE_BURST, last_refill: now, window_start: now, window_drops: 0, dropped: DroppedFrames::default() }
+    }
+
+    /// Checks the size and rate of a frame of `len` bytes arriving at `now`
+    pub fn admit(&mut self, len: usize, now: Instant) -> Verdict {
+        if len > MAX_FRAME_BYTES {
+            self.dropped.oversized += 1;
+            if self.dropped.oversized > MAX_OVERSIZED_FRAMES {
+                return Verdict::Disconnect(AbuseReason::OversizedFrames);
+            }
+            return Verdict::Drop;
+        }
+
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+        self.tokens = (self.tokens + elapsed * MESSAGES_PER_SEC).min(MESSAGE_BURST);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Verdict::Accept;
+        }
+
+        self.dropped.rate_limited += 1;
+        if now.saturating_duration_since(self.window_start) >= Duration::from_secs(1) {
+            self.window_start = now;
+            self.window_drops = 0;
+        }
+        self.window_drops += 1;
+        if self.window_drops > FLOOD_DROPS_PER_SEC {
+            return Verdict::Disconnect(AbuseReason::MessageFlood);
+        }
+        Verdict::Drop
+    }
+
+    /// Counts an admitted frame that turned out not to be a valid message
+    pub fn malformed(&mut self) -> Verdict {
+        self.dropped.malformed += 1;
+        if self.dropped.malformed > MAX_MALFORMED_FRAMES {
+            return Verdict::Disconnect(AbuseReason::MalformedFrames);
+        }
+        Verdict::Drop
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steady_mouse_moves_pass() {
+        let start = Instant::now();
+        let mut guard = InputGuard::new(start);
+        for i in 0..600 {
+            let now = start + Duration::from_secs_f64(i as f64 / MESSAGES_PER_SEC);
+            assert_eq!(guard.admit(20, now), Verdict::Accept);
+        }
+        assert_eq!(guard.dropped.total(), 0);
+    }
+
+    #[test]
+    fn bursts_beyond_the_rate_are_dropped_and_floods_disconnect() {
+        let start = Instant::now();
+        let mut guard = InputGuard::new(start);
+        let verdicts: Vec<Verdict> = (0..MESSAGE_BURST as usize + 10).map(|_| guard.admit(20, start)).collect();
+        assert!(verdicts[..MESSAGE_BURST as usize].iter().all(|v| *v == Verdict::Accept));
+        assert!(verdicts[MESSAGE_BURST as usize..].iter().all(|v| *v == Verdict::Drop));
+        assert_eq!(guard.dropped.rate_limited, 10);
+
+        let flood = (0..FLOOD_DROPS_PER_SEC).map(|_| guard.admit(20, start)).last();
+        assert_eq!(flood, Some(Verdict::Disconnect(AbuseReason::MessageFlood)));
+    }
+
+    #[test]
+    fn oversized_and_malformed_frames_count_towards_a_disconnect() {
+        let now = Instant::now();
+        let mut guard = InputGuard::new(now);
+        for _ in 0..MAX_OVERSIZED_FRAMES {
+            assert_eq!(guard.admit(MAX_FRAME_BYTES + 1, now), Verdict::Drop);
+        }
+        assert_eq!(guard.admit(MAX_FRAME_BYTES + 1, now), Verdict::Disconnect(AbuseReason::OversizedFrames));
+
+        for _ in 0..MAX_MALFORMED_FRAMES {
+            assert_eq!(guard.malformed(), Verdict::Drop);
+        }
+        assert_eq!(guard.malformed(), Verdict::Disconnect(AbuseReason::MalformedFrames));
+    }
+}
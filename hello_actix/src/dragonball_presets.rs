@@ -0,0 +1,175 @@
+use actix_web::{web, HttpResponse, Responder};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use serde::Serialize;
+use sqlx::types::Json;
+use sqlx::{Pool, Postgres};
+use crate::{AppState, validate_admin_token};
+use crate::dragonballgame::{GameConfig, BUILTIN_PRESETS, DEFAULT_DIFFICULTY};
+
+const MAX_PRESET_NAME_LEN: usize = 32;
+
+#[derive(Serialize)]
+pub struct Preset {
+    pub name: String,
+    pub builtin: bool,
+    pub config: GameConfig,
+}
+
+/// Leaderboard `game_name` of a difficulty. Normal keeps the original `dragonball` board.
+pub fn leaderboard_game(difficulty: &str) -> String {
+    if difficulty == DEFAULT_DIFFICULTY {
+        "dragonball".to_string()
+    } else {
+        format!("dragonball_{}", difficulty)
+    }
+}
+
+/// Leaderboard `game_name` of co-op runs on a difficulty, where each defender's contribution counts
+pub fn coop_leaderboard_game(difficulty: &str) -> String {
+    if difficulty == DEFAULT_DIFFICULTY {
+        "dragonball_coop".to_string()
+    } else {
+        format!("dragonball_coop_{}", difficulty)
+    }
+}
+
+/// Custom preset names are lowercase letters, digits and dashes so they fit in leaderboard names
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_PRESET_NAME_LEN
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// Names whose leaderboard would be mistaken for a co-op board, e.g. `coop` shares `dragonball_coop`
+fn reserved_name(name: &str) -> bool {
+    name == "coop" || name.starts_with("coop-")
+}
+
+/// Config of a built-in or custom preset, None if there is no preset of that name
+pub async fn resolve(db: &Pool<Postgres>, name: &str) -> Result<Option<GameConfig>, sqlx::Error> {
+    if let Some(config) = GameConfig::preset(name) {
+        return Ok(Some(config));
+    }
+    if !valid_name(name) {
+        return Ok(None);
+    }
+    let config: Option<Json<GameConfig>> = sqlx::query_scalar("SELECT config FROM dragonball_presets WHERE name = $1")
+        .bind(name)
+        .fetch_optional(db)
+        .await?;
+    Ok(config.map(|c| c.0))
+}
+
+/// Every preset that can be passed as `difficulty`, built-in ones first
+#[actix_web::get("/dragonball/presets")]
+pub async fn list_presets(data: web::Data<AppState>) -> impl Responder {
+    let custom: Vec<(String, Json<GameConfig>)> = match sqlx::query_as("SELECT name, config FROM dragonball_presets ORDER BY name")
+        .fetch_all(&data.db)
+        .await
+    {
+        Ok(rows) => rows,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("DB error: {}", e)),
+    };
+
+    let mut presets: Vec<Preset> = BUILTIN_PRESETS.iter()
+        .filter_map(|name| GameConfig::preset(name).map(|config| Preset { name: name.to_string(), builtin: true, config }))
+        .collect();
+    presets.extend(custom.into_iter().map(|(name, config)| Preset { name, builtin: false, config: config.0 }));
+    HttpResponse::Ok().json(presets)
+}
+
+/// Admin endpoint creating or replacing a custom preset.
+/// Its leaderboard compares runs played with one config, so once runs are recorded the config is frozen;
+/// saving the same config again is allowed, a different one needs a new preset name.
+#[actix_web::put("/admin/dragonball/presets/{name}")]
+pub async fn put_preset(
+    data: web::Data<AppState>,
+    auth: BearerAuth,
+    path: web::Path<String>,
+    config: web::Json<GameConfig>,
+) -> impl Responder {
+    let claims = match validate_admin_token(auth.token(), &data.oidc_jwks_uri).await {
+        Ok(c) => c,
+        Err(r) => return r,
+    };
+    let name = path.into_inner();
+    if GameConfig::preset(&name).is_some() {
+        return HttpResponse::Conflict().body("Built-in presets cannot be changed");
+    }
+    if !valid_name(&name) {
+        return HttpResponse::BadRequest().body("Preset names are 1-32 lowercase letters, digits or dashes");
+    }
+    if reserved_name(&name) {
+        return HttpResponse::BadRequest().body("Preset names may not be coop or start with coop-");
+    }
+    if let Err(e) = config.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+
+    let played_otherwise: Result<bool, sqlx::Error> = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM dragonball_runs WHERE difficulty = $1 AND config IS DISTINCT FROM $2)"
+    )
+    .bind(&name)
+    .bind(Json(&*config))
+    .fetch_one(&data.db)
+    .await;
+    match played_otherwise {
+        Ok(true) => return HttpResponse::Conflict().body("Runs were played with this preset; save the new config under another name"),
+        Ok(false) => {}
+        Err(e) => return HttpResponse::InternalServerError().body(format!("DB error: {}", e)),
+    }
+
+    let result = sqlx::query(
+        "INSERT INTO dragonball_presets (name, config, updated_by) VALUES ($1, $2, $3)
+         ON CONFLICT (name) DO UPDATE SET config = EXCLUDED.config, updated_by = EXCLUDED.updated_by, updated_at = NOW()"
+    )
+    .bind(&name)
+    .bind(Json(&*config))
+    .bind(&claims.sub)
+    .execute(&data.db)
+    .await;
+    if let Err(e) = result {
+        return HttpResponse::InternalServerError().body(format!("DB error: {}", e));
+    }
+
+    HttpResponse::Ok().json(Preset { name, builtin: false, config: config.into_inner() })
+}
+
+/// Admin endpoint removing a custom preset. Its leaderboard and recorded runs stay, so the name can only
+/// come back with the config those runs were played with.
+#[actix_web::delete("/admin/dragonball/presets/{name}")]
+pub async fn delete_preset(
+    data: web::Data<AppState>,
+    auth: BearerAuth,
+    path: web::Path<String>,
+) -> impl Responder {
+    if let Err(r) = validate_admin_token(auth.token(), &data.oidc_jwks_uri).await {
+        return r;
+    }
+    if GameConfig::preset(&path).is_some() {
+        return HttpResponse::Conflict().body("Built-in presets cannot be removed");
+    }
+
+    match sqlx::query("DELETE FROM dragonball_presets WHERE name = $1").bind(path.as_str()).execute(&data.db).await {
+        Ok(done) if done.rows_affected() == 0 => HttpResponse::NotFound().body("Preset not found"),
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => HttpResponse::InternalServerError().body(format!("DB error: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_presets_cannot_share_a_coop_board() {
+        assert_eq!(leaderboard_game("coop"), coop_leaderboard_game(DEFAULT_DIFFICULTY));
+        assert!(reserved_name("coop"));
+        assert!(reserved_name("coop-hard"));
+        assert!(!reserved_name("cooper"));
+        assert!(!reserved_name("hard-coop"));
+        for difficulty in BUILTIN_PRESETS {
+            assert_ne!(leaderboard_game(difficulty), coop_leaderboard_game(DEFAULT_DIFFICULTY));
+        }
+    }
+}
@@ -0,0 +1,400 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use crate::achievements::Unlocked;
+use crate::dragonballgame::{ActiveEffect, Boss, Fireball, FireballKind, FireballState, GameConfig, GameState, Pickup, Player, PowerUpKind};
+
+/// v1 sends the full `GameState` as JSON every frame; v2 sends a snapshot followed by deltas
+pub const PROTOCOL_VERSIONS: [u32; 2] = [1, 2];
+pub const DEFAULT_PROTOCOL_VERSION: u32 = 1;
+
+/// Wire encoding of v2 server messages. Client messages are always JSON text.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    #[default]
+    Json,
+    Msgpack, // Binary frames, maps keyed by field name
+}
+
+/// Messages the client may send besides `{ "y": ... }` position updates
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientControl {
+    /// The client saw a gap in `seq` and wants a fresh snapshot
+    Resync,
+    /// Activate a stored power-up
+    UsePowerUp { power_up: PowerUpKind },
+    /// Start a cooperative room's game; only its creator may, once a second defender joined
+    Start,
+    /// Hold the game until some member sends `resume`
+    Pause,
+    Resume,
+    /// Start a new game on the same connection once the `game_over` result is in
+    PlayAgain,
+}
+
+/// Position and fade of a fireball that moved since the previous message.
+/// Velocities are left out; clients draw positions only.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FireballChange {
+    pub id: u64,
+    pub x: f64,
+    pub y: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<FireballKind>, // A splitting fireball that split
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<FireballState>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extinguish_timer: Option<f64>,
+}
+
+/// v2 server messages. `seq` grows by one per message, so a client that sees a jump has missed one.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// First message on every connection: what to pass as `resume`/`resume_token` to reconnect
+    /// within `resume_grace_secs` of a drop
+    Session { session_id: String, resume_token: String, resume_grace_secs: u64 },
+    /// Who is in a cooperative room that has not started yet, sent on every change.
+    /// `seat` is the receiver's defender once the game starts.
+    Lobby { room_id: String, players: Vec<String>, seat: usize, can_start: bool },
+    /// First message of a game on a v2 connection, confirming what was negotiated
+    Hello { protocol: u32, encoding: Encoding, tick_rate: u32, difficulty: String, config: GameConfig, seat: usize },
+    Snapshot { seq: u64, tick: u64, state: Box<GameState> },
+    /// The game stands still because a member asked for it or the listed seats are reconnecting
+    Paused { requested: bool, away: Vec<usize> },
+    Resumed,
+    /// The receiver's result, sent once after the final state. `counted` runs were verified and
+    /// entered on `leaderboard`; `personal_best` and `rank` are the receiver's standing there.
+    /// The connection is closed unless someone sends `play_again` within `play_again_secs`.
+    GameOver {
+        score: u32,
+        team_score: u32,
+        leaderboard: String,
+        counted: bool,
+        personal_best: Option<u32>,
+        new_best: bool,
+        rank: Option<i64>,
+        play_again_secs: u64,
+    },
+    /// The receiver unlocked an achievement, in this game or any other
+    AchievementUnlocked { achievement: Unlocked },
+    Delta {
+        seq: u64,
+        tick: u64,
+        #[serde(skip_serializing_if = "Vec::is_empty", default)]
+        spawned: Vec<Fireball>,
+        #[serde(skip_serializing_if = "Vec::is_empty", default)]
+        removed: Vec<u64>,
+        #[serde(skip_serializing_if = "Vec::is_empty", default)]
+        changed: Vec<FireballChange>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        score: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        game_over: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        players: Option<Vec<Player>>, // All defenders, whenever one moved or scored
+        #[serde(skip_serializing_if = "Option::is_none")]
+        lives: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        invulnerable_for: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        wave: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        boss: Option<Boss>, // Sent whole whenever it changes
+        #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+        boss_defeated: bool, // The boss is gone
+        // Pickups, stored power-ups and effects are short lists, sent whole whenever they change
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pickups: Option<Vec<Pickup>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        power_ups: Option<Vec<PowerUpKind>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        effects: Option<Vec<ActiveEffect>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        combo: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        multiplier: Option<u32>,
+    },
+}
+
+/// A frame ready for the websocket
+pub enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+pub fn encode(msg: &ServerMessage, encoding: Encoding) -> Frame {
+    match encoding {
+        Encoding::Json => Frame::Text(serde_json::to_string(msg).unwrap_or_default()),
+        Encoding::Msgpack => Frame::Binary(rmp_serde::to_vec_named(msg).unwrap_or_default()),
+    }
+}
+
+pub async fn send(session: &mut actix_ws::Session, frame: Frame) -> Result<(), actix_ws::Closed> {
+    match frame {
+        Frame::Text(text) => session.text(text).await,
+        Frame::Binary(bytes) => session.binary(bytes).await,
+    }
+}
+
+/// Turns successive states of one run into a snapshot followed by deltas against what the client last received
+#[derive(Default)]
+pub struct DeltaEncoder {
+    seq: u64,
+    sent: Option<GameState>, // The state as the client knows it
+}
+
+impl DeltaEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The next message carries a full snapshot
+    pub fn request_resync(&mut self) {
+        self.sent = None;
+    }
+
+    /// Message bringing the client up to `state`, or None if nothing changed since the last one
+    pub fn next(&mut self, state: &GameState) -> Option<ServerMessage> {
+        let msg = match &self.sent {
+            None => ServerMessage::Snapshot { seq: self.seq, tick: state.tick_count, state: Box::new(state.clone()) },
+            Some(prev) => {
+                let before: HashMap<u64, &Fireball> = prev.fireballs.iter().map(|f| (f.id, f)).collect();
+                let mut spawned = Vec::new();
+                let mut changed = Vec::new();
+                for f in &state.fireballs {
+                    match before.get(&f.id) {
+                        None => spawned.push(f.clone()),
+                        Some(old) if old.x != f.x || old.y != f.y || old.kind != f.kind || old.state != f.state || old.extinguish_timer != f.extinguish_timer => {
+                            changed.push(FireballChange {
+                                id: f.id,
+                                x: f.x,
+                                y: f.y,
+                                kind: (old.kind != f.kind).then(|| f.kind.clone()),
+                                state: (old.state != f.state).then(|| f.state.clone()),
+                                extinguish_timer: (old.extinguish_timer != f.extinguish_timer).then_some(f.extinguish_timer),
+                            });
+                        }
+                        Some(_) => {}
+                    }
+                }
+                let removed: Vec<u64> = prev.fireballs.iter()
+                    .map(|f| f.id)
+                    .filter(|id| !state.fireballs.iter().any(|f| f.id == *id))
+                    .collect();
+                let score = (prev.score != state.score).then_some(state.score);
+                let game_over = (prev.game_over != state.game_over).then_some(state.game_over);
+                let players = (prev.players != state.players).then(|| state.players.clone());
+                let lives = (prev.lives != state.lives).then_some(state.lives);
+                let invulnerable_for = (prev.invulnerable_for != state.invulnerable_for).then_some(state.invulnerable_for);
+                let wave = (prev.wave != state.wave).then_some(state.wave);
+                let boss = state.boss.clone().filter(|boss| prev.boss.as_ref() != Some(boss));
+                let boss_defeated = prev.boss.is_some() && state.boss.is_none();
+                let pickups = (prev.pickups != state.pickups).then(|| state.pickups.clone());
+                let power_ups = (prev.power_ups != state.power_ups).then(|| state.power_ups.clone());
+                let effects = (prev.effects != state.effects).then(|| state.effects.clone());
+                let combo = (prev.combo != state.combo).then_some(state.combo);
+                let multiplier = (prev.multiplier != state.multiplier).then_some(state.multiplier);
+
+                if spawned.is_empty() && removed.is_empty() && changed.is_empty()
+                    && score.is_none() && game_over.is_none() && players.is_none()
+                    && lives.is_none() && invulnerable_for.is_none() && wave.is_none() && boss.is_none() && !boss_defeated
+                    && pickups.is_none() && power_ups.is_none() && effects.is_none() && combo.is_none() && multiplier.is_none()
+                {
+                    return None;
+                }
+                ServerMessage::Delta {
+                    seq: self.seq,
+                    tick: state.tick_count,
+                    spawned,
+                    removed,
+                    changed,
+                    score,
+                    game_over,
+                    players,
+                    lives,
+                    invulnerable_for,
+                    wave,
+                    boss,
+                    boss_defeated,
+                    pickups,
+                    power_ups,
+                    effects,
+                    combo,
+                    multiplier,
+                }
+            }
+        };
+        self.seq += 1;
+        self.sent = Some(state.clone());
+        Some(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dragonballgame::DEFAULT_TICK_RATE;
+
+    /// Applies a delta the way a client would
+    fn apply(state: &mut GameState, msg: &ServerMessage) {
+        match msg {
+            ServerMessage::Snapshot { state: snapshot, .. } => *state = (**snapshot).clone(),
+            ServerMessage::Delta {
+                spawned, removed, changed, score, game_over, players, lives, invulnerable_for, wave, boss, boss_defeated,
+                pickups, power_ups, effects, combo, multiplier, ..
+            } => {
+                state.fireballs.retain(|f| !removed.contains(&f.id));
+                state.fireballs.extend(spawned.iter().cloned());
+                for c in changed {
+                    let f = state.fireballs.iter_mut().find(|f| f.id == c.id).expect("changed fireball is known");
+                    f.x = c.x;
+                    f.y = c.y;
+                    if let Some(k) = &c.kind {
+                        f.kind = k.clone();
+                    }
+                    if let Some(s) = &c.state {
+                        f.state = s.clone();
+                    }
+                    if let Some(t) = c.extinguish_timer {
+                        f.extinguish_timer = t;
+                    }
+                }
+                if let Some(s) = score {
+                    state.score = *s;
+                }
+                if let Some(g) = game_over {
+                    state.game_over = *g;
+                }
+                if let Some(p) = players {
+                    state.players = p.clone();
+                }
+                if let Some(l) = lives {
+                    state.lives = *l;
+                }
+                if let Some(t) = invulnerable_for {
+                    state.invulnerable_for = *t;
+                }
+                if let Some(w) = wave {
+                    state.wave = *w;
+                }
+                if *boss_defeated {
+                    state.boss = None;
+                }
+                if let Some(b) = boss {
+                    state.boss = Some(b.clone());
+                }
+                if let Some(p) = pickups {
+                    state.pickups = p.clone();
+                }
+                if let Some(p) = power_ups {
+                    state.power_ups = p.clone();
+                }
+                if let Some(e) = effects {
+                    state.effects = e.clone();
+                }
+                if let Some(c) = combo {
+                    state.combo = *c;
+                }
+                if let Some(m) = multiplier {
+                    state.multiplier = *m;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// What a client draws
+    fn view(state: &GameState) -> String {
+        let mut fireballs: Vec<_> = state.fireballs.iter()
+            .map(|f| (f.id, f.x, f.y, f.kind.clone(), f.state.clone(), f.extinguish_timer))
+            .collect();
+        fireballs.sort_by_key(|f| f.0);
+        let hud = (state.lives, state.invulnerable_for, state.wave, &state.boss, &state.pickups, &state.power_ups, &state.effects, state.combo, state.multiplier);
+        serde_json::to_string(&(state.score, state.game_over, &state.players, fireballs, hud)).unwrap()
+    }
+
+    #[test]
+    fn deltas_rebuild_the_server_state() {
+        // Long enough on easy to see every fireball kind, power-ups, lost lives and a boss fight
+        let mut server = GameState::with_seed(10, DEFAULT_TICK_RATE, GameConfig::preset("easy").unwrap());
+        let mut client = GameState::with_seed(0, DEFAULT_TICK_RATE, GameConfig::default());
+        let mut encoder = DeltaEncoder::new();
+        let mut expected_seq = 0;
+        let mut bosses_defeated = 0;
+
+        while !server.game_over {
+            if server.tick_count.is_multiple_of(5) {
+                let target = server.boss.as_ref().map(|b| b.y).or_else(|| server.fireballs.iter()
+                    .filter(|f| f.state == FireballState::Active)
+                    .max_by(|a, b| a.x.total_cmp(&b.x))
+                    .map(|f| f.y));
+                if let Some(y) = target {
+                    server.update_player_pos(0, y);
+                }
+                if let Some(&kind) = server.power_ups.first() {
+                    server.use_power_up(0, kind);
+                }
+            }
+            server.tick();
+            if let Some(msg) = encoder.next(&server) {
+                let seq = match &msg {
+                    ServerMessage::Snapshot { seq, .. } | ServerMessage::Delta { seq, .. } => *seq,
+                    _ => unreachable!(),
+                };
+                assert_eq!(seq, expected_seq);
+                expected_seq += 1;
+                if let ServerMessage::Delta { boss_defeated: true, .. } = msg {
+                    bosses_defeated += 1;
+                }
+                apply(&mut client, &msg);
+                assert_eq!(view(&client), view(&server));
+            }
+        }
+        assert!(bosses_defeated > 0);
+    }
+
+    #[test]
+    fn resync_sends_a_snapshot() {
+        let mut state = GameState::with_seed(7, DEFAULT_TICK_RATE, GameConfig::default());
+        let mut encoder = DeltaEncoder::new();
+        assert!(matches!(encoder.next(&state), Some(ServerMessage::Snapshot { seq: 0, .. })));
+        assert!(encoder.next(&state).is_none());
+        state.players[0].y = 10.0;
+        assert!(matches!(encoder.next(&state), Some(ServerMessage::Delta { seq: 1, .. })));
+        encoder.request_resync();
+        assert!(matches!(encoder.next(&state), Some(ServerMessage::Snapshot { seq: 2, .. })));
+    }
+
+    #[test]
+    fn game_over_and_play_again_are_typed_messages() {
+        assert!(matches!(serde_json::from_str(r#"{ "type": "play_again" }"#), Ok(ClientControl::PlayAgain)));
+        let result = ServerMessage::GameOver {
+            score: 40,
+            team_score: 40,
+            leaderboard: "dragonball".to_string(),
+            counted: true,
+            personal_best: Some(40),
+            new_best: true,
+            rank: Some(3),
+            play_again_secs: 30,
+        };
+        let json: serde_json::Value = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["type"], "game_over");
+        assert_eq!(json["rank"], 3);
+    }
+
+    #[test]
+    fn msgpack_round_trips() {
+        let mut state = GameState::with_seed(7, DEFAULT_TICK_RATE, GameConfig::default());
+        for _ in 0..300 {
+            state.tick();
+        }
+        let msg = DeltaEncoder::new().next(&state).unwrap();
+        let Frame::Binary(bytes) = encode(&msg, Encoding::Msgpack) else { panic!("expected a binary frame") };
+        let decoded: ServerMessage = rmp_serde::from_slice(&bytes).unwrap();
+        let ServerMessage::Snapshot { state: decoded, .. } = decoded else { panic!("expected a snapshot") };
+        assert_eq!(view(&decoded), view(&state));
+    }
+}
@@ -0,0 +1,1107 @@
+use actix_web::{web, HttpResponse};
+use actix_ws::{CloseCode, CloseReason, Message, Session};
+use futures_util::StreamExt;
+use redis::AsyncCommands;
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use crate::AppState;
+use crate::achievements::{self, Notification};
+use crate::dragonball_input::{AbuseReason, InputGuard, Verdict};
+use crate::dragonball_presets::{coop_leaderboard_game, leaderboard_game};
+use crate::dragonball_protocol::{self, ClientControl, DeltaEncoder, Encoding, Frame, ServerMessage};
+use crate::dragonball_runs::{self, Defender, RunRecord};
+use crate::dragonballgame::{GameConfig, GameState, Player};
+use crate::{leaderboard, spectate};
+
+pub const MAX_ROOM_PLAYERS: usize = 4;
+pub const MIN_COOP_PLAYERS: usize = 2;
+/// How long a dropped defender's seat is held for a reconnect. The game stays paused meanwhile.
+pub const RESUME_GRACE_SECS: u64 = 60;
+/// How long the results wait for a `play_again` before the connections are closed
+pub const PLAY_AGAIN_SECS: u64 = 30;
+/// Longest a game may stand still over all its pauses. After that requested pauses end and held seats are given up.
+pub const MAX_PAUSE_SECS: u64 = 180;
+const PING_INTERVAL: Duration = Duration::from_secs(10);
+/// A connection that sent nothing, not even a pong, for this long is closed as dead
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Dragonball rooms of this instance. A room's simulation runs in the instance that
+/// created it, so every member has to be connected (and reconnect) to that instance.
+#[derive(Default)]
+pub struct Registry {
+    rooms: HashMap<String, Arc<Mutex<Room>>>,    // Co-op rooms by room id, for joining
+    sessions: HashMap<String, Arc<Mutex<Room>>>, // Every member's room by session id, for resuming
+}
+
+pub type Rooms = Arc<Mutex<Registry>>;
+
+/// How a connection talks to its client, from the `/dragon_ws` query
+#[derive(Clone, Copy)]
+pub struct Wire {
+    pub protocol: u32,
+    pub encoding: Encoding,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Presence {
+    Connected,
+    Away(Instant), // Dropped at that time; the seat is held for a reconnect
+    Gone,          // Left for good
+}
+
+/// Who holds a seat and whether they are connected, apart from the connection itself
+struct Claim {
+    session_id: String,
+    resume_token: String, // Secret the client presents to reconnect to `session_id`
+    user_id: String,
+    presence: Presence,
+}
+
+/// One player's connection in a room. Its index in `Room::members` is its seat once the game starts.
+struct Member {
+    conn_id: String,      // The current connection; replaced on every reconnect
+    stop: Arc<Notify>,    // Ends the current connection's input task
+    defender: Defender,
+    session: Session,
+    wire: Wire,
+    encoder: DeltaEncoder,
+    resync: bool, // The client asked for a fresh snapshot
+    dropped_frames: u32,
+    abuse: Option<AbuseReason>, // Set when the member was disconnected for abuse
+}
+
+/// Why the game is paused, as announced to the members
+#[derive(Clone, Debug, PartialEq)]
+struct Pause {
+    requested: bool, // A member sent `pause`
+    away: Vec<usize>,
+}
+
+/// The presence and pause rules of a room. Holds no connections, so the rules can be tested on their own.
+#[derive(Default)]
+struct Seating {
+    claims: Vec<Claim>, // By seat, alongside `Room::members`
+    pause_requested: bool,
+    announced_pause: Option<Pause>,
+    paused_since: Option<Instant>, // Start of the pause in effect
+    paused_for: Duration,          // Earlier pauses of this game
+    over_since: Option<Instant>, // The results went out then; the room waits for a play again
+}
+
+impl Seating {
+    fn add(&mut self, user_id: &str) {
+        self.claims.push(Claim {
+            session_id: uuid::Uuid::new_v4().to_string(),
+            resume_token: uuid::Uuid::new_v4().to_string(),
+            user_id: user_id.to_string(),
+            presence: Presence::Connected,
+        });
+    }
+
+    fn connected(&self, seat: usize) -> bool {
+        self.claims[seat].presence == Presence::Connected
+    }
+
+    /// Whether the game stands still. Moves and power-ups are ignored meanwhile.
+    fn paused(&self) -> bool {
+        self.announced_pause.is_some()
+    }
+
+    /// Marks a session's seat connected again. Returns the seat and whether its previous connection was still open.
+    fn resume(&mut self, session_id: &str, resume_token: &str, user_id: &str) -> Result<(usize, bool), ResumeError> {
+        let seat = self.claims.iter().position(|c| c.session_id == session_id).ok_or(ResumeError::NotFound)?;
+        let claim = &mut self.claims[seat];
+        if claim.resume_token != resume_token || claim.user_id != user_id {
+            return Err(ResumeError::Forbidden);
+        }
+        let was_connected = match claim.presence {
+            Presence::Gone => return Err(ResumeError::Expired),
+            Presence::Connected => true,
+            Presence::Away(_) => false,
+        };
+        claim.presence = Presence::Connected;
+        Ok((seat, was_connected))
+    }
+
+    /// The member in `seat` lost its connection. With `release` the seat is given up, else it is held for a reconnect.
+    fn disconnect(&mut self, seat: usize, release: bool, now: Instant) {
+        let claim = &mut self.claims[seat];
+        if claim.presence == Presence::Connected {
+            claim.presence = if release { Presence::Gone } else { Presence::Away(now) };
+        }
+    }
+
+    /// Gives up seats held past the grace period, and every seat once nobody played again in time.
+    /// Returns true when every member left for good.
+    fn expire(&mut self, now: Instant) -> bool {
+        let play_again_expired = self.over_since
+            .is_some_and(|since| now.duration_since(since) >= Duration::from_secs(PLAY_AGAIN_SECS));
+        for claim in &mut self.claims {
+            if let Presence::Away(since) = claim.presence {
+                if now.duration_since(since) >= Duration::from_secs(RESUME_GRACE_SECS) {
+                    claim.presence = Presence::Gone;
+                }
+            }
+            if play_again_expired {
+                claim.presence = Presence::Gone;
+            }
+        }
+        self.claims.iter().all(|c| c.presence == Presence::Gone)
+    }
+
+    /// The pause due while a seat is held or a member asked for one, never once the game is over.
+    /// Once the game stood still for `MAX_PAUSE_SECS` in total, held seats are given up and requests ignored.
+    /// Returns it with the message announcing it, if that changed.
+    fn update_pause(&mut self, game_over: bool, now: Instant) -> (Option<Pause>, Option<ServerMessage>) {
+        let paused_for = self.paused_for + self.paused_since.map_or(Duration::ZERO, |since| now.duration_since(since));
+        if paused_for >= Duration::from_secs(MAX_PAUSE_SECS) {
+            self.pause_requested = false;
+            for claim in &mut self.claims {
+                if matches!(claim.presence, Presence::Away(_)) {
+                    claim.presence = Presence::Gone;
+                }
+            }
+        }
+        let away: Vec<usize> = self.claims.iter().enumerate()
+            .filter(|(_, c)| matches!(c.presence, Presence::Away(_)))
+            .map(|(seat, _)| seat)
+            .collect();
+        let pause = (!game_over && (self.pause_requested || !away.is_empty()))
+            .then_some(Pause { requested: self.pause_requested, away });
+        match (&pause, self.paused_since) {
+            (Some(_), None) => self.paused_since = Some(now),
+            (None, Some(since)) => {
+                self.paused_for += now.duration_since(since);
+                self.paused_since = None;
+            }
+            _ => {}
+        }
+        if pause == self.announced_pause {
+            return (pause, None);
+        }
+        let msg = match &pause {
+            Some(pause) => ServerMessage::Paused { requested: pause.requested, away: pause.away.clone() },
+            None => ServerMessage::Resumed,
+        };
+        self.announced_pause = pause.clone();
+        (pause, Some(msg))
+    }
+
+    /// Clears the pause and the results for a new game
+    fn restart(&mut self) {
+        self.pause_requested = false;
+        self.announced_pause = None;
+        self.paused_since = None;
+        self.paused_for = Duration::ZERO;
+        self.over_since = None;
+    }
+}
+
+/// Advances the simulation unless paused. The clock starts over on resume, so a pause is not caught up on.
+fn run_clock(state: &mut GameState, paused: bool, now: Instant) {
+    if paused {
+        state.last_update = None;
+    } else {
+        state.advance(now);
+    }
+}
+
+/// A solo run, or up to four defenders sharing one arena.
+/// Co-op rooms wait in a lobby until their creator starts them; a solo room starts at once.
+pub struct Room {
+    id: String,
+    coop: bool,
+    tick_rate: u32,
+    difficulty: String,
+    config: GameConfig,
+    members: Vec<Member>,
+    seating: Seating,
+    state: Option<GameState>, // None until the game starts
+    started_at_ms: i64,
+}
+
+/// Why a connection could not join a room
+pub enum JoinError {
+    NotFound,
+    Started,
+    Full,
+}
+
+/// Why a connection could not resume a session
+pub enum ResumeError {
+    NotFound,
+    Forbidden, // Wrong token, or another user's session
+    Expired,
+}
+
+/// What one iteration of the game loop sends
+struct Step {
+    game_id: String,
+    state_json: Option<String>, // None while paused or over
+    frames: Vec<(String, Session, Frame)>, // By connection id
+    game_over: bool, // The game ended this step; the results are due
+    live: Option<spectate::LiveGame>,
+    finished: bool, // Every member left for good
+}
+
+impl Room {
+    pub fn new(id: String, coop: bool, tick_rate: u32, difficulty: String, config: GameConfig) -> Self {
+        Self {
+            id,
+            coop,
+            tick_rate,
+            difficulty,
+            config,
+            members: Vec::new(),
+            seating: Seating::default(),
+            state: None,
+            started_at_ms: 0,
+        }
+    }
+
+    /// Adds a member and returns its seat
+    fn join(&mut self, user_id: String, username: String, session: Session, wire: Wire) -> Result<usize, JoinError> {
+        if self.state.is_some() {
+            return Err(JoinError::Started);
+        }
+        if self.members.len() >= MAX_ROOM_PLAYERS {
+            return Err(JoinError::Full);
+        }
+        let seat = self.members.len();
+        self.seating.add(&user_id);
+        self.members.push(Member {
+            conn_id: uuid::Uuid::new_v4().to_string(),
+            stop: Arc::new(Notify::new()),
+            defender: Defender { user_id, username, seat },
+            session,
+            wire,
+            encoder: DeltaEncoder::new(),
+            resync: false,
+            dropped_frames: 0,
+            abuse: None,
+        });
+        Ok(seat)
+    }
+
+    /// Moves a session onto a new connection. Returns its seat and the connection it replaced, if that was still open.
+    /// The replaced connection's input task is stopped.
+    fn resume(&mut self, session_id: &str, resume_token: &str, user_id: &str, session: Session, wire: Wire) -> Result<(usize, Option<Session>), ResumeError> {
+        let (seat, was_connected) = self.seating.resume(session_id, resume_token, user_id)?;
+        let member = &mut self.members[seat];
+        let replaced = was_connected.then(|| member.session.clone());
+        member.stop.notify_one();
+        member.conn_id = uuid::Uuid::new_v4().to_string();
+        member.stop = Arc::new(Notify::new());
+        member.session = session;
+        member.wire = wire;
+        member.encoder = DeltaEncoder::new();
+        Ok((seat, replaced))
+    }
+
+    fn seat(&self, conn_id: &str) -> Option<usize> {
+        self.members.iter().position(|m| m.conn_id == conn_id)
+    }
+
+    /// Creates the shared state with one defender per member
+    fn start(&mut self) {
+        let mut state = GameState::new(self.tick_rate, self.config.clone());
+        for _ in 1..self.members.len() {
+            state.add_player();
+        }
+        self.state = Some(state);
+        self.started_at_ms = chrono::Utc::now().timestamp_millis();
+    }
+
+    /// Starts a new game with the members still connected once the results are out. Returns the
+    /// session ids of the members left behind, or None if there is no finished game to restart.
+    fn play_again(&mut self, conn_id: &str) -> Option<Vec<String>> {
+        self.seating.over_since?;
+        self.seat(conn_id)?;
+        let mut left = Vec::new();
+        let claims = std::mem::take(&mut self.seating.claims);
+        for (member, claim) in std::mem::take(&mut self.members).into_iter().zip(claims) {
+            if claim.presence == Presence::Connected {
+                self.members.push(member);
+                self.seating.claims.push(claim);
+            } else {
+                left.push(claim.session_id);
+            }
+        }
+        for (seat, member) in self.members.iter_mut().enumerate() {
+            member.defender.seat = seat;
+            member.encoder = DeltaEncoder::new();
+            member.dropped_frames = 0;
+            member.abuse = None;
+        }
+        // A solo run is stored under the room id, so the next one needs a new one
+        if !self.coop {
+            self.id = uuid::Uuid::new_v4().to_string();
+        }
+        self.seating.restart();
+        self.start();
+        Some(left)
+    }
+
+    /// The member in `seat` lost its connection. Its seat is held for a reconnect unless the game
+    /// is over or the member was cut off for abuse.
+    fn disconnect(&mut self, seat: usize, now: Instant) {
+        let game_over = self.state.as_ref().is_some_and(|s| s.game_over);
+        let release = game_over || self.members[seat].abuse.is_some();
+        self.seating.disconnect(seat, release, now);
+    }
+
+    /// A connection closed. Before the start its member leaves and the others move up a seat;
+    /// returns the departed member's session id then.
+    fn leave(&mut self, conn_id: &str, now: Instant) -> Option<String> {
+        let seat = self.seat(conn_id)?;
+        if self.state.is_some() {
+            self.disconnect(seat, now);
+            return None;
+        }
+        self.members.remove(seat);
+        for (seat, member) in self.members.iter_mut().enumerate() {
+            member.defender.seat = seat;
+        }
+        Some(self.seating.claims.remove(seat).session_id)
+    }
+
+    /// Keeps a member's dropped frame count, and the reason if they were cut off, for its run record
+    fn record_drops(&mut self, conn_id: &str, dropped_frames: u32, abuse: Option<AbuseReason>) {
+        let Some(seat) = self.seat(conn_id) else { return };
+        let member = &mut self.members[seat];
+        member.dropped_frames = dropped_frames;
+        member.abuse = abuse.or(member.abuse);
+    }
+
+    /// The ids and tokens a client needs to reconnect, sent on every new connection
+    fn session_frame(&self, seat: usize) -> (Session, Frame) {
+        let (member, claim) = (&self.members[seat], &self.seating.claims[seat]);
+        let msg = ServerMessage::Session {
+            session_id: claim.session_id.clone(),
+            resume_token: claim.resume_token.clone(),
+            resume_grace_secs: RESUME_GRACE_SECS,
+        };
+        (member.session.clone(), dragonball_protocol::encode(&msg, member.wire.encoding))
+    }
+
+    /// Lobby message for every member, sent whenever someone joins or leaves
+    fn lobby_frames(&self) -> Vec<(Session, Frame)> {
+        let players: Vec<String> = self.members.iter().map(|m| m.defender.username.clone()).collect();
+        self.members.iter().enumerate()
+            .map(|(seat, member)| {
+                let lobby = ServerMessage::Lobby {
+                    room_id: self.id.clone(),
+                    players: players.clone(),
+                    seat,
+                    can_start: seat == 0 && self.members.len() >= MIN_COOP_PLAYERS,
+                };
+                (member.session.clone(), dragonball_protocol::encode(&lobby, member.wire.encoding))
+            })
+            .collect()
+    }
+
+    /// Hello for the member in `seat`, if it speaks v2
+    fn hello_frame(&self, seat: usize) -> Option<(Session, Frame)> {
+        let member = &self.members[seat];
+        if member.wire.protocol != 2 {
+            return None;
+        }
+        let hello = ServerMessage::Hello {
+            protocol: member.wire.protocol,
+            encoding: member.wire.encoding,
+            tick_rate: self.tick_rate,
+            difficulty: self.difficulty.clone(),
+            config: self.config.clone(),
+            seat,
+        };
+        Some((member.session.clone(), dragonball_protocol::encode(&hello, member.wire.encoding)))
+    }
+
+    /// What a reconnected member needs to pick up where it left: its session, the hello on v2,
+    /// the current state and whether the game is paused
+    fn resume_frames(&mut self, seat: usize) -> Vec<(Session, Frame)> {
+        let mut frames = vec![self.session_frame(seat)];
+        frames.extend(self.hello_frame(seat));
+        let member = &mut self.members[seat];
+        if let Some(state) = self.state.as_ref() {
+            let frame = match member.wire.protocol {
+                1 => Some(Frame::Text(serde_json::to_string(state).unwrap_or_default())),
+                _ => member.encoder.next(state).map(|msg| dragonball_protocol::encode(&msg, member.wire.encoding)),
+            };
+            frames.extend(frame.map(|frame| (member.session.clone(), frame)));
+        }
+        if let Some(pause) = &self.seating.announced_pause {
+            let msg = ServerMessage::Paused { requested: pause.requested, away: pause.away.clone() };
+            frames.push((member.session.clone(), dragonball_protocol::encode(&msg, member.wire.encoding)));
+        }
+        frames
+    }
+
+    /// `msg` for every connected member
+    fn broadcast(&self, msg: &ServerMessage) -> Vec<(String, Session, Frame)> {
+        self.members.iter().enumerate()
+            .filter(|(seat, _)| self.seating.connected(*seat))
+            .map(|(_, member)| (member.conn_id.clone(), member.session.clone(), dragonball_protocol::encode(msg, member.wire.encoding)))
+            .collect()
+    }
+
+    /// This frame for every connected member: the whole state on v1, a delta (if anything changed) on v2
+    fn state_frames(&mut self, state_json: &str) -> Vec<(String, Session, Frame)> {
+        let Some(state) = self.state.as_ref() else { return Vec::new() };
+        let mut frames = Vec::new();
+        for (seat, member) in self.members.iter_mut().enumerate() {
+            if !self.seating.connected(seat) {
+                continue;
+            }
+            if member.wire.protocol == 1 {
+                frames.push((member.conn_id.clone(), member.session.clone(), Frame::Text(state_json.to_string())));
+                continue;
+            }
+            if std::mem::take(&mut member.resync) {
+                member.encoder.request_resync();
+            }
+            if let Some(update) = member.encoder.next(state) {
+                frames.push((member.conn_id.clone(), member.session.clone(), dragonball_protocol::encode(&update, member.wire.encoding)));
+            }
+        }
+        frames
+    }
+
+    fn live_game(&self, state: &GameState) -> spectate::LiveGame {
+        spectate::LiveGame {
+            game: "dragonball".to_string(),
+            game_id: self.id.clone(),
+            players: self.members.iter().zip(&state.players)
+                .map(|(m, p)| spectate::LivePlayer { user_id: m.defender.user_id.clone(), username: m.defender.username.clone(), score: p.score as i64 })
+                .collect(),
+            started_at_ms: self.started_at_ms,
+            spectators: 0,
+        }
+    }
+
+    /// One game loop iteration. Seats held past the grace period are given up; while a seat is
+    /// held or a member asked for a pause the state stands still. Once the results are out nothing
+    /// is sent, and when nobody asked to play again in time everyone is let go.
+    fn step(&mut self, now: Instant) -> Step {
+        let finished = self.seating.expire(now);
+        let game_id = self.id.clone();
+        let over = self.seating.over_since.is_some();
+        let Some(state) = self.state.as_mut().filter(|_| !over) else {
+            return Step { game_id, state_json: None, frames: Vec::new(), game_over: false, live: None, finished };
+        };
+        let (pause, announcement) = self.seating.update_pause(state.game_over, now);
+        run_clock(state, pause.is_some(), now);
+        let mut frames = announcement.map(|msg| self.broadcast(&msg)).unwrap_or_default();
+
+        let state = self.state.as_ref().expect("checked above");
+        let game_over = state.game_over;
+        let live = (!game_over).then(|| self.live_game(state));
+        let state_json = pause.is_none().then(|| serde_json::to_string(state).unwrap());
+        if let Some(json) = &state_json {
+            frames.extend(self.state_frames(json));
+        }
+        Step { game_id, state_json, frames, game_over, live, finished }
+    }
+
+    /// Each connected member's result, in seat order as returned by `save_runs`.
+    /// Opens the play again window.
+    fn results(&mut self, results: Vec<ServerMessage>, now: Instant) -> Vec<(String, Session, Frame)> {
+        self.seating.over_since = Some(now);
+        self.members.iter().enumerate().zip(results)
+            .filter(|((seat, _), _)| self.seating.connected(*seat))
+            .map(|((_, member), msg)| (member.conn_id.clone(), member.session.clone(), dragonball_protocol::encode(&msg, member.wire.encoding)))
+            .collect()
+    }
+
+    /// One record per defender. A solo run is recorded under the room id and without a room.
+    fn records(&self) -> Vec<RunRecord> {
+        let Some(state) = self.state.as_ref() else { return Vec::new() };
+        self.members.iter()
+            .map(|member| {
+                let mut run = if self.coop {
+                    let run_id = uuid::Uuid::new_v4().to_string();
+                    RunRecord::from_state(&run_id, Some(&self.id), &member.defender, &self.difficulty, self.started_at_ms, state)
+                } else {
+                    RunRecord::from_state(&self.id, None, &member.defender, &self.difficulty, self.started_at_ms, state)
+                };
+                run.dropped_frames = member.dropped_frames;
+                run.disconnect_reason = member.abuse.map(|reason| reason.as_str().to_string());
+                run
+            })
+            .collect()
+    }
+}
+
+/// Adds a connection to a room that has not started yet. Returns the room and the connection id.
+pub fn join(rooms: &Rooms, room_id: &str, user_id: String, username: String, session: Session, wire: Wire) -> Result<(Arc<Mutex<Room>>, String), JoinError> {
+    let room = rooms.lock().unwrap().rooms.get(room_id).cloned().ok_or(JoinError::NotFound)?;
+    let (session_id, conn_id) = {
+        let mut locked = room.lock().unwrap();
+        let seat = locked.join(user_id, username, session, wire)?;
+        (locked.seating.claims[seat].session_id.clone(), locked.members[seat].conn_id.clone())
+    };
+    rooms.lock().unwrap().sessions.insert(session_id, room.clone());
+    Ok((room, conn_id))
+}
+
+/// A new room with its creator as the first member. Co-op rooms are listed so others can join.
+/// Returns the room and the connection id.
+pub fn create(rooms: &Rooms, mut room: Room, user_id: String, username: String, session: Session, wire: Wire) -> (Arc<Mutex<Room>>, String) {
+    // A fresh room is empty, so joining cannot fail
+    let _ = room.join(user_id, username, session, wire);
+    let (id, coop) = (room.id.clone(), room.coop);
+    let (session_id, conn_id) = (room.seating.claims[0].session_id.clone(), room.members[0].conn_id.clone());
+    let room = Arc::new(Mutex::new(room));
+    let mut registry = rooms.lock().unwrap();
+    if coop {
+        registry.rooms.insert(id, room.clone());
+    }
+    registry.sessions.insert(session_id, room.clone());
+    (room, conn_id)
+}
+
+/// Moves a session of `user_id` onto a new connection and catches the client up.
+/// Returns the room and the connection id.
+pub async fn resume(
+    rooms: &Rooms,
+    session_id: &str,
+    resume_token: &str,
+    user_id: &str,
+    session: Session,
+    wire: Wire,
+) -> Result<(Arc<Mutex<Room>>, String), ResumeError> {
+    let room = rooms.lock().unwrap().sessions.get(session_id).cloned().ok_or(ResumeError::NotFound)?;
+    let (conn_id, replaced, frames) = {
+        let mut locked = room.lock().unwrap();
+        let (seat, replaced) = locked.resume(session_id, resume_token, user_id, session, wire)?;
+        (locked.members[seat].conn_id.clone(), replaced, locked.resume_frames(seat))
+    };
+    // The old connection may not have noticed it dropped yet
+    if let Some(old) = replaced {
+        let _ = old.close(None).await;
+    }
+    send_all(frames).await;
+    Ok((room, conn_id))
+}
+
+impl JoinError {
+    pub fn response(&self) -> HttpResponse {
+        match self {
+            JoinError::NotFound => HttpResponse::NotFound().body("Room not found"),
+            JoinError::Started => HttpResponse::Conflict().body("Room already started"),
+            JoinError::Full => HttpResponse::Conflict().body("Room is full"),
+        }
+    }
+}
+
+impl ResumeError {
+    pub fn response(&self) -> HttpResponse {
+        match self {
+            ResumeError::NotFound => HttpResponse::NotFound().body("Session not found"),
+            ResumeError::Forbidden => HttpResponse::Forbidden().body("Invalid resume token"),
+            ResumeError::Expired => HttpResponse::Gone().body("Session expired"),
+        }
+    }
+}
+
+async fn send_all(frames: Vec<(Session, Frame)>) {
+    for (mut session, frame) in frames {
+        let _ = dragonball_protocol::send(&mut session, frame).await;
+    }
+}
+
+/// Greets a new connection with its session, then sends the lobby to a co-op room's members
+/// or starts a solo room's game at once
+pub async fn open(data: web::Data<AppState>, room: Arc<Mutex<Room>>, conn_id: String) {
+    let (coop, frames) = {
+        let room = room.lock().unwrap();
+        let Some(seat) = room.seat(&conn_id) else { return };
+        let mut frames = vec![room.session_frame(seat)];
+        if room.coop {
+            frames.extend(room.lobby_frames());
+        }
+        (room.coop, frames)
+    };
+    send_all(frames).await;
+    if !coop {
+        start(data, room).await;
+    }
+}
+
+/// Starts the game and its loop, unless it already runs
+async fn start(data: web::Data<AppState>, room: Arc<Mutex<Room>>) {
+    let hello = {
+        let mut room = room.lock().unwrap();
+        if room.state.is_some() {
+            return;
+        }
+        room.start();
+        (0..room.members.len()).filter_map(|seat| room.hello_frame(seat)).collect()
+    };
+    send_all(hello).await;
+    actix_web::rt::spawn(run_game(data, room));
+}
+
+/// Applies a client text message. Returns false if it is none of the known messages.
+async fn apply_text(data: &web::Data<AppState>, room: &Arc<Mutex<Room>>, conn_id: &str, text: &str) -> bool {
+    if let Ok(player_input) = serde_json::from_str::<Player>(text) {
+        let mut room = room.lock().unwrap();
+        if let (Some(seat), false) = (room.seat(conn_id), room.seating.paused()) {
+            if let Some(state) = room.state.as_mut() {
+                state.update_player_pos(seat, player_input.y);
+            }
+        }
+        return true;
+    }
+    let Ok(control) = serde_json::from_str::<ClientControl>(text) else { return false };
+    match control {
+        ClientControl::Resync => {
+            let mut room = room.lock().unwrap();
+            if let Some(seat) = room.seat(conn_id) {
+                room.members[seat].resync = true;
+            }
+        }
+        ClientControl::UsePowerUp { power_up } => {
+            let mut room = room.lock().unwrap();
+            if let (Some(seat), false) = (room.seat(conn_id), room.seating.paused()) {
+                if let Some(state) = room.state.as_mut() {
+                    state.use_power_up(seat, power_up);
+                }
+            }
+        }
+        ClientControl::Start => {
+            let may_start = {
+                let room = room.lock().unwrap();
+                room.coop && room.seat(conn_id) == Some(0) && room.members.len() >= MIN_COOP_PLAYERS
+            };
+            if may_start {
+                start(data.clone(), room.clone()).await;
+            }
+        }
+        ClientControl::Pause | ClientControl::Resume => {
+            let mut room = room.lock().unwrap();
+            if room.seat(conn_id).is_some() {
+                room.seating.pause_requested = matches!(control, ClientControl::Pause);
+            }
+        }
+        ClientControl::PlayAgain => {
+            let restarted = {
+                let mut room = room.lock().unwrap();
+                room.play_again(conn_id)
+                    .map(|left| (left, (0..room.members.len()).filter_map(|seat| room.hello_frame(seat)).collect()))
+            };
+            if let Some((left, hello)) = restarted {
+                {
+                    let mut registry = data.dragonball_rooms.lock().unwrap();
+                    for session_id in left {
+                        registry.sessions.remove(&session_id);
+                    }
+                }
+                send_all(hello).await;
+            }
+        }
+    }
+    true
+}
+
+/// Client -> server messages of one connection until it closes, goes idle or the room stops it.
+/// Oversized, malformed and excess frames are dropped; a client that keeps sending them is disconnected.
+/// The player's achievement notifications are passed on meanwhile.
+pub async fn read_inputs(
+    data: web::Data<AppState>,
+    room: Arc<Mutex<Room>>,
+    conn_id: String,
+    mut session: Session,
+    mut msg_stream: actix_ws::MessageStream,
+) {
+    let (stop, user_id, encoding) = {
+        let room = room.lock().unwrap();
+        let Some(seat) = room.seat(&conn_id) else { return };
+        let member = &room.members[seat];
+        (member.stop.clone(), member.defender.user_id.clone(), member.wire.encoding)
+    };
+    let (notification_tx, mut notifications) = tokio::sync::mpsc::channel::<String>(8);
+    let relay = achievements::relay(data.redis_client.clone(), &user_id, notification_tx);
+    let mut guard = InputGuard::new(Instant::now());
+    let mut abuse = None;
+    let mut idle = false;
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    let mut last_seen = Instant::now();
+    loop {
+        let msg = tokio::select! {
+            msg = msg_stream.next() => match msg {
+                Some(Ok(msg)) => msg,
+                _ => break,
+            },
+            _ = stop.notified() => break,
+            Some(payload) = notifications.recv() => {
+                if let Ok(Notification::AchievementUnlocked { achievement }) = serde_json::from_str(&payload) {
+                    let msg = ServerMessage::AchievementUnlocked { achievement };
+                    if dragonball_protocol::send(&mut session, dragonball_protocol::encode(&msg, encoding)).await.is_err() {
+                        break;
+                    }
+                }
+                continue;
+            }
+            _ = ping.tick() => {
+                if last_seen.elapsed() >= IDLE_TIMEOUT {
+                    idle = true;
+                    break;
+                }
+                if session.ping(b"").await.is_err() {
+                    break;
+                }
+                continue;
+            }
+        };
+        last_seen = Instant::now();
+        let verdict = match msg {
+            Message::Text(text) => match guard.admit(text.len(), Instant::now()) {
+                Verdict::Accept if apply_text(&data, &room, &conn_id, &text).await => Verdict::Accept,
+                Verdict::Accept => guard.malformed(),
+                other => other,
+            },
+            // Client messages are always JSON text
+            Message::Binary(bytes) => match guard.admit(bytes.len(), Instant::now()) {
+                Verdict::Accept => guard.malformed(),
+                other => other,
+            },
+            Message::Ping(bytes) => match guard.admit(bytes.len(), Instant::now()) {
+                Verdict::Accept if session.pong(&bytes).await.is_err() => break,
+                other => other,
+            },
+            Message::Close(_) => break,
+            _ => Verdict::Accept,
+        };
+        match verdict {
+            Verdict::Accept => {}
+            Verdict::Drop => room.lock().unwrap().record_drops(&conn_id, guard.dropped.total(), None),
+            Verdict::Disconnect(reason) => {
+                abuse = Some(reason);
+                break;
+            }
+        }
+    }
+
+    relay.abort();
+
+    if let Some(reason) = abuse {
+        room.lock().unwrap().record_drops(&conn_id, guard.dropped.total(), Some(reason));
+        eprintln!("Disconnected dragonball player {} ({}): {:?}", user_id, reason.as_str(), guard.dropped);
+        let _ = session.close(Some(CloseReason { code: CloseCode::Policy, description: Some(reason.as_str().to_string()) })).await;
+    } else if idle {
+        let _ = session.close(Some(CloseReason { code: CloseCode::Away, description: Some("idle_timeout".to_string()) })).await;
+    }
+
+    // A started game holds the seat for a reconnect. Leaving the lobby updates the others; an emptied lobby is closed.
+    let (lobby, departed, abandoned, room_id) = {
+        let mut room = room.lock().unwrap();
+        let departed = room.leave(&conn_id, Instant::now());
+        let waiting = room.state.is_none();
+        (if waiting { room.lobby_frames() } else { Vec::new() }, departed, waiting && room.members.is_empty(), room.id.clone())
+    };
+    {
+        let mut registry = data.dragonball_rooms.lock().unwrap();
+        if let Some(session_id) = departed {
+            registry.sessions.remove(&session_id);
+        }
+        if abandoned {
+            registry.rooms.remove(&room_id);
+        }
+    }
+    send_all(lobby).await;
+}
+
+/// Adds a run's score to its leaderboard, keeping the best, and records it for window exports
+async fn record_leaderboard_score(db: &Pool<Postgres>, game: &str, run: &RunRecord) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO leaderboard (user_id, game_name, username, score, games_played)
+         VALUES ($1, $2, $3, $4, 1)
+         ON CONFLICT (user_id, game_name) DO UPDATE
+         SET score = GREATEST(leaderboard.score, EXCLUDED.score),
+             games_played = leaderboard.games_played + 1,
+             updated_at = CASE WHEN EXCLUDED.score > leaderboard.score THEN NOW() ELSE leaderboard.updated_at END"
+    )
+    .bind(&run.user_id)
+    .bind(game)
+    .bind(&run.username)
+    .bind(run.score as i32)
+    .execute(db)
+    .await?;
+    leaderboard::record_scored_run(db, game, &run.user_id, &run.username, run.score as i32).await
+}
+
+/// Saves every defender's run and returns each one's `game_over` result, in seat order.
+/// Verified runs count for the leaderboard: a solo run on its difficulty's board, each co-op
+/// defender's contribution on the co-op board of the difficulty. Defenders disconnected for abuse count
+/// neither there nor towards achievements.
+async fn save_runs(data: &AppState, room: &Mutex<Room>) -> Vec<ServerMessage> {
+    let (records, progress, coop, difficulty) = {
+        let room = room.lock().unwrap();
+        let progress: Vec<_> = match room.state.as_ref() {
+            Some(state) => (0..room.members.len()).map(|seat| achievements::dragonball_progress(state, seat, room.coop)).collect(),
+            None => Vec::new(),
+        };
+        (room.records(), progress, room.coop, room.difficulty.clone())
+    };
+    let board = if coop { coop_leaderboard_game(&difficulty) } else { leaderboard_game(&difficulty) };
+    let mut results = Vec::with_capacity(records.len());
+    for (run, progress) in records.into_iter().zip(progress) {
+        let finished = run.game_over_tick.is_some() && run.disconnect_reason.is_none();
+        let abusive = run.disconnect_reason.is_some();
+        let (user_id, recorded) = (run.user_id.clone(), run.clone());
+        let before = leaderboard::best_and_rank(&data.db, &board, &user_id).await.unwrap_or(None);
+        let verified = dragonball_runs::save_run(&data.db, run).await;
+        let mut counted = false;
+        if finished && verified {
+            match record_leaderboard_score(&data.db, &board, &recorded).await {
+                Ok(()) => counted = true,
+                Err(e) => eprintln!("Failed to record dragonball score of {}: {}", user_id, e),
+            }
+            // Update Redis with the best score stored in the DB
+            leaderboard::refresh_cached_score(&data.db, &data.redis_client, &board, &user_id).await;
+            achievements::record_rank(data, &board, &user_id).await;
+        }
+        // Runs left early still count towards achievements, as long as they were played honestly
+        if verified && !abusive {
+            achievements::record(data, &user_id, &progress).await;
+        }
+        let after = if counted { leaderboard::best_and_rank(&data.db, &board, &user_id).await.unwrap_or(None) } else { before };
+        results.push(ServerMessage::GameOver {
+            score: recorded.score,
+            team_score: recorded.team_score,
+            leaderboard: board.clone(),
+            counted,
+            personal_best: after.map(|(best, _)| best as u32),
+            new_best: counted && before.is_none_or(|(best, _)| recorded.score as i32 > best),
+            rank: after.map(|(_, rank)| rank),
+            play_again_secs: PLAY_AGAIN_SECS,
+        });
+    }
+    results
+}
+
+/// Sends frames to members. A member whose send fails lost its connection.
+async fn send_to_members(room: &Mutex<Room>, frames: Vec<(String, Session, Frame)>) {
+    let mut dropped = Vec::new();
+    for (conn_id, mut session, frame) in frames {
+        if dragonball_protocol::send(&mut session, frame).await.is_err() {
+            dropped.push(conn_id);
+        }
+    }
+    if !dropped.is_empty() {
+        let mut room = room.lock().unwrap();
+        for conn_id in dropped {
+            if let Some(seat) = room.seat(&conn_id) {
+                room.disconnect(seat, Instant::now());
+            }
+        }
+    }
+}
+
+/// Server -> client loop of a room, until every member left for good or nobody played again.
+/// The simulation runs once per room; each member gets the frames of its own protocol.
+/// When the loop ends every connection is closed and its input task stopped.
+async fn run_game(data: web::Data<AppState>, room: Arc<Mutex<Room>>) {
+    let tick_rate = room.lock().unwrap().tick_rate;
+    // The loop only paces sending; the state advances by however much real time passed
+    let mut ticker = tokio::time::interval(Duration::from_secs_f64(1.0 / tick_rate as f64));
+    // Spectators receive the same states through Valkey, so they may be on another instance
+    let mut live_con = data.redis_client.get_async_connection().await.ok();
+    let mut tick_count: u64 = 0;
+
+    loop {
+        ticker.tick().await;
+
+        let step = room.lock().unwrap().step(Instant::now());
+        if step.finished {
+            break;
+        }
+
+        if let Some(con) = live_con.as_mut() {
+            if let Some(state_json) = &step.state_json {
+                let _: Result<(), redis::RedisError> = con.publish(spectate::dragonball_channel(&step.game_id), state_json).await;
+            }
+            // Refresh the live entry about once a second while the run lasts, paused or not
+            if let Some(entry) = step.live.filter(|_| tick_count.is_multiple_of(tick_rate as u64)) {
+                let _ = spectate::publish_live(con, &entry, spectate::DRAGONBALL_LIVE_TTL_SECS).await;
+            }
+        }
+        tick_count += 1;
+
+        send_to_members(&room, step.frames).await;
+
+        // Game over is shared: every defender's run ends on the same tick. The final state
+        // went out above; each defender then gets their own result.
+        if step.game_over {
+            if let Some(con) = live_con.as_mut() {
+                let _ = spectate::remove_live(con, "dragonball", &step.game_id).await;
+            }
+            let results = save_runs(&data, &room).await;
+            let frames = room.lock().unwrap().results(results, Instant::now());
+            send_to_members(&room, frames).await;
+        }
+    }
+
+    let (saved, game_id, members) = {
+        let room = room.lock().unwrap();
+        let members: Vec<(String, Session, Arc<Notify>)> = room.members.iter().zip(&room.seating.claims)
+            .map(|(m, c)| (c.session_id.clone(), m.session.clone(), m.stop.clone()))
+            .collect();
+        (room.seating.over_since.is_some(), room.id.clone(), members)
+    };
+    // Everyone left mid-run
+    if !saved {
+        if let Some(con) = live_con.as_mut() {
+            let _ = spectate::remove_live(con, "dragonball", &game_id).await;
+        }
+        save_runs(&data, &room).await;
+    }
+    for (_, session, stop) in &members {
+        stop.notify_one();
+        let _ = session.clone().close(Some(CloseReason { code: CloseCode::Normal, description: Some("game_over".to_string()) })).await;
+    }
+    let mut registry = data.dragonball_rooms.lock().unwrap();
+    registry.rooms.remove(&game_id);
+    for (session_id, _, _) in members {
+        registry.sessions.remove(&session_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dragonballgame::DEFAULT_TICK_RATE;
+
+    fn seating(users: &[&str]) -> Seating {
+        let mut seating = Seating::default();
+        for user in users {
+            seating.add(user);
+        }
+        seating
+    }
+
+    fn credentials(seating: &Seating, seat: usize) -> (String, String) {
+        (seating.claims[seat].session_id.clone(), seating.claims[seat].resume_token.clone())
+    }
+
+    #[test]
+    fn resuming_needs_the_token_and_the_same_user() {
+        let mut seating = seating(&["u1", "u2"]);
+        let (session_id, token) = credentials(&seating, 1);
+        assert!(matches!(seating.resume("unknown", &token, "u2"), Err(ResumeError::NotFound)));
+        assert!(matches!(seating.resume(&session_id, "wrong", "u2"), Err(ResumeError::Forbidden)));
+        assert!(matches!(seating.resume(&session_id, &token, "u1"), Err(ResumeError::Forbidden)));
+        assert!(matches!(seating.resume(&session_id, &token, "u2"), Ok((1, true))));
+
+        seating.disconnect(1, false, Instant::now());
+        assert!(!seating.connected(1));
+        assert!(matches!(seating.resume(&session_id, &token, "u2"), Ok((1, false))));
+        assert!(seating.connected(1));
+    }
+
+    #[test]
+    fn held_seats_are_given_up_after_the_grace_period() {
+        let mut seating = seating(&["u1", "u2"]);
+        let (session_id, token) = credentials(&seating, 1);
+        let dropped = Instant::now();
+        seating.disconnect(1, false, dropped);
+
+        assert!(!seating.expire(dropped + Duration::from_secs(RESUME_GRACE_SECS - 1)));
+        assert!(matches!(seating.claims[1].presence, Presence::Away(_)));
+        assert!(!seating.expire(dropped + Duration::from_secs(RESUME_GRACE_SECS)));
+        assert!(seating.claims[1].presence == Presence::Gone);
+        assert!(matches!(seating.resume(&session_id, &token, "u2"), Err(ResumeError::Expired)));
+
+        // Released seats, after game over or abuse, are not held at all
+        seating.disconnect(0, true, dropped);
+        assert!(seating.claims[0].presence == Presence::Gone);
+        assert!(seating.expire(dropped));
+    }
+
+    #[test]
+    fn everyone_is_let_go_when_nobody_plays_again() {
+        let mut seating = seating(&["u1", "u2"]);
+        let over = Instant::now();
+        seating.over_since = Some(over);
+        assert!(!seating.expire(over + Duration::from_secs(PLAY_AGAIN_SECS - 1)));
+        assert!(seating.expire(over + Duration::from_secs(PLAY_AGAIN_SECS)));
+    }
+
+    #[test]
+    fn the_game_pauses_while_a_seat_is_held() {
+        let mut seating = seating(&["u1", "u2"]);
+        let now = Instant::now();
+        let (session_id, token) = credentials(&seating, 1);
+        assert_eq!(seating.update_pause(false, now).0, None);
+
+        seating.disconnect(1, false, Instant::now());
+        let (pause, msg) = seating.update_pause(false, now);
+        assert_eq!(pause, Some(Pause { requested: false, away: vec![1] }));
+        assert!(matches!(msg, Some(ServerMessage::Paused { requested: false, ref away }) if away == &[1]));
+        // Announced once
+        assert!(seating.update_pause(false, now).1.is_none());
+
+        assert!(seating.resume(&session_id, &token, "u2").is_ok());
+        let (pause, msg) = seating.update_pause(false, now);
+        assert_eq!(pause, None);
+        assert!(matches!(msg, Some(ServerMessage::Resumed)));
+
+        seating.pause_requested = true;
+        assert_eq!(seating.update_pause(false, now).0, Some(Pause { requested: true, away: Vec::new() }));
+        // Nothing to wait for once the game is over
+        assert_eq!(seating.update_pause(true, now).0, None);
+    }
+
+    #[test]
+    fn pauses_end_once_the_game_stood_still_long_enough() {
+        let mut seating = seating(&["u1", "u2"]);
+        let start = Instant::now();
+        seating.pause_requested = true;
+        assert!(seating.update_pause(false, start).0.is_some());
+        seating.pause_requested = false;
+        let resumed = start + Duration::from_secs(MAX_PAUSE_SECS - 10);
+        assert_eq!(seating.update_pause(false, resumed).0, None);
+        assert!(!seating.paused());
+
+        // The budget is shared by all pauses of the game
+        seating.disconnect(1, false, resumed);
+        assert!(seating.update_pause(false, resumed).0.is_some());
+        assert!(seating.paused());
+        assert!(seating.update_pause(false, resumed + Duration::from_secs(9)).0.is_some());
+        let (pause, msg) = seating.update_pause(false, resumed + Duration::from_secs(10));
+        assert_eq!(pause, None);
+        assert!(matches!(msg, Some(ServerMessage::Resumed)));
+        assert!(seating.claims[1].presence == Presence::Gone);
+
+        // Further requests are ignored until the next game
+        seating.pause_requested = true;
+        assert_eq!(seating.update_pause(false, resumed + Duration::from_secs(11)).0, None);
+        seating.restart();
+        seating.pause_requested = true;
+        assert!(seating.update_pause(false, resumed + Duration::from_secs(12)).0.is_some());
+    }
+
+    #[test]
+    fn the_clock_does_not_catch_up_on_a_pause() {
+        let mut state = GameState::new(DEFAULT_TICK_RATE, GameConfig::default());
+        let step = Duration::from_secs_f64(state.step_secs());
+        let start = Instant::now();
+        run_clock(&mut state, false, start);
+        run_clock(&mut state, false, start + step * 2);
+        let ticks = state.tick_count;
+        assert!(ticks >= 1);
+
+        let resumed = start + Duration::from_secs(10);
+        run_clock(&mut state, true, start + Duration::from_secs(5));
+        run_clock(&mut state, true, resumed);
+        assert_eq!(state.tick_count, ticks);
+        run_clock(&mut state, false, resumed);
+        assert_eq!(state.tick_count, ticks);
+        run_clock(&mut state, false, resumed + step * 2 + step / 2);
+        assert_eq!(state.tick_count, ticks + 2);
+    }
+}
@@ -0,0 +1,516 @@
+use actix_web::{web, Error, HttpRequest, HttpResponse, Responder};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use actix_ws::Message;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use sqlx::{Pool, Postgres};
+use std::time::Duration;
+use crate::{AppState, authenticate_ws, validate_admin_token, validate_token};
+use crate::dragonballgame::{GameConfig, GameState, PlayerInput, SIM_VERSION};
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+/// A player and the defender they control
+#[derive(Clone)]
+pub struct Defender {
+    pub user_id: String,
+    pub username: String,
+    pub seat: usize,
+}
+
+/// A run as it ended in the game loop, either at game over or when the player left.
+/// A cooperative run is recorded once per defender, each record with the whole input log.
+#[derive(Clone)]
+pub struct RunRecord {
+    pub run_id: String,
+    pub user_id: String,
+    pub username: String,
+    pub room_id: Option<String>, // Set for cooperative runs
+    pub seat: usize,
+    pub players: usize,
+    pub seed: u64,
+    pub sim_version: u32, // Simulation the run was played under; only that version can replay it
+    pub tick_rate: u32,
+    pub difficulty: String,
+    pub config: GameConfig, // As played; a custom preset may have been edited since
+    pub inputs: Vec<PlayerInput>,
+    pub score: u32, // This defender's points; the whole run's in a solo run
+    pub team_score: u32,
+    pub wave: u32, // Wave the run ended in
+    pub ticks: u64,
+    pub game_over_tick: Option<u64>,
+    pub started_at_ms: i64,
+    pub dropped_frames: u32, // Client frames dropped as oversized, malformed or over the rate limit
+    pub disconnect_reason: Option<String>, // Set if the player was disconnected for abuse
+}
+
+impl RunRecord {
+    pub fn from_state(run_id: &str, room_id: Option<&str>, defender: &Defender, difficulty: &str, started_at_ms: i64, state: &GameState) -> Self {
+        Self {
+            run_id: run_id.to_string(),
+            user_id: defender.user_id.clone(),
+            username: defender.username.clone(),
+            room_id: room_id.map(str::to_string),
+            seat: defender.seat,
+            players: state.players.len(),
+            seed: state.seed,
+            sim_version: SIM_VERSION,
+            tick_rate: state.tick_rate,
+            difficulty: difficulty.to_string(),
+            config: state.config.clone(),
+            inputs: state.inputs.clone(),
+            score: state.players.get(defender.seat).map_or(0, |p| p.score),
+            team_score: state.score,
+            wave: state.wave,
+            ticks: state.tick_count,
+            game_over_tick: state.game_over_tick,
+            started_at_ms,
+            dropped_frames: 0,
+            disconnect_reason: None,
+        }
+    }
+}
+
+/// Recorded result next to the result of re-simulating the run
+#[derive(Serialize)]
+pub struct Verification {
+    pub recorded_score: u32,
+    pub simulated_score: u32,
+    pub recorded_team_score: u32,
+    pub simulated_team_score: u32,
+    pub recorded_game_over_tick: Option<u64>,
+    pub simulated_game_over_tick: Option<u64>,
+    pub matches: bool,
+}
+
+/// Re-simulates a run from its seed, config and inputs. Any difference from the recorded scores or
+/// game-over tick means the record cannot have come from the simulation and is treated as cheating.
+pub fn verify(run: &RunRecord) -> Verification {
+    let simulated = GameState::replay(run.seed, run.tick_rate, run.config.clone(), run.players, &run.inputs, run.ticks);
+    let simulated_score = simulated.players.get(run.seat).map_or(0, |p| p.score);
+    Verification {
+        recorded_score: run.score,
+        simulated_score,
+        recorded_team_score: run.team_score,
+        simulated_team_score: simulated.score,
+        recorded_game_over_tick: run.game_over_tick,
+        simulated_game_over_tick: simulated.game_over_tick,
+        matches: simulated_score == run.score && simulated.score == run.team_score && simulated.game_over_tick == run.game_over_tick,
+    }
+}
+
+/// Verifies a run on the blocking pool and stores it, flagged if verification failed.
+/// Returns true if the run verified, i.e. its score may count.
+pub async fn save_run(db: &Pool<Postgres>, run: RunRecord) -> bool {
+    let check = run.clone();
+    let verified = tokio::task::spawn_blocking(move || verify(&check).matches)
+    .await
+    .unwrap_or(false);
+    if !verified {
+        eprintln!("Dragonball run {} of {} failed verification and was flagged", run.run_id, run.user_id);
+    }
+
+    let started_at = chrono::DateTime::from_timestamp_millis(run.started_at_ms)
+        .unwrap_or_else(chrono::Utc::now)
+        .naive_utc();
+    let result = sqlx::query(
+        "INSERT INTO dragonball_runs
+             (run_id, user_id, username, room_id, seat, players, seed, sim_version, tick_rate, difficulty, config, inputs, score, team_score,
+              wave, ticks, game_over_tick, verified, flagged, dropped_frames, disconnect_reason, started_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22)
+         ON CONFLICT (run_id) DO NOTHING"
+    )
+    .bind(&run.run_id)
+    .bind(&run.user_id)
+    .bind(&run.username)
+    .bind(&run.room_id)
+    .bind(run.seat as i32)
+    .bind(run.players as i32)
+    .bind(run.seed as i64)
+    .bind(run.sim_version as i32)
+    .bind(run.tick_rate as i32)
+    .bind(&run.difficulty)
+    .bind(Json(&run.config))
+    .bind(Json(&run.inputs))
+    .bind(run.score as i32)
+    .bind(run.team_score as i32)
+    .bind(run.wave as i32)
+    .bind(run.ticks as i64)
+    .bind(run.game_over_tick.map(|t| t as i64))
+    .bind(verified)
+    .bind(!verified)
+    .bind(run.dropped_frames as i32)
+    .bind(&run.disconnect_reason)
+    .bind(started_at)
+    .execute(db)
+    .await;
+
+    if let Err(e) = result {
+        eprintln!("Failed to store dragonball run {}: {}", run.run_id, e);
+    }
+    verified
+}
+
+#[derive(sqlx::FromRow)]
+struct RunRow {
+    run_id: String,
+    user_id: String,
+    username: Option<String>,
+    room_id: Option<String>,
+    seat: i32,
+    players: i32,
+    seed: i64,
+    sim_version: i32,
+    tick_rate: i32,
+    difficulty: String,
+    config: Option<Json<GameConfig>>, // NULL for runs recorded before presets existed
+    inputs: Json<Vec<PlayerInput>>,
+    score: i32,
+    team_score: Option<i32>, // NULL for runs recorded before co-op
+    wave: i32,
+    ticks: i64,
+    game_over_tick: Option<i64>,
+    verified: bool,
+    flagged: bool,
+    dropped_frames: i32,
+    disconnect_reason: Option<String>,
+    started_at: chrono::NaiveDateTime,
+    finished_at: chrono::NaiveDateTime,
+}
+
+impl RunRow {
+    /// Whether this server's simulation can re-simulate the run
+    fn replayable(&self) -> bool {
+        self.sim_version as u32 == SIM_VERSION
+    }
+
+    fn config(&self) -> GameConfig {
+        self.config.as_ref().map(|c| c.0.clone()).unwrap_or_default()
+    }
+
+    fn record(&self) -> RunRecord {
+        RunRecord {
+            run_id: self.run_id.clone(),
+            user_id: self.user_id.clone(),
+            username: self.username.clone().unwrap_or_default(),
+            room_id: self.room_id.clone(),
+            seat: self.seat as usize,
+            players: self.players as usize,
+            seed: self.seed as u64,
+            sim_version: self.sim_version as u32,
+            tick_rate: self.tick_rate as u32,
+            difficulty: self.difficulty.clone(),
+            config: self.config(),
+            inputs: self.inputs.0.clone(),
+            score: self.score as u32,
+            team_score: self.team_score.unwrap_or(self.score) as u32,
+            wave: self.wave as u32,
+            ticks: self.ticks as u64,
+            game_over_tick: self.game_over_tick.map(|t| t as u64),
+            started_at_ms: self.started_at.and_utc().timestamp_millis(),
+            dropped_frames: self.dropped_frames as u32,
+            disconnect_reason: self.disconnect_reason.clone(),
+        }
+    }
+
+    /// The run without its seed and input log
+    fn summary(&self) -> RunSummary {
+        RunSummary {
+            run_id: self.run_id.clone(),
+            user_id: self.user_id.clone(),
+            username: self.username.clone(),
+            room_id: self.room_id.clone(),
+            players: self.players,
+            sim_version: self.sim_version,
+            difficulty: self.difficulty.clone(),
+            score: self.score,
+            team_score: self.team_score.unwrap_or(self.score),
+            wave: self.wave,
+            ticks: self.ticks,
+            completed: self.game_over_tick.is_some(),
+            verified: self.verified,
+            flagged: self.flagged,
+            dropped_frames: self.dropped_frames,
+            disconnect_reason: self.disconnect_reason.clone(),
+            started_at: self.started_at,
+            finished_at: self.finished_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct RunSummary {
+    pub run_id: String,
+    pub user_id: String,
+    pub username: Option<String>,
+    pub room_id: Option<String>,
+    pub players: i32,
+    pub sim_version: i32,
+    pub difficulty: String,
+    pub score: i32,
+    pub team_score: i32,
+    pub wave: i32,
+    pub ticks: i64,
+    pub completed: bool, // False if the player left before game over
+    pub verified: bool,
+    pub flagged: bool,
+    pub dropped_frames: i32,
+    pub disconnect_reason: Option<String>, // Set if the player was disconnected for abuse
+    pub started_at: chrono::NaiveDateTime,
+    pub finished_at: chrono::NaiveDateTime,
+}
+
+const RUN_COLUMNS: &str = "run_id, user_id, username, room_id, seat, players, seed, sim_version, tick_rate, difficulty, config, inputs, \
+    score, team_score, wave, ticks, game_over_tick, verified, flagged, dropped_frames, disconnect_reason, started_at, finished_at";
+
+async fn fetch_run(db: &Pool<Postgres>, run_id: &str) -> Result<Option<RunRow>, sqlx::Error> {
+    sqlx::query_as(&format!("SELECT {} FROM dragonball_runs WHERE run_id = $1", RUN_COLUMNS))
+        .bind(run_id)
+        .fetch_optional(db)
+        .await
+}
+
+#[derive(Deserialize)]
+pub struct RunsQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+impl RunsQuery {
+    fn page(&self) -> (i64, i64) {
+        (self.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE), self.offset.unwrap_or(0).max(0))
+    }
+}
+
+/// The authenticated player's runs, most recent first
+#[actix_web::get("/dragonball/runs")]
+pub async fn list_runs(
+    data: web::Data<AppState>,
+    auth: BearerAuth,
+    query: web::Query<RunsQuery>,
+) -> impl Responder {
+    let claims = match validate_token(auth.token(), &data.oidc_jwks_uri).await {
+        Ok(c) => c,
+        Err(r) => return r,
+    };
+    let (limit, offset) = query.page();
+
+    let rows: Vec<RunRow> = match sqlx::query_as(&format!(
+        "SELECT {} FROM dragonball_runs WHERE user_id = $1 ORDER BY finished_at DESC, run_id LIMIT $2 OFFSET $3",
+        RUN_COLUMNS
+    ))
+    .bind(&claims.sub)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(&data.db)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("DB error: {}", e)),
+    };
+
+    let runs: Vec<RunSummary> = rows.iter().map(RunRow::summary).collect();
+    HttpResponse::Ok().json(runs)
+}
+
+/// Admin list of runs whose re-simulation disagreed with the recorded result, or whose player was disconnected for abuse
+#[actix_web::get("/admin/dragonball/runs/flagged")]
+pub async fn list_flagged_runs(
+    data: web::Data<AppState>,
+    auth: BearerAuth,
+    query: web::Query<RunsQuery>,
+) -> impl Responder {
+    if let Err(r) = validate_admin_token(auth.token(), &data.oidc_jwks_uri).await {
+        return r;
+    }
+    let (limit, offset) = query.page();
+
+    let rows: Vec<RunRow> = match sqlx::query_as(&format!(
+        "SELECT {} FROM dragonball_runs WHERE flagged OR disconnect_reason IS NOT NULL ORDER BY finished_at DESC, run_id LIMIT $1 OFFSET $2",
+        RUN_COLUMNS
+    ))
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(&data.db)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("DB error: {}", e)),
+    };
+
+    let runs: Vec<RunSummary> = rows.iter().map(RunRow::summary).collect();
+    HttpResponse::Ok().json(runs)
+}
+
+/// Admin endpoint re-simulating a stored run and updating its verified/flagged state.
+/// Runs recorded under another simulation version cannot be re-simulated and are left as they are.
+#[actix_web::post("/admin/dragonball/runs/{run_id}/verify")]
+pub async fn verify_run(
+    data: web::Data<AppState>,
+    auth: BearerAuth,
+    path: web::Path<String>,
+) -> impl Responder {
+    if let Err(r) = validate_admin_token(auth.token(), &data.oidc_jwks_uri).await {
+        return r;
+    }
+
+    let row = match fetch_run(&data.db, &path).await {
+        Ok(Some(row)) => row,
+        Ok(None) => return HttpResponse::NotFound().body("Run not found"),
+        Err(e) => return HttpResponse::InternalServerError().body(format!("DB error: {}", e)),
+    };
+    if !row.replayable() {
+        return HttpResponse::Conflict().body(format!(
+            "Run was recorded with simulation version {}; this server runs version {}", row.sim_version, SIM_VERSION
+        ));
+    }
+    let verification = match tokio::task::spawn_blocking(move || verify(&row.record())).await {
+        Ok(v) => v,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("Verification failed: {}", e)),
+    };
+
+    let updated = sqlx::query("UPDATE dragonball_runs SET verified = $1, flagged = $2 WHERE run_id = $3")
+        .bind(verification.matches)
+        .bind(!verification.matches)
+        .bind(path.as_str())
+        .execute(&data.db)
+        .await;
+    if let Err(e) = updated {
+        return HttpResponse::InternalServerError().body(format!("DB error: {}", e));
+    }
+
+    HttpResponse::Ok().json(verification)
+}
+
+/// Replays a recorded run over a websocket: the run is re-simulated from its seed and inputs
+/// and every `GameState` is sent at the run's tick rate, exactly as the player saw it. The socket closes at the end.
+pub async fn replay_socket(
+    req: HttpRequest,
+    stream: web::Payload,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    if let Err(res) = authenticate_ws(&req, &data).await {
+        return Ok(res);
+    }
+    let row = match fetch_run(&data.db, &path).await {
+        Ok(Some(row)) => row,
+        Ok(None) => return Ok(HttpResponse::NotFound().body("Run not found")),
+        Err(e) => return Ok(HttpResponse::InternalServerError().body(format!("DB error: {}", e))),
+    };
+    if !row.replayable() {
+        return Ok(HttpResponse::Conflict().body("Run was recorded with another simulation version and cannot be replayed"));
+    }
+
+    let (res, mut session, mut msg_stream) = actix_ws::handle(&req, stream)?;
+
+    actix_web::rt::spawn(async move {
+        let ticks = row.ticks as u64;
+        let mut state = GameState::with_seed(row.seed as u64, row.tick_rate as u32, row.config());
+        for _ in 1..row.players {
+            state.add_player();
+        }
+        let mut next_input = 0;
+        let mut ticker = tokio::time::interval(Duration::from_secs_f64(state.step_secs()));
+
+        while !state.game_over && state.tick_count < ticks {
+            tokio::select! {
+                msg = msg_stream.next() => {
+                    match msg {
+                        Some(Ok(Message::Ping(bytes))) if session.pong(&bytes).await.is_err() => break,
+                        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                        _ => {}
+                    }
+                }
+                _ = ticker.tick() => {
+                    state.replay_tick(&row.inputs, &mut next_input);
+                    if session.text(serde_json::to_string(&state).unwrap_or_default()).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+        let _ = session.close(None).await;
+    });
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dragonballgame::{DEFAULT_DIFFICULTY, DEFAULT_TICK_RATE};
+
+    fn recorded_run(seed: u64, players: usize) -> GameState {
+        let mut state = GameState::with_seed(seed, DEFAULT_TICK_RATE, GameConfig::default());
+        for _ in 1..players {
+            state.add_player();
+        }
+        while !state.game_over {
+            if state.tick_count == 100 {
+                state.update_player_pos(0, 120.0);
+            }
+            if players > 1 && state.tick_count == 200 {
+                state.update_player_pos(1, 420.0);
+            }
+            state.tick();
+        }
+        state
+    }
+
+    fn record(state: &GameState, seat: usize) -> RunRecord {
+        let defender = Defender { user_id: format!("user-{}", seat), username: format!("player {}", seat), seat };
+        let room_id = (state.players.len() > 1).then_some("room");
+        RunRecord::from_state("run", room_id, &defender, DEFAULT_DIFFICULTY, 0, state)
+    }
+
+    #[test]
+    fn honest_run_verifies() {
+        let state = recorded_run(42, 1);
+        let run = record(&state, 0);
+        assert_eq!(run.score, run.team_score);
+        assert_eq!(run.sim_version, SIM_VERSION);
+        assert!(verify(&run).matches);
+    }
+
+    #[test]
+    fn every_defender_of_a_coop_run_verifies() {
+        let state = recorded_run(42, 2);
+        let runs: Vec<RunRecord> = (0..2).map(|seat| record(&state, seat)).collect();
+        assert_eq!(runs.iter().map(|r| r.score).sum::<u32>(), state.score);
+        for run in &runs {
+            assert!(verify(run).matches);
+        }
+
+        // Claiming a teammate's points
+        let mut swapped = runs[0].clone();
+        swapped.score = runs[1].score + 1;
+        assert!(!verify(&swapped).matches);
+    }
+
+    #[test]
+    fn tampered_runs_are_flagged() {
+        let state = recorded_run(42, 1);
+        let run = record(&state, 0);
+
+        let mut inflated = run.clone();
+        inflated.score += 100;
+        inflated.team_score += 100;
+        let v = verify(&inflated);
+        assert!(!v.matches);
+        assert_eq!(v.simulated_score, state.score);
+
+        // Claiming to have survived longer than the simulation allows
+        let mut longer = run.clone();
+        longer.ticks += 500;
+        longer.game_over_tick = longer.game_over_tick.map(|t| t + 500);
+        assert!(!verify(&longer).matches);
+
+        // Re-simulating under a different preset does not reproduce the run
+        let mut other_config = run.clone();
+        other_config.config = GameConfig::preset("easy").unwrap();
+        assert!(!verify(&other_config).matches);
+    }
+}
@@ -1,17 +1,155 @@
 use serde::{Deserialize, Serialize};
-use rand::Rng; // Kept as it is used
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use std::time::Instant;
 
+/// Version of the simulation's rules and random number generator, stored with every run.
+/// A run only replays under the version it was recorded with; bump this whenever a change alters replays.
+/// Version 1 runs were drawn from `StdRng`, whose algorithm may change between rand releases.
+pub const SIM_VERSION: u32 = 2;
 
+/// Simulation steps per second the server can run at. Gameplay speed is the same at every rate.
+pub const TICK_RATES: [u32; 3] = [30, 60, 120];
+pub const DEFAULT_TICK_RATE: u32 = 60;
+/// Longest stall the simulation catches up on; anything beyond is dropped rather than fast-forwarded
+const MAX_CATCH_UP_SECS: f64 = 0.25;
 
-const CANVAS_WIDTH: f64 = 800.0;
-const CANVAS_HEIGHT: f64 = 600.0;
+/// Arena size, the same as the client's canvas
+pub const CANVAS_WIDTH: f64 = 800.0;
+pub const CANVAS_HEIGHT: f64 = 600.0;
 const PLAYER_X_OFFSET: f64 = 50.0; // Distance from right edge
+const MAX_PLAYER_SPEED: f64 = 1200.0; // Pixels per second; a defender can't jump to where the client points
 const DRAGON_X_OFFSET: f64 = 50.0; // Distance from left edge
-const FIREBALL_SPEED_BASE: f64 = 3.0; // Reduced speed for better playability
-const FIREBALL_RADIUS: f64 = 20.0;
-const WATER_SPRAY_RANGE: f64 = 300.0; // Range of the water spray
-const WATER_SPRAY_ANGLE: f64 = 0.5; // Spread of spray in radians
+const WAVE_SECS: f64 = 20.0; // Length of a regular wave; boss waves last until the boss is down
+const BOSS_EVERY: u32 = 5; // Every fifth wave is a boss wave
+const WAVE_SPAWN_RAMP: f64 = 0.15; // Extra spawn chance per wave, relative to the config's
+const MAX_WAVE_SPAWN_FACTOR: f64 = 3.0;
+const BURST_SPREAD: f64 = 80.0; // Vertical distance between the targets of a burst
+const CURVE_ACCEL: f64 = 90.0; // Vertical acceleration of curving fireballs, px/s²
+const SPLIT_X: f64 = CANVAS_WIDTH / 2.0; // Splitting fireballs break in two here
+const SPLIT_VY: f64 = 70.0; // Vertical speed the halves fly apart with
+const BOSS_HEALTH_PER_LEVEL: f64 = 150.0;
+const BOSS_ADVANCE_SPEED: f64 = 12.0; // Pixels per second towards the red line
+const BOSS_BOB_SPEED: f64 = 80.0;
+const SPRAY_DAMAGE: f64 = 50.0; // Boss health lost per second in the spray
+const BOSS_BONUS: u32 = 5; // A boss is worth this many fireballs per boss level
+const PICKUP_CHANCE_PER_60HZ_TICK: f64 = 0.002;
+const PICKUP_SPEED: f64 = 60.0; // Pickups drift towards the player, pixels per second
+/// Collected power-ups waiting to be used; further pickups are left floating
+pub const MAX_STORED_POWER_UPS: usize = 3;
+const WIDE_SPRAY_FACTOR: f64 = 1.6;
+const LONG_RANGE_FACTOR: f64 = 1.5;
+const SLOW_MOTION_FACTOR: f64 = 0.5; // Fireball and boss speed while slow-motion lasts
+const COMBO_STEP: u32 = 5; // Consecutive extinguishes per extra multiplier point
+const MAX_MULTIPLIER: u32 = 4;
+
+/// Built-in difficulty presets; `normal` is the default and matches the original tuning
+pub const BUILTIN_PRESETS: [&str; 4] = ["easy", "normal", "hard", "insane"];
+pub const DEFAULT_DIFFICULTY: &str = "normal";
+
+/// Gameplay values of a run. Rates are per second so they hold at every tick rate.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct GameConfig {
+    pub fireball_speed_base: f64,      // Pixels per second
+    pub fireball_speed_per_point: f64, // Speed ramp per base point scored; multipliers and bonuses don't count
+    pub spawn_chance: f64,             // Chance of a new fireball per 1/60 s
+    pub extinguish_rate: f64,          // Fade-out per second
+    pub water_spray_range: f64,        // Range of the water spray
+    pub water_spray_angle: f64,        // Spread of spray in radians
+    pub points_per_fireball: u32,
+    #[serde(default = "default_lives")]
+    pub lives: u32,
+    #[serde(default = "default_invulnerable_secs")]
+    pub invulnerable_secs: f64, // Grace period after losing a life
+}
+
+fn default_lives() -> u32 {
+    3
+}
+
+fn default_invulnerable_secs() -> f64 {
+    1.5
+}
+
+impl Default for GameConfig {
+    /// The original tuning of 3 px, 2% and 0.05 per tick at 60 Hz
+    fn default() -> Self {
+        Self {
+            fireball_speed_base: 180.0,
+            fireball_speed_per_point: 6.0,
+            spawn_chance: 0.02,
+            extinguish_rate: 3.0,
+            water_spray_range: 300.0,
+            water_spray_angle: 0.5,
+            points_per_fireball: 10,
+            lives: default_lives(),
+            invulnerable_secs: default_invulnerable_secs(),
+        }
+    }
+}
+
+impl GameConfig {
+    /// A built-in preset by name
+    pub fn preset(name: &str) -> Option<Self> {
+        let normal = Self::default();
+        match name {
+            "easy" => Some(Self {
+                fireball_speed_base: 140.0,
+                fireball_speed_per_point: 4.0,
+                spawn_chance: 0.015,
+                water_spray_range: 360.0,
+                water_spray_angle: 0.6,
+                lives: 5,
+                ..normal
+            }),
+            "normal" => Some(normal),
+            "hard" => Some(Self {
+                fireball_speed_base: 220.0,
+                fireball_speed_per_point: 8.0,
+                spawn_chance: 0.03,
+                water_spray_range: 260.0,
+                water_spray_angle: 0.45,
+                ..normal
+            }),
+            "insane" => Some(Self {
+                fireball_speed_base: 280.0,
+                fireball_speed_per_point: 10.0,
+                spawn_chance: 0.045,
+                water_spray_range: 220.0,
+                water_spray_angle: 0.4,
+                lives: 2,
+                invulnerable_secs: 1.0,
+                ..normal
+            }),
+            _ => None,
+        }
+    }
+
+    /// Rejects values that would make the game unplayable or break the simulation
+    pub fn validate(&self) -> Result<(), String> {
+        let ranges = [
+            ("fireball_speed_base", self.fireball_speed_base, 30.0, 1000.0),
+            ("fireball_speed_per_point", self.fireball_speed_per_point, 0.0, 50.0),
+            ("spawn_chance", self.spawn_chance, 0.001, 0.5),
+            ("extinguish_rate", self.extinguish_rate, 0.5, 20.0),
+            ("water_spray_range", self.water_spray_range, 50.0, CANVAS_WIDTH),
+            ("water_spray_angle", self.water_spray_angle, 0.05, 1.5),
+            ("invulnerable_secs", self.invulnerable_secs, 0.0, 5.0),
+        ];
+        for (field, value, min, max) in ranges {
+            if !(min..=max).contains(&value) {
+                return Err(format!("{} must be between {} and {}", field, min, max));
+            }
+        }
+        if !(1..=100).contains(&self.points_per_fireball) {
+            return Err("points_per_fireball must be between 1 and 100".to_string());
+        }
+        if !(1..=10).contains(&self.lives) {
+            return Err("lives must be between 1 and 10".to_string());
+        }
+        Ok(())
+    }
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub enum FireballState {
@@ -19,20 +157,128 @@ pub enum FireballState {
     Extinguishing,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Default)]
+pub enum FireballKind {
+    #[default]
+    Normal,
+    Curving,   // Bends up or down on its way
+    Splitting, // Breaks in two halfway across
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Fireball {
     pub id: u64,
     pub x: f64,
     pub y: f64,
-    pub vx: f64,
+    pub vx: f64, // Pixels per second
     pub vy: f64,
+    #[serde(default)]
+    pub ay: f64, // Vertical acceleration, px/s²
+    #[serde(default)]
+    pub kind: FireballKind,
     pub state: FireballState,
     pub extinguish_timer: f64, // Normalized 1.0 to 0.0
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// How one spawn roll turns into fireballs. Each wave before the first boss unlocks the next pattern.
+#[derive(Clone, Copy)]
+enum SpawnPattern {
+    Single,
+    Burst, // Three fireballs fanned out
+    Curving,
+    Splitting,
+}
+
+const SPAWN_PATTERNS: [SpawnPattern; 4] = [SpawnPattern::Single, SpawnPattern::Burst, SpawnPattern::Curving, SpawnPattern::Splitting];
+
+/// A big dragon that appears every `BOSS_EVERY` waves. It throws the wave's fireballs, creeps towards
+/// the red line and must be sprayed down before it reaches it.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Boss {
+    pub level: u32, // 1 for the first boss, 2 for the second, ...
+    pub x: f64,
+    pub y: f64,
+    pub vy: f64,
+    pub health: f64,
+    pub max_health: f64,
+}
+
+/// Power-ups the player collects by spraying pickups and activates later
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerUpKind {
+    WideSpray,  // Wider spray angle
+    LongRange,  // Longer spray range
+    SlowMotion, // Fireballs and the boss move at half speed
+    Shield,     // Fireballs crossing the line cost no lives
+    Bomb,       // Extinguishes every fireball on screen at once
+}
+
+pub const ALL_POWER_UPS: [PowerUpKind; 5] =
+    [PowerUpKind::WideSpray, PowerUpKind::LongRange, PowerUpKind::SlowMotion, PowerUpKind::Shield, PowerUpKind::Bomb];
+
+impl PowerUpKind {
+    /// How long the effect lasts once activated; zero for the instant bomb
+    pub fn duration_secs(self) -> f64 {
+        match self {
+            PowerUpKind::WideSpray | PowerUpKind::LongRange => 8.0,
+            PowerUpKind::SlowMotion => 5.0,
+            PowerUpKind::Shield => 6.0,
+            PowerUpKind::Bomb => 0.0,
+        }
+    }
+}
+
+/// A collectible floating towards the player. Spraying it stores its power-up.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Pickup {
+    pub id: u64,
+    pub kind: PowerUpKind,
+    pub x: f64,
+    pub y: f64,
+}
+
+/// An activated power-up and the seconds it has left
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ActiveEffect {
+    pub kind: PowerUpKind,
+    pub remaining: f64,
+}
+
+/// A defender on the red line. Solo runs have one, cooperative rooms up to four.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct Player {
     pub y: f64,
+    #[serde(default)]
+    pub score: u32, // Points this defender earned; the defenders' scores add up to the run's score
+}
+
+/// A `Player.y` update or a power-up activation, applied before tick number `tick` is simulated
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct PlayerInput {
+    pub tick: u64,
+    pub y: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub power_up: Option<PowerUpKind>, // Set for activations, which leave `y` as it was
+    #[serde(default, skip_serializing_if = "is_first_seat")]
+    pub seat: usize, // Index into `players`
+}
+
+fn is_first_seat(seat: &usize) -> bool {
+    *seat == 0
+}
+
+/// Score multiplier after `combo` consecutive extinguishes
+fn multiplier_for(combo: u32) -> u32 {
+    (1 + combo / COMBO_STEP).min(MAX_MULTIPLIER)
+}
+
+fn entropy_rng() -> ChaCha8Rng {
+    ChaCha8Rng::from_entropy()
+}
+
+fn default_tick_rate() -> u32 {
+    DEFAULT_TICK_RATE
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -40,111 +286,765 @@ pub struct GameState {
     pub score: u32,
     pub game_over: bool,
     pub fireballs: Vec<Fireball>,
-    pub player: Player,
+    pub players: Vec<Player>,
+    pub lives: u32,
+    pub invulnerable_for: f64, // Seconds of invulnerability left
+    pub wave: u32,
+    pub boss: Option<Boss>,
+    pub pickups: Vec<Pickup>,
+    pub power_ups: Vec<PowerUpKind>, // Collected, not yet used
+    pub effects: Vec<ActiveEffect>,
+    pub combo: u32, // Extinguishes since a fireball last crossed the line
+    pub multiplier: u32,
+    #[serde(skip)]
+    wave_started_tick: u64,
+    #[serde(skip)]
+    pickup_id_counter: u64,
+    #[serde(skip)]
+    base_points: u32, // Score without multipliers and boss bonuses, drives the speed ramp
+    #[serde(skip)]
+    targets: Vec<Option<f64>>, // Where each defender is heading, None once it got there
+    #[serde(skip)]
+    pub extinguished: Vec<u32>, // Fireballs each defender put out, by spray or bomb
     #[serde(skip)]
     pub last_update: Option<Instant>,
     #[serde(skip)]
+    accumulator: f64, // Real time not yet simulated, in seconds
+    #[serde(skip, default = "default_tick_rate")]
+    pub tick_rate: u32,
+    #[serde(skip)]
+    pub config: GameConfig,
+    #[serde(skip)]
     pub fireball_id_counter: u64,
+    // The seed and the input log are all that is needed to reproduce a run; neither is sent to the client
+    #[serde(skip)]
+    pub seed: u64,
+    #[serde(skip, default = "entropy_rng")]
+    rng: ChaCha8Rng, // Portable: the same seed gives the same stream on every platform and rand release
+    #[serde(skip)]
+    pub tick_count: u64,
+    #[serde(skip)]
+    pub inputs: Vec<PlayerInput>,
+    #[serde(skip)]
+    pub game_over_tick: Option<u64>,
 }
 
 impl GameState {
-    pub fn new() -> Self {
+    /// New run with a random seed, simulated at `tick_rate` steps per second
+    pub fn new(tick_rate: u32, config: GameConfig) -> Self {
+        Self::with_seed(rand::random(), tick_rate, config)
+    }
+
+    /// New run whose spawns and trajectories are fully determined by `seed`, the tick rate, the config and the player's inputs
+    pub fn with_seed(seed: u64, tick_rate: u32, config: GameConfig) -> Self {
         Self {
             score: 0,
             game_over: false,
             fireballs: Vec::new(),
-            player: Player { y: CANVAS_HEIGHT / 2.0 },
+            players: vec![Player { y: CANVAS_HEIGHT / 2.0, score: 0 }],
+            lives: config.lives,
+            invulnerable_for: 0.0,
+            wave: 1,
+            boss: None,
+            pickups: Vec::new(),
+            power_ups: Vec::new(),
+            effects: Vec::new(),
+            combo: 0,
+            multiplier: 1,
+            wave_started_tick: 0,
+            pickup_id_counter: 0,
+            base_points: 0,
+            targets: vec![None],
+            extinguished: vec![0],
             last_update: None,
+            accumulator: 0.0,
+            tick_rate,
+            config,
             fireball_id_counter: 0,
+            seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            tick_count: 0,
+            inputs: Vec::new(),
+            game_over_tick: None,
+        }
+    }
+
+    /// Adds another defender and returns its seat. Rooms add everyone before the first tick.
+    pub fn add_player(&mut self) -> usize {
+        self.players.push(Player { y: CANVAS_HEIGHT / 2.0, score: 0 });
+        self.targets.push(None);
+        self.extinguished.push(0);
+        self.players.len() - 1
+    }
+
+    /// Re-simulates a run of `players` defenders from its seed, tick rate, config and input log,
+    /// stopping at game over or after `max_ticks`
+    pub fn replay(seed: u64, tick_rate: u32, config: GameConfig, players: usize, inputs: &[PlayerInput], max_ticks: u64) -> Self {
+        let mut state = Self::with_seed(seed, tick_rate, config);
+        while state.players.len() < players {
+            state.add_player();
+        }
+        let mut next_input = 0;
+        while !state.game_over && state.tick_count < max_ticks {
+            state.replay_tick(inputs, &mut next_input);
+        }
+        state
+    }
+
+    /// Applies the recorded inputs due before the coming tick, then simulates it.
+    /// `next_input` is the position in `inputs` and advances past the applied ones.
+    pub fn replay_tick(&mut self, inputs: &[PlayerInput], next_input: &mut usize) {
+        while let Some(input) = inputs.get(*next_input).filter(|i| i.tick <= self.tick_count) {
+            match input.power_up {
+                Some(kind) => {
+                    self.use_power_up(input.seat, kind);
+                }
+                None => self.update_player_pos(input.seat, input.y),
+            }
+            *next_input += 1;
+        }
+        self.tick();
+    }
+
+    /// Sends the defender in `seat` towards `y`. It gets there at `MAX_PLAYER_SPEED` over the coming ticks.
+    pub fn update_player_pos(&mut self, seat: usize, y: f64) {
+        if self.game_over || !y.is_finite() {
+            return;
+        }
+        let Some(player) = self.players.get(seat) else { return };
+        let y = y.clamp(0.0, CANVAS_HEIGHT);
+        if y != self.targets[seat].unwrap_or(player.y) {
+            self.targets[seat] = Some(y);
+            self.inputs.push(PlayerInput { tick: self.tick_count, y, power_up: None, seat });
+        }
+    }
+
+    /// Activates a stored power-up for the defender in `seat`; the power-ups are shared.
+    /// Returns false if none of that kind is stored.
+    pub fn use_power_up(&mut self, seat: usize, kind: PowerUpKind) -> bool {
+        if self.game_over || seat >= self.players.len() {
+            return false;
+        }
+        let Some(index) = self.power_ups.iter().position(|k| *k == kind) else { return false };
+        self.power_ups.remove(index);
+        self.inputs.push(PlayerInput { tick: self.tick_count, y: self.players[seat].y, power_up: Some(kind), seat });
+
+        if kind == PowerUpKind::Bomb {
+            for fireball in self.fireballs.iter_mut().filter(|f| f.state == FireballState::Active) {
+                fireball.state = FireballState::Extinguishing;
+                self.score += self.config.points_per_fireball;
+                self.base_points += self.config.points_per_fireball;
+                self.players[seat].score += self.config.points_per_fireball;
+                self.extinguished[seat] += 1;
+            }
+            return true;
+        }
+        // Activating a running effect restarts its timer
+        self.effects.retain(|e| e.kind != kind);
+        self.effects.push(ActiveEffect { kind, remaining: kind.duration_secs() });
+        true
+    }
+
+    fn effect_active(&self, kind: PowerUpKind) -> bool {
+        self.effects.iter().any(|e| e.kind == kind)
+    }
+
+    /// Spray range and angle with active power-ups applied
+    fn spray(&self) -> (f64, f64) {
+        let mut range = self.config.water_spray_range;
+        let mut angle = self.config.water_spray_angle;
+        if self.effect_active(PowerUpKind::LongRange) {
+            range *= LONG_RANGE_FACTOR;
         }
+        if self.effect_active(PowerUpKind::WideSpray) {
+            angle *= WIDE_SPRAY_FACTOR;
+        }
+        (range, angle)
+    }
+
+    /// Length of one simulation step in seconds
+    pub fn step_secs(&self) -> f64 {
+        1.0 / self.tick_rate as f64
     }
 
-    pub fn update_player_pos(&mut self, y: f64) {
-        if !self.game_over {
-            self.player.y = y.clamp(0.0, CANVAS_HEIGHT);
+    /// Simulates as many fixed steps as the real time since the last call covers and returns how many ran.
+    /// Jitter in the caller's timer changes the number of steps per call, never the gameplay speed.
+    pub fn advance(&mut self, now: Instant) -> u32 {
+        let elapsed = self.last_update.map_or(0.0, |last| now.duration_since(last).as_secs_f64());
+        self.last_update = Some(now);
+        self.accumulator = (self.accumulator + elapsed).min(MAX_CATCH_UP_SECS);
+
+        let dt = self.step_secs();
+        let mut steps = 0;
+        while self.accumulator >= dt && !self.game_over {
+            self.tick();
+            self.accumulator -= dt;
+            steps += 1;
         }
+        steps
     }
 
+    /// One fixed simulation step of `step_secs()`
     pub fn tick(&mut self) {
         if self.game_over {
             return;
         }
 
-        // Assume 60 FPS tick rate roughly, or use delta time if we tracked it strictly per tick
-        // For simplicity in this loop, we'll just move by velocity
-        
-        // 1. Spawn Fireballs (Random chance)
-        let mut rng = rand::thread_rng();
-        if rng.gen_bool(0.02) { // 2% chance per tick to spawn
-            self.fireball_id_counter += 1;
-            let target_y = rng.gen_range(50.0..CANVAS_HEIGHT-50.0);
-            let start_y = rng.gen_range(100.0..CANVAS_HEIGHT-100.0);
-            
-            // Calculate velocity vector towards a random point on the right side
-            let dx = CANVAS_WIDTH - DRAGON_X_OFFSET;
-            let dy = target_y - start_y;
-            let distance = (dx*dx + dy*dy).sqrt();
-            
-            let speed = FIREBALL_SPEED_BASE + (self.score as f64 * 0.1); // Increase difficulty
-            
-            self.fireballs.push(Fireball {
-                id: self.fireball_id_counter,
-                x: DRAGON_X_OFFSET,
-                y: start_y,
-                vx: (dx / distance) * speed,
-                vy: (dy / distance) * speed,
-                state: FireballState::Active,
-                extinguish_timer: 1.0,
-            });
-        }
-
-        // 2. Update Fireballs & Check Collisions
-        let mut game_over_triggered = false;
+        let dt = self.step_secs();
+        // Step length relative to the 60 Hz the game was tuned at; exactly 1.0 at 60 Hz
+        let scale = dt * 60.0;
+        self.tick_count += 1;
+        self.invulnerable_for = (self.invulnerable_for - dt).max(0.0);
+        for effect in &mut self.effects {
+            effect.remaining -= dt;
+        }
+        self.effects.retain(|e| e.remaining > 0.0);
+
+        // Defenders move towards their targets
+        let max_step = MAX_PLAYER_SPEED * dt;
+        for (player, target) in self.players.iter_mut().zip(&mut self.targets) {
+            let Some(y) = *target else { continue };
+            if (y - player.y).abs() <= max_step {
+                player.y = y;
+                *target = None;
+            } else {
+                player.y += max_step.copysign(y - player.y);
+            }
+        }
+
+        // 1. Waves (regular waves are timed, boss waves end when the boss goes down)
+        let wave_ticks = (WAVE_SECS * self.tick_rate as f64) as u64;
+        if self.boss.is_none() && self.tick_count - self.wave_started_tick >= wave_ticks {
+            self.start_wave(self.wave + 1);
+        }
+
+        // 2. Spawn Fireballs (Random chance, drawn from the run's own seeded RNG)
+        let wave_factor = (1.0 + WAVE_SPAWN_RAMP * (self.wave - 1) as f64).min(MAX_WAVE_SPAWN_FACTOR);
+        let spawn_chance = 1.0 - (1.0 - (self.config.spawn_chance * wave_factor).min(1.0)).powf(scale);
+        if self.rng.gen_bool(spawn_chance) {
+            let pattern = if self.wave == 1 {
+                SpawnPattern::Single
+            } else {
+                let unlocked = &SPAWN_PATTERNS[..(self.wave as usize).min(SPAWN_PATTERNS.len())];
+                unlocked[self.rng.gen_range(0..unlocked.len())]
+            };
+            let target_y = self.rng.gen_range(50.0..CANVAS_HEIGHT-50.0);
+            // A boss throws from where it is, otherwise the dragon at the left edge
+            let (start_x, start_y) = match &self.boss {
+                Some(boss) => (boss.x, boss.y),
+                None => (DRAGON_X_OFFSET, self.rng.gen_range(100.0..CANVAS_HEIGHT-100.0)),
+            };
+
+            match pattern {
+                SpawnPattern::Single => self.spawn_fireball(start_x, start_y, target_y, FireballKind::Normal, 0.0),
+                SpawnPattern::Burst => {
+                    for offset in [-BURST_SPREAD, 0.0, BURST_SPREAD] {
+                        let target_y = (target_y + offset).clamp(0.0, CANVAS_HEIGHT);
+                        self.spawn_fireball(start_x, start_y, target_y, FireballKind::Normal, 0.0);
+                    }
+                }
+                SpawnPattern::Curving => {
+                    let ay = if self.rng.gen_bool(0.5) { CURVE_ACCEL } else { -CURVE_ACCEL };
+                    self.spawn_fireball(start_x, start_y, target_y, FireballKind::Curving, ay);
+                }
+                SpawnPattern::Splitting => self.spawn_fireball(start_x, start_y, target_y, FireballKind::Splitting, 0.0),
+            }
+        }
+
+        // 3. Spawn Pickups
+        let pickup_chance = 1.0 - (1.0 - PICKUP_CHANCE_PER_60HZ_TICK).powf(scale);
+        if self.rng.gen_bool(pickup_chance) {
+            self.pickup_id_counter += 1;
+            let kind = ALL_POWER_UPS[self.rng.gen_range(0..ALL_POWER_UPS.len())];
+            let y = self.rng.gen_range(100.0..CANVAS_HEIGHT-100.0);
+            self.pickups.push(Pickup { id: self.pickup_id_counter, kind, x: DRAGON_X_OFFSET, y });
+        }
+
+        // 4. Update Fireballs & Check Collisions
+        let (spray_range, spray_angle) = self.spray();
+        let shielded = self.effect_active(PowerUpKind::Shield);
+        let speed = if self.effect_active(PowerUpKind::SlowMotion) { SLOW_MOTION_FACTOR } else { 1.0 };
+        let mut hits = 0;
+        let mut halves = Vec::new();
+        let config = &self.config;
 
         for fireball in &mut self.fireballs {
             if fireball.state == FireballState::Extinguishing {
-                fireball.extinguish_timer -= 0.05; // Fade out speed
+                fireball.extinguish_timer -= config.extinguish_rate * dt; // Fade out speed
                 continue; // Skip movement/collision for extinguishing fireballs
             }
 
-            fireball.x += fireball.vx;
-            fireball.y += fireball.vy;
+            let move_dt = dt * speed;
+            fireball.vy += fireball.ay * move_dt;
+            fireball.x += fireball.vx * move_dt;
+            fireball.y += fireball.vy * move_dt;
+
+            // Splitting fireballs carry on as two plain ones flying apart
+            if fireball.kind == FireballKind::Splitting && fireball.x >= SPLIT_X {
+                fireball.kind = FireballKind::Normal;
+                let mut other = fireball.clone();
+                fireball.vy -= SPLIT_VY;
+                other.vy += SPLIT_VY;
+                halves.push(other);
+            }
 
-            // Check Collision with Red Line (Right side)
+            // Check Collision with Red Line (Right side); the fireball is used up
             if fireball.x > CANVAS_WIDTH - PLAYER_X_OFFSET {
-                game_over_triggered = true;
-            }
-
-            // Check Collision with Water Spray
-            // Simple logic: If fireball is within range and angle of player
-            let dx = fireball.x - (CANVAS_WIDTH - PLAYER_X_OFFSET);
-            let dy = fireball.y - self.player.y;
-            let dist = (dx*dx + dy*dy).sqrt();
-            
-            // In our game, player shoots LEFT. 
-            // So valid spray area is to the LEFT of the player.
-            // dx should be negative.
-            
-            if dist < WATER_SPRAY_RANGE && dx < 0.0 {
-                 // Check angle. 
-                 // At (dx, dy), atan2(dy, dx) gives angle. 
-                 // Player aims directly LEFT (Pi or -Pi).
-                 // We check if angle is close to Pi.
-                 let angle = dy.atan2(dx).abs(); 
-                 // Target angle is PI (180 deg). We accept PI +/- spray_angle
-                 if (std::f64::consts::PI - angle).abs() < WATER_SPRAY_ANGLE {
-                     // Extinguished!
-                     fireball.state = FireballState::Extinguishing;
-                     self.score += 10;
-                 }
-            }
-        }
-        
-        if game_over_triggered {
+                hits += 1;
+                fireball.state = FireballState::Extinguishing;
+                fireball.extinguish_timer = 0.0;
+                continue;
+            }
+
+            // Check Collision with Water Spray; the first defender covering it gets the points
+            if let Some(seat) = self.players.iter().position(|p| in_spray(spray_range, spray_angle, p.y, fireball.x, fireball.y)) {
+                // Extinguished! Consecutive ones raise the multiplier
+                fireball.state = FireballState::Extinguishing;
+                self.combo += 1;
+                self.multiplier = multiplier_for(self.combo);
+                let points = config.points_per_fireball * self.multiplier;
+                self.score += points;
+                self.players[seat].score += points;
+                self.extinguished[seat] += 1;
+                self.base_points += config.points_per_fireball;
+            }
+        }
+
+        for mut half in halves {
+            self.fireball_id_counter += 1;
+            half.id = self.fireball_id_counter;
+            self.fireballs.push(half);
+        }
+
+        // Fireballs reaching the line together, or during invulnerability, cost one life at most.
+        // Any of them, shielded or not, breaks the combo.
+        if hits > 0 {
+            self.combo = 0;
+            self.multiplier = 1;
+            if self.invulnerable_for <= 0.0 && !shielded {
+                self.lives = self.lives.saturating_sub(1);
+                self.invulnerable_for = self.config.invulnerable_secs;
+            }
+        }
+
+        // 5. Move Pickups; spraying one stores it if there is room, the line loses it
+        let players = &self.players;
+        let mut collected = Vec::new();
+        self.pickups.retain_mut(|pickup| {
+            pickup.x += PICKUP_SPEED * dt;
+            if players.iter().any(|p| in_spray(spray_range, spray_angle, p.y, pickup.x, pickup.y))
+                && self.power_ups.len() + collected.len() < MAX_STORED_POWER_UPS
+            {
+                collected.push(pickup.kind);
+                return false;
+            }
+            pickup.x <= CANVAS_WIDTH - PLAYER_X_OFFSET
+        });
+        self.power_ups.extend(collected);
+
+        // 6. Boss
+        let mut boss_defeated = None;
+        if let Some(boss) = &mut self.boss {
+            boss.x += BOSS_ADVANCE_SPEED * dt * speed;
+            boss.y += boss.vy * dt * speed;
+            if !(100.0..=CANVAS_HEIGHT - 100.0).contains(&boss.y) {
+                boss.y = boss.y.clamp(100.0, CANVAS_HEIGHT - 100.0);
+                boss.vy = -boss.vy;
+            }
+            // Every defender spraying the boss wears it down; the one finishing it gets the bonus
+            for (seat, player) in self.players.iter().enumerate() {
+                if boss.health > 0.0 && in_spray(spray_range, spray_angle, player.y, boss.x, boss.y) {
+                    boss.health -= SPRAY_DAMAGE * dt;
+                    if boss.health <= 0.0 {
+                        boss_defeated = Some((boss.level, seat));
+                    }
+                }
+            }
+
+            if boss_defeated.is_none() && boss.x > CANVAS_WIDTH - PLAYER_X_OFFSET {
+                self.lives = 0; // No life survives a boss breaking through
+            }
+        }
+        if let Some((level, seat)) = boss_defeated {
+            let bonus = self.config.points_per_fireball * BOSS_BONUS * level;
+            self.boss = None;
+            self.score += bonus;
+            self.players[seat].score += bonus;
+            self.start_wave(self.wave + 1);
+        }
+
+        if self.lives == 0 {
             self.game_over = true;
+            self.game_over_tick = Some(self.tick_count);
         }
 
         // Remove fireballs that are done extinguishing
         self.fireballs.retain(|f| f.state == FireballState::Active || f.extinguish_timer > 0.0);
     }
+
+    /// Bosses beaten so far. A boss wave only ends once its boss is down.
+    pub fn bosses_defeated(&self) -> u32 {
+        (self.wave - 1) / BOSS_EVERY
+    }
+
+    /// Starts wave number `wave`, bringing in a boss on every `BOSS_EVERY`th
+    fn start_wave(&mut self, wave: u32) {
+        self.wave = wave;
+        self.wave_started_tick = self.tick_count;
+        if wave.is_multiple_of(BOSS_EVERY) {
+            let level = wave / BOSS_EVERY;
+            let health = BOSS_HEALTH_PER_LEVEL * level as f64;
+            self.boss = Some(Boss { level, x: DRAGON_X_OFFSET, y: CANVAS_HEIGHT / 2.0, vy: BOSS_BOB_SPEED, health, max_health: health });
+        }
+    }
+
+    /// Adds a fireball flying from (`x`, `y`) towards `target_y` on the right edge
+    fn spawn_fireball(&mut self, x: f64, y: f64, target_y: f64, kind: FireballKind, ay: f64) {
+        self.fireball_id_counter += 1;
+
+        // Calculate velocity vector towards the target on the right side
+        let dx = CANVAS_WIDTH - x;
+        let dy = target_y - y;
+        let distance = (dx*dx + dy*dy).sqrt();
+
+        let speed = self.config.fireball_speed_base + (self.base_points as f64 * self.config.fireball_speed_per_point); // Increase difficulty
+
+        self.fireballs.push(Fireball {
+            id: self.fireball_id_counter,
+            x,
+            y,
+            vx: (dx / distance) * speed,
+            vy: (dy / distance) * speed,
+            ay,
+            kind,
+            state: FireballState::Active,
+            extinguish_timer: 1.0,
+        });
+    }
+}
+
+/// Whether a point is inside the player's water spray.
+/// The player shoots LEFT, so the spray covers points to the left of the player (negative dx)
+/// within range whose angle is close to PI.
+fn in_spray(range: f64, spray_angle: f64, player_y: f64, x: f64, y: f64) -> bool {
+    let dx = x - (CANVAS_WIDTH - PLAYER_X_OFFSET);
+    let dy = y - player_y;
+    let dist = (dx*dx + dy*dy).sqrt();
+    if dist >= range || dx >= 0.0 {
+        return false;
+    }
+    // atan2 gives the angle of (dx, dy); we accept PI +/- spray_angle
+    let angle = dy.atan2(dx).abs();
+    (std::f64::consts::PI - angle).abs() < spray_angle
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const MAX_TICKS: u64 = 100_000;
+
+    /// Plays a run live, moving the player towards the boss or the nearest active fireball twelve times a second
+    /// and using each power-up right away
+    fn play_tracking(seed: u64, tick_rate: u32) -> GameState {
+        play_tracking_with(seed, tick_rate, GameConfig::default())
+    }
+
+    fn play_tracking_with(seed: u64, tick_rate: u32, config: GameConfig) -> GameState {
+        let mut state = GameState::with_seed(seed, tick_rate, config);
+        let every = (tick_rate / 12) as u64;
+        while !state.game_over && state.tick_count < MAX_TICKS {
+            if state.tick_count.is_multiple_of(every) {
+                let target = state.boss.as_ref().map(|b| b.y).or_else(|| state.fireballs.iter()
+                    .filter(|f| f.state == FireballState::Active)
+                    .max_by(|a, b| a.x.total_cmp(&b.x))
+                    .map(|f| f.y));
+                if let Some(y) = target {
+                    state.update_player_pos(0, y);
+                }
+                // Power-ups are used as soon as they are collected
+                if let Some(&kind) = state.power_ups.first() {
+                    state.use_power_up(0, kind);
+                }
+            }
+            state.tick();
+        }
+        state
+    }
+
+    fn with_fireball(tick_rate: u32) -> GameState {
+        let mut state = GameState::with_seed(0, tick_rate, GameConfig::default());
+        state.fireballs.push(Fireball {
+            id: 1,
+            x: DRAGON_X_OFFSET,
+            y: 100.0,
+            vx: GameConfig::default().fireball_speed_base,
+            vy: 0.0,
+            ay: 0.0,
+            kind: FireballKind::Normal,
+            state: FireballState::Active,
+            extinguish_timer: 1.0,
+        });
+        state.players[0].y = CANVAS_HEIGHT; // Out of spray range
+        state
+    }
+
+    #[test]
+    fn same_seed_and_inputs_replay_identically() {
+        for tick_rate in TICK_RATES {
+            for seed in [1, 42, 2024] {
+                let live = play_tracking(seed, tick_rate);
+                let replayed = GameState::replay(live.seed, tick_rate, GameConfig::default(), 1, &live.inputs, MAX_TICKS);
+                assert_eq!(replayed.score, live.score);
+                assert_eq!(replayed.game_over_tick, live.game_over_tick);
+                assert_eq!(replayed.inputs, live.inputs);
+            }
+        }
+    }
+
+    #[test]
+    fn golden_idle_runs() {
+        for (seed, score, game_over_tick, wave) in [(1, 530, 1487, 2), (42, 840, 1776, 2), (2024, 230, 1051, 1)] {
+            let state = GameState::replay(seed, DEFAULT_TICK_RATE, GameConfig::default(), 1, &[], MAX_TICKS);
+            assert_eq!((state.score, state.game_over_tick, state.wave), (score, Some(game_over_tick), wave), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn golden_tracking_runs() {
+        for (seed, score, game_over_tick, wave) in [(1, 1880, 2120, 2), (42, 2480, 2969, 3), (2024, 2570, 3160, 3)] {
+            let state = play_tracking(seed, DEFAULT_TICK_RATE);
+            assert_eq!((state.score, state.game_over_tick, state.wave), (score, Some(game_over_tick), wave), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn different_seeds_diverge() {
+        let a = GameState::replay(1, DEFAULT_TICK_RATE, GameConfig::default(), 1, &[], MAX_TICKS);
+        let b = GameState::replay(2, DEFAULT_TICK_RATE, GameConfig::default(), 1, &[], MAX_TICKS);
+        assert_ne!((a.score, a.game_over_tick), (b.score, b.game_over_tick));
+    }
+
+    #[test]
+    fn fireballs_cover_the_same_distance_at_every_tick_rate() {
+        let distances: Vec<f64> = TICK_RATES.iter().map(|&tick_rate| {
+            let mut state = with_fireball(tick_rate);
+            for _ in 0..tick_rate {
+                state.tick(); // One second
+            }
+            state.fireballs.iter().find(|f| f.id == 1).unwrap().x - DRAGON_X_OFFSET
+        }).collect();
+        for distance in distances {
+            assert!((distance - GameConfig::default().fireball_speed_base).abs() < 1e-6, "moved {}", distance);
+        }
+    }
+
+    #[test]
+    fn timer_jitter_does_not_change_game_speed() {
+        let start = Instant::now();
+        let mut state = with_fireball(DEFAULT_TICK_RATE);
+        state.advance(start);
+        // One second delivered in uneven slices
+        let mut now = start;
+        let mut steps = 0;
+        for ms in [5, 31, 16, 2, 48, 16, 16, 100, 9, 7].iter().cycle().take(40) {
+            now += Duration::from_millis(*ms);
+            steps += state.advance(now);
+        }
+        assert_eq!(now - start, Duration::from_secs(1));
+        assert!((59..=60).contains(&steps), "ran {} steps", steps);
+    }
+
+    #[test]
+    fn builtin_presets_are_valid() {
+        for name in BUILTIN_PRESETS {
+            let config = GameConfig::preset(name).unwrap();
+            assert_eq!(config.validate(), Ok(()), "{}", name);
+        }
+        assert_eq!(GameConfig::preset(DEFAULT_DIFFICULTY), Some(GameConfig::default()));
+        assert_eq!(GameConfig::preset("custom"), None);
+
+        let broken = GameConfig { spawn_chance: f64::NAN, ..GameConfig::default() };
+        assert!(broken.validate().is_err());
+    }
+
+    #[test]
+    fn harder_presets_end_runs_sooner() {
+        let survived = |name: &str| -> u64 {
+            [1, 42, 2024, 7, 99].iter()
+                .map(|&seed| GameState::replay(seed, DEFAULT_TICK_RATE, GameConfig::preset(name).unwrap(), 1, &[], MAX_TICKS).tick_count)
+                .sum()
+        };
+        let ticks: Vec<u64> = BUILTIN_PRESETS.iter().map(|name| survived(name)).collect();
+        assert!(ticks.windows(2).all(|w| w[0] > w[1]), "{:?}", ticks);
+    }
+
+    #[test]
+    fn losing_a_life_grants_invulnerability() {
+        let mut state = with_fireball(DEFAULT_TICK_RATE);
+        state.fireballs[0].x = CANVAS_WIDTH - PLAYER_X_OFFSET - 1.0;
+        let mut second = state.fireballs[0].clone();
+        second.id = 2;
+        second.x -= 10.0; // Crosses a few ticks later
+        state.fireballs.push(second);
+
+        state.tick();
+        assert_eq!(state.lives, 2);
+        assert!(state.invulnerable_for > 0.0);
+        for _ in 0..10 {
+            state.tick();
+        }
+        assert_eq!(state.lives, 2, "second crossing fell into the grace period");
+        assert!(!state.game_over);
+        assert!(state.fireballs.iter().all(|f| f.x <= CANVAS_WIDTH - PLAYER_X_OFFSET), "crossed fireballs are used up");
+    }
+
+    #[test]
+    fn game_ends_when_the_last_life_is_lost() {
+        let config = GameConfig { lives: 1, ..GameConfig::default() };
+        let mut state = GameState::with_seed(0, DEFAULT_TICK_RATE, config);
+        state.spawn_fireball(CANVAS_WIDTH - PLAYER_X_OFFSET - 1.0, 100.0, 100.0, FireballKind::Normal, 0.0);
+        state.players[0].y = CANVAS_HEIGHT;
+        state.tick();
+        assert_eq!((state.lives, state.game_over, state.game_over_tick), (0, true, Some(1)));
+    }
+
+    #[test]
+    fn splitting_fireballs_break_in_two() {
+        let mut state = GameState::with_seed(0, DEFAULT_TICK_RATE, GameConfig { spawn_chance: 0.001, ..GameConfig::default() });
+        state.spawn_fireball(SPLIT_X - 1.0, 300.0, 300.0, FireballKind::Splitting, 0.0);
+        state.players[0].y = 0.0; // Out of spray range
+        state.tick();
+        let halves: Vec<&Fireball> = state.fireballs.iter().filter(|f| f.x >= SPLIT_X).collect();
+        assert_eq!(halves.len(), 2);
+        assert!(halves.iter().all(|f| f.kind == FireballKind::Normal));
+        assert!(halves[0].vy < 0.0 && halves[1].vy > 0.0);
+    }
+
+    #[test]
+    fn bosses_arrive_every_fifth_wave_and_can_be_sprayed_down() {
+        let mut state = GameState::with_seed(0, DEFAULT_TICK_RATE, GameConfig::default());
+        state.start_wave(BOSS_EVERY - 1);
+        assert!(state.boss.is_none());
+        state.start_wave(BOSS_EVERY);
+        let boss = state.boss.clone().unwrap();
+        assert_eq!((boss.level, boss.health), (1, BOSS_HEALTH_PER_LEVEL));
+
+        // A boss wave does not time out
+        let wave_ticks = (WAVE_SECS * DEFAULT_TICK_RATE as f64) as u64;
+        state.lives = 100;
+        for _ in 0..wave_ticks + 1 {
+            state.tick();
+        }
+        assert_eq!(state.wave, BOSS_EVERY);
+
+        // Hold the spray on it until it goes down
+        let score = state.score;
+        let boss = state.boss.as_mut().unwrap();
+        boss.x = CANVAS_WIDTH - PLAYER_X_OFFSET - 100.0;
+        boss.vy = 0.0;
+        state.players[0].y = boss.y;
+        let ticks = (BOSS_HEALTH_PER_LEVEL / SPRAY_DAMAGE * DEFAULT_TICK_RATE as f64) as u64 + 1;
+        for _ in 0..ticks {
+            state.tick();
+        }
+        assert!(state.boss.is_none());
+        assert_eq!(state.wave, BOSS_EVERY + 1);
+        assert!(state.score >= score + GameConfig::default().points_per_fireball * BOSS_BONUS);
+    }
+
+    #[test]
+    fn a_steady_player_beats_the_first_boss_on_easy() {
+        let state = play_tracking_with(10, DEFAULT_TICK_RATE, GameConfig::preset("easy").unwrap());
+        assert!(state.wave > BOSS_EVERY, "reached wave {}", state.wave);
+    }
+
+    #[test]
+    fn sprayed_pickups_are_stored_until_used() {
+        let mut state = GameState::with_seed(0, DEFAULT_TICK_RATE, GameConfig::default());
+        state.players[0].y = 300.0;
+        for (id, kind) in [(1, PowerUpKind::Bomb), (2, PowerUpKind::Shield), (3, PowerUpKind::WideSpray), (4, PowerUpKind::LongRange)] {
+            state.pickups.push(Pickup { id, kind, x: CANVAS_WIDTH - PLAYER_X_OFFSET - 100.0, y: 300.0 });
+        }
+        state.tick();
+        assert_eq!(state.power_ups, vec![PowerUpKind::Bomb, PowerUpKind::Shield, PowerUpKind::WideSpray]);
+        assert_eq!(state.pickups.len(), 1, "no room for the fourth");
+
+        assert!(!state.use_power_up(0, PowerUpKind::SlowMotion));
+        assert!(state.use_power_up(0, PowerUpKind::Shield));
+        assert_eq!(state.effects, vec![ActiveEffect { kind: PowerUpKind::Shield, remaining: PowerUpKind::Shield.duration_secs() }]);
+        assert_eq!(state.inputs.last().and_then(|i| i.power_up), Some(PowerUpKind::Shield));
+    }
+
+    #[test]
+    fn bomb_clears_every_active_fireball() {
+        let mut state = with_fireball(DEFAULT_TICK_RATE);
+        state.spawn_fireball(DRAGON_X_OFFSET, 500.0, 500.0, FireballKind::Curving, CURVE_ACCEL);
+        state.power_ups.push(PowerUpKind::Bomb);
+        assert!(state.use_power_up(0, PowerUpKind::Bomb));
+        assert!(state.fireballs.iter().all(|f| f.state == FireballState::Extinguishing));
+        assert_eq!(state.score, 2 * GameConfig::default().points_per_fireball);
+        assert!(state.power_ups.is_empty());
+    }
+
+    #[test]
+    fn shield_keeps_lives_but_breaks_the_combo() {
+        let mut state = with_fireball(DEFAULT_TICK_RATE);
+        state.fireballs[0].x = CANVAS_WIDTH - PLAYER_X_OFFSET - 1.0;
+        state.combo = 7;
+        state.multiplier = multiplier_for(7);
+        state.power_ups.push(PowerUpKind::Shield);
+        state.use_power_up(0, PowerUpKind::Shield);
+        state.tick();
+        assert_eq!((state.lives, state.combo, state.multiplier), (GameConfig::default().lives, 0, 1));
+    }
+
+    #[test]
+    fn consecutive_extinguishes_raise_the_multiplier() {
+        let points = GameConfig::default().points_per_fireball;
+        let mut state = GameState::with_seed(0, DEFAULT_TICK_RATE, GameConfig::default());
+        state.players[0].y = 300.0;
+        let mut expected = 0;
+        for combo in 1..=25 {
+            state.spawn_fireball(CANVAS_WIDTH - PLAYER_X_OFFSET - 100.0, 300.0, 300.0, FireballKind::Normal, 0.0);
+            state.tick();
+            expected += points * multiplier_for(combo);
+        }
+        assert_eq!(state.combo, 25);
+        assert_eq!(state.multiplier, MAX_MULTIPLIER);
+        assert_eq!(state.score, expected);
+        assert_eq!(multiplier_for(COMBO_STEP - 1), 1);
+        assert_eq!(multiplier_for(COMBO_STEP), 2);
+    }
+
+    #[test]
+    fn defenders_move_no_faster_than_the_speed_limit() {
+        let mut state = GameState::with_seed(0, DEFAULT_TICK_RATE, GameConfig::default());
+        state.update_player_pos(0, 0.0);
+        state.tick();
+        let step = MAX_PLAYER_SPEED / DEFAULT_TICK_RATE as f64;
+        assert_eq!(state.players[0].y, CANVAS_HEIGHT / 2.0 - step);
+
+        // The target is reached and held; replays move the same way
+        for _ in 0..DEFAULT_TICK_RATE {
+            state.tick();
+        }
+        assert_eq!(state.players[0].y, 0.0);
+        let replayed = GameState::replay(0, DEFAULT_TICK_RATE, GameConfig::default(), 1, &state.inputs, 2);
+        assert_eq!(replayed.players[0].y, CANVAS_HEIGHT / 2.0 - 2.0 * step);
+    }
+
+    #[test]
+    fn defenders_score_the_fireballs_they_spray() {
+        let points = GameConfig::default().points_per_fireball;
+        let mut state = GameState::with_seed(0, DEFAULT_TICK_RATE, GameConfig::default());
+        assert_eq!(state.add_player(), 1);
+        state.players[0].y = 100.0;
+        state.players[1].y = 500.0;
+        state.spawn_fireball(CANVAS_WIDTH - PLAYER_X_OFFSET - 100.0, 500.0, 300.0, FireballKind::Normal, 0.0);
+        state.tick();
+        assert_eq!((state.players[0].score, state.players[1].score, state.score), (0, points, points));
+        assert_eq!(state.extinguished, vec![0, 1]);
+    }
 }
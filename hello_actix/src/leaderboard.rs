@@ -2,8 +2,11 @@ use actix_web::{web, HttpResponse, Responder};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
 use serde::{Deserialize, Serialize};
 use redis::AsyncCommands;
+use sqlx::{Pool, Postgres};
 use crate::{AppState, LeaderboardEntry, validate_token};
 
+const TOP_N: isize = 10;
+
 /// Request payload for submitting a new score
 #[derive(Serialize, Deserialize)]
 pub struct ScoreRequest {
@@ -11,9 +14,98 @@ pub struct ScoreRequest {
     pub game_name: Option<String>,
 }
 
+/// How tied scores are ranked.
+/// Competition: 1, 2, 2, 4. Dense: 1, 2, 2, 3.
+/// In both modes tied players are listed by who reached the score first.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RankingMode {
+    #[default]
+    Competition,
+    Dense,
+}
+
 #[derive(Deserialize)]
 pub struct LeaderboardQuery {
     pub game: Option<String>,
+    pub ranking: Option<RankingMode>,
+}
+
+/// Row shape used to enrich Valkey results with PostgreSQL details
+#[derive(sqlx::FromRow)]
+struct LeaderboardRow {
+    user_id: String,
+    username: Option<String>,
+    score: i32,
+    updated_at: Option<chrono::NaiveDateTime>,
+    games_played: i32,
+}
+
+/// Re-reads a user's stored score from PostgreSQL and pushes it into the Valkey sorted set.
+/// Sorted set members are user ids; display data lives in PostgreSQL.
+pub async fn refresh_cached_score(db: &Pool<Postgres>, redis_client: &redis::Client, game: &str, user_id: &str) {
+    let score: Option<i32> = sqlx::query_scalar("SELECT score FROM leaderboard WHERE user_id = $1 AND game_name = $2")
+        .bind(user_id)
+        .bind(game)
+        .fetch_optional(db)
+        .await
+        .unwrap_or(None);
+
+    if let (Some(score), Ok(mut con)) = (score, redis_client.get_async_connection().await) {
+        let _: Result<(), redis::RedisError> = con.zadd(format!("leaderboard:{}", game), user_id, score).await;
+    }
+}
+
+/// A user's stored score on a board and its competition rank, if they have one
+pub async fn best_and_rank(db: &Pool<Postgres>, game: &str, user_id: &str) -> Result<Option<(i32, i64)>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT score, (SELECT COUNT(*) FROM leaderboard higher WHERE higher.game_name = $1 AND higher.score > mine.score) + 1
+         FROM leaderboard mine WHERE game_name = $1 AND user_id = $2"
+    )
+    .bind(game)
+    .bind(user_id)
+    .fetch_optional(db)
+    .await
+}
+
+/// Rebuilds every Valkey leaderboard from PostgreSQL.
+/// Run at startup so the cache always uses the current member format (user ids).
+pub async fn rebuild_cache(db: &Pool<Postgres>, redis_client: &redis::Client) -> Result<(), Box<dyn std::error::Error>> {
+    let rows: Vec<(String, String, i32)> = sqlx::query_as("SELECT game_name, user_id, score FROM leaderboard")
+        .fetch_all(db)
+        .await?;
+
+    let mut con = redis_client.get_async_connection().await?;
+    let games: std::collections::HashSet<&String> = rows.iter().map(|(game, _, _)| game).collect();
+    for game in games {
+        let _: () = con.del(format!("leaderboard:{}", game)).await?;
+    }
+    for (game, user_id, score) in rows {
+        let _: () = con.zadd(format!("leaderboard:{}", game), user_id, score).await?;
+    }
+    Ok(())
+}
+
+/// Boards that add up the points of every game instead of keeping the best score
+pub fn accumulates(game: &str) -> bool {
+    game == "xandzero"
+}
+
+/// Keeps a score with the time it was made, so exports can rank a season by what was played in it
+pub async fn record_scored_run(db: &Pool<Postgres>, game: &str, user_id: &str, username: &str, score: i32) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO leaderboard_scores (user_id, game_name, username, score) VALUES ($1, $2, $3, $4)")
+        .bind(user_id)
+        .bind(game)
+        .bind(username)
+        .bind(score)
+        .execute(db)
+        .await
+        .map(|_| ())
+}
+
+/// Boards the server fills from games it ran itself, which clients may not submit to
+pub fn server_scored(game: &str) -> bool {
+    game == "xandzero" || game == "dragonball" || game.starts_with("dragonball_")
 }
 
 /// API endpoint to submit a player's latest score.
@@ -33,15 +125,20 @@ pub async fn submit_score(
     let username = claims.preferred_username.or(claims.name).unwrap_or_else(|| "Anonymous".to_string());
     let new_score = score_req.score;
     let game = score_req.game_name.clone().unwrap_or_else(|| "default".to_string());
-    let redis_key = format!("leaderboard:{}", game);
+    if server_scored(&game) {
+        return HttpResponse::Forbidden().body("Scores for this game are recorded by the server");
+    }
 
     // 1. Update PostgreSQL (High Score Logic)
+    // Every submission counts as a game played; updated_at only moves when the high score improves.
     let result = sqlx::query(
-        "INSERT INTO leaderboard (user_id, game_name, username, score) 
-         VALUES ($1, $2, $3, $4) 
-         ON CONFLICT (user_id, game_name) DO UPDATE 
-         SET score = EXCLUDED.score, username = EXCLUDED.username, updated_at = NOW() 
-         WHERE leaderboard.score < EXCLUDED.score"
+        "INSERT INTO leaderboard (user_id, game_name, username, score, games_played)
+         VALUES ($1, $2, $3, $4, 1)
+         ON CONFLICT (user_id, game_name) DO UPDATE
+         SET score = GREATEST(leaderboard.score, EXCLUDED.score),
+             username = EXCLUDED.username,
+             games_played = leaderboard.games_played + 1,
+             updated_at = CASE WHEN EXCLUDED.score > leaderboard.score THEN NOW() ELSE leaderboard.updated_at END"
     )
     .bind(&user_id)
     .bind(&game)
@@ -53,19 +150,41 @@ pub async fn submit_score(
     if let Err(e) = result {
         return HttpResponse::InternalServerError().body(format!("DB error: {}", e));
     }
+    if let Err(e) = record_scored_run(&data.db, &game, &user_id, &username, new_score).await {
+        return HttpResponse::InternalServerError().body(format!("DB error: {}", e));
+    }
 
     // 2. Update Valkey (Redis)
-    let mut con = match data.redis_client.get_async_connection().await {
-        Ok(con) => con,
-        Err(e) => return HttpResponse::InternalServerError().body(format!("Redis error: {}", e)),
-    };
-
-    // ZADD will update if score is different. 
-    let _: Result<(), redis::RedisError> = con.zadd(&redis_key, &username, new_score).await;
+    refresh_cached_score(&data.db, &data.redis_client, &game, &user_id).await;
 
     HttpResponse::Ok().body("Score submitted")
 }
 
+/// Sorts entries by score (desc), then by who reached the score first, then by user id.
+fn sort_and_rank(rows: &mut [LeaderboardRow], mode: RankingMode) -> Vec<i64> {
+    rows.sort_by(|a, b| {
+        b.score.cmp(&a.score)
+            .then_with(|| a.updated_at.cmp(&b.updated_at))
+            .then_with(|| a.user_id.cmp(&b.user_id))
+    });
+
+    let mut ranks = Vec::with_capacity(rows.len());
+    let mut distinct_scores = 0;
+    for (i, row) in rows.iter().enumerate() {
+        let new_score = i == 0 || rows[i - 1].score != row.score;
+        if new_score {
+            distinct_scores += 1;
+        }
+        let rank = match (mode, new_score) {
+            (_, false) => *ranks.last().unwrap(),
+            (RankingMode::Competition, true) => (i + 1) as i64,
+            (RankingMode::Dense, true) => distinct_scores,
+        };
+        ranks.push(rank);
+    }
+    ranks
+}
+
 /// API endpoint to retrieve the current top 10 global leaderboard.
 #[actix_web::get("/leaderboard")]
 pub async fn get_leaderboard(
@@ -78,21 +197,58 @@ pub async fn get_leaderboard(
     };
 
     let game = query.game.clone().unwrap_or_else(|| "default".to_string());
+    let mode = query.ranking.unwrap_or_default();
     let redis_key = format!("leaderboard:{}", game);
 
     // Get top 10
-    let results: Vec<(String, i32)> = match con.zrevrange_withscores(&redis_key, 0, 9).await {
+    let mut results: Vec<(String, i32)> = match con.zrevrange_withscores(&redis_key, 0, TOP_N - 1).await {
         Ok(res) => res,
         Err(e) => return HttpResponse::InternalServerError().body(format!("Redis error: {}", e)),
     };
 
-    let leaderboard: Vec<LeaderboardEntry> = results
+    // Valkey orders equal scores lexicographically, so pull in everyone tied with the
+    // last place to let the tie-break decide who makes the cut.
+    if results.len() == TOP_N as usize {
+        let cutoff = results[results.len() - 1].1;
+        results = match con.zrevrangebyscore_withscores(&redis_key, "+inf", cutoff).await {
+            Ok(res) => res,
+            Err(e) => return HttpResponse::InternalServerError().body(format!("Redis error: {}", e)),
+        };
+    }
+
+    let user_ids: Vec<String> = results.iter().map(|(id, _)| id.clone()).collect();
+    let mut rows: Vec<LeaderboardRow> = match sqlx::query_as(
+        "SELECT user_id, username, score, updated_at, games_played FROM leaderboard
+         WHERE game_name = $1 AND user_id = ANY($2)"
+    )
+    .bind(&game)
+    .bind(&user_ids)
+    .fetch_all(&data.db)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("DB error: {}", e)),
+    };
+
+    // Valkey is the source of truth for the live score
+    for row in rows.iter_mut() {
+        if let Some((_, score)) = results.iter().find(|(id, _)| id == &row.user_id) {
+            row.score = *score;
+        }
+    }
+
+    let ranks = sort_and_rank(&mut rows, mode);
+    let leaderboard: Vec<LeaderboardEntry> = rows
         .into_iter()
-        .enumerate()
-        .map(|(rank, (username, score))| LeaderboardEntry {
-            username,
-            score,
-            rank: Some((rank + 1) as i64),
+        .zip(ranks)
+        .take(TOP_N as usize)
+        .map(|(row, rank)| LeaderboardEntry {
+            user_id: row.user_id,
+            username: row.username.unwrap_or_else(|| "Anonymous".to_string()),
+            score: row.score,
+            rank: Some(rank),
+            achieved_at: row.updated_at,
+            games_played: row.games_played,
         })
         .collect();
 
@@ -102,7 +258,7 @@ pub async fn get_leaderboard(
 /// API endpoint to retrieve the rank and score of the currently authenticated player.
 #[actix_web::get("/leaderboard/me")]
 pub async fn get_my_rank(
-    data: web::Data<AppState>, 
+    data: web::Data<AppState>,
     auth: BearerAuth,
     query: web::Query<LeaderboardQuery>,
 ) -> impl Responder {
@@ -111,8 +267,9 @@ pub async fn get_my_rank(
         Err(r) => return r,
     };
 
-    let username = claims.preferred_username.or(claims.name).unwrap_or_else(|| "Anonymous".to_string());
+    let user_id = claims.sub;
     let game = query.game.clone().unwrap_or_else(|| "default".to_string());
+    let mode = query.ranking.unwrap_or_default();
     let redis_key = format!("leaderboard:{}", game);
 
     let mut con = match data.redis_client.get_async_connection().await {
@@ -120,15 +277,67 @@ pub async fn get_my_rank(
         Err(e) => return HttpResponse::InternalServerError().body(format!("Redis error: {}", e)),
     };
 
-    let score: Option<i32> = con.zscore(&redis_key, &username).await.ok();
-    let rank: Option<i64> = con.zrevrank(&redis_key, &username).await.ok();
+    let score: Option<i32> = con.zscore(&redis_key, &user_id).await.ok();
+    let Some(score) = score else {
+        return HttpResponse::Ok().json(serde_json::json!({ "not_found": true }));
+    };
+
+    let row: Option<LeaderboardRow> = sqlx::query_as(
+        "SELECT user_id, username, score, updated_at, games_played FROM leaderboard
+         WHERE game_name = $1 AND user_id = $2"
+    )
+    .bind(&game)
+    .bind(&user_id)
+    .fetch_optional(&data.db)
+    .await
+    .unwrap_or(None);
 
-    match (score, rank) {
-        (Some(s), Some(r)) => HttpResponse::Ok().json(LeaderboardEntry {
-            username,
-            score: s,
-            rank: Some(r + 1),
+    // Competition rank: one more than the number of strictly higher scores.
+    // Dense rank: one more than the number of distinct higher scores.
+    let rank: Option<i64> = match mode {
+        RankingMode::Competition => con
+            .zcount(&redis_key, format!("({}", score), "+inf")
+            .await
+            .ok()
+            .map(|higher: i64| higher + 1),
+        RankingMode::Dense => sqlx::query_scalar(
+            "SELECT COUNT(DISTINCT score) FROM leaderboard WHERE game_name = $1 AND score > $2"
+        )
+        .bind(&game)
+        .bind(score)
+        .fetch_one(&data.db)
+        .await
+        .ok()
+        .map(|higher: i64| higher + 1),
+    };
+
+    match (row, rank) {
+        (Some(row), Some(r)) => HttpResponse::Ok().json(LeaderboardEntry {
+            user_id,
+            username: row.username.unwrap_or_else(|| "Anonymous".to_string()),
+            score,
+            rank: Some(r),
+            achieved_at: row.updated_at,
+            games_played: row.games_played,
         }),
         _ => HttpResponse::Ok().json(serde_json::json!({ "not_found": true })),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dragonball_presets::{coop_leaderboard_game, leaderboard_game};
+
+    #[test]
+    fn game_boards_are_closed_to_client_scores() {
+        for game in ["xandzero", "dragonball", "dragonball_hard", "dragonball_coop", "dragonball_coop_easy"] {
+            assert!(server_scored(game), "{}", game);
+        }
+        assert!(server_scored(&leaderboard_game("my-preset")));
+        assert!(server_scored(&coop_leaderboard_game("my-preset")));
+        for game in ["default", "tetris", "dragonballs", "xandzero2"] {
+            assert!(!server_scored(game), "{}", game);
+        }
+    }
+}
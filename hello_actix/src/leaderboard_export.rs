@@ -0,0 +1,197 @@
+use actix_web::{web, HttpResponse, Responder};
+use actix_web::web::Bytes;
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use chrono::NaiveDateTime;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use crate::leaderboard::{self, RankingMode};
+use crate::{AppState, validate_admin_token};
+
+/// Output format of the export
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Ndjson,
+}
+
+/// Query parameters for the admin export.
+/// `season` takes precedence over `from`/`to` and resolves to the window stored in the `seasons` table.
+/// A window ranks only the scores made inside it: each player's best, or their total on boards that add up points.
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    pub game: Option<String>,
+    pub format: Option<ExportFormat>,
+    pub ranking: Option<RankingMode>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+    pub season: Option<String>,
+}
+
+/// A single exported standing
+#[derive(Serialize, sqlx::FromRow)]
+struct ExportRow {
+    user_id: String,
+    display_name: Option<String>,
+    score: i32,
+    rank: i64,
+    last_updated: Option<NaiveDateTime>,
+}
+
+/// Quotes a CSV field if it contains a delimiter, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn encode_row(row: &ExportRow, format: ExportFormat) -> String {
+    match format {
+        ExportFormat::Csv => format!(
+            "{},{},{},{},{}\n",
+            csv_field(&row.user_id),
+            csv_field(row.display_name.as_deref().unwrap_or("")),
+            row.score,
+            row.rank,
+            row.last_updated.map(|t| t.to_string()).unwrap_or_default(),
+        ),
+        ExportFormat::Ndjson => {
+            let mut line = serde_json::to_string(row).unwrap_or_default();
+            line.push('\n');
+            line
+        }
+    }
+}
+
+/// Admin endpoint streaming the full leaderboard of a game, or its standings for a time window or season.
+/// Rows are read from PostgreSQL with a cursor and written out in chunks, so large boards never sit in memory.
+#[actix_web::get("/admin/leaderboard/export")]
+pub async fn export_leaderboard(
+    data: web::Data<AppState>,
+    auth: BearerAuth,
+    query: web::Query<ExportQuery>,
+) -> impl Responder {
+    if let Err(r) = validate_admin_token(auth.token(), &data.oidc_jwks_uri).await {
+        return r;
+    }
+
+    let game = query.game.clone().unwrap_or_else(|| "default".to_string());
+    let format = query.format.unwrap_or_default();
+    let rank_fn = match query.ranking.unwrap_or_default() {
+        RankingMode::Competition => "RANK()",
+        RankingMode::Dense => "DENSE_RANK()",
+    };
+
+    let (from, to) = match &query.season {
+        Some(season) => {
+            let window: Option<(NaiveDateTime, NaiveDateTime)> = match sqlx::query_as(
+                "SELECT starts_at, ends_at FROM seasons WHERE game_name = $1 AND name = $2"
+            )
+            .bind(&game)
+            .bind(season)
+            .fetch_optional(&data.db)
+            .await
+            {
+                Ok(w) => w,
+                Err(e) => return HttpResponse::InternalServerError().body(format!("DB error: {}", e)),
+            };
+            match window {
+                Some((start, end)) => (Some(start), Some(end)),
+                None => return HttpResponse::NotFound().body("Unknown season"),
+            }
+        }
+        None => (query.from, query.to),
+    };
+
+    // All-time standings come from the leaderboard itself; a window is ranked from the scores recorded in it
+    let standings = if from.is_none() && to.is_none() {
+        "SELECT user_id, username AS display_name, score, updated_at AS last_updated
+         FROM leaderboard
+         WHERE game_name = $1 AND $2::timestamp IS NULL AND $3::timestamp IS NULL"
+    } else if leaderboard::accumulates(&game) {
+        "SELECT user_id, (ARRAY_AGG(username ORDER BY scored_at DESC))[1] AS display_name,
+                SUM(score)::INT AS score, MAX(scored_at) AS last_updated
+         FROM leaderboard_scores
+         WHERE game_name = $1
+           AND ($2::timestamp IS NULL OR scored_at >= $2)
+           AND ($3::timestamp IS NULL OR scored_at < $3)
+         GROUP BY user_id"
+    } else {
+        "SELECT DISTINCT ON (user_id) user_id, username AS display_name, score, scored_at AS last_updated
+         FROM leaderboard_scores
+         WHERE game_name = $1
+           AND ($2::timestamp IS NULL OR scored_at >= $2)
+           AND ($3::timestamp IS NULL OR scored_at < $3)
+         ORDER BY user_id, score DESC, scored_at ASC"
+    };
+    let sql = format!(
+        "WITH standings AS ({})
+         SELECT user_id, display_name, score,
+                {} OVER (ORDER BY score DESC) AS rank,
+                last_updated
+         FROM standings
+         ORDER BY score DESC, last_updated ASC, user_id ASC",
+        standings, rank_fn
+    );
+
+    // The sqlx row stream borrows the pool, so it is drained on its own task and
+    // forwarded through a bounded channel that applies backpressure to the query.
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<Bytes, actix_web::Error>>(16);
+    let db = data.db.clone();
+    actix_web::rt::spawn(async move {
+        if format == ExportFormat::Csv {
+            let header = Bytes::from_static(b"user_id,display_name,score,rank,last_updated\n");
+            if tx.send(Ok(header)).await.is_err() {
+                return;
+            }
+        }
+
+        let mut rows = sqlx::query_as::<_, ExportRow>(&sql)
+            .bind(&game)
+            .bind(from)
+            .bind(to)
+            .fetch(&db);
+
+        let mut chunk = String::new();
+        while let Some(row) = rows.next().await {
+            match row {
+                Ok(row) => chunk.push_str(&encode_row(&row, format)),
+                Err(e) => {
+                    println!("Leaderboard export error: {:?}", e);
+                    let _ = tx.send(Err(actix_web::error::ErrorInternalServerError("export failed"))).await;
+                    return;
+                }
+            }
+            if chunk.len() >= 8 * 1024 && tx.send(Ok(Bytes::from(std::mem::take(&mut chunk)))).await.is_err() {
+                return; // Client went away
+            }
+        }
+        if !chunk.is_empty() {
+            let _ = tx.send(Ok(Bytes::from(chunk))).await;
+        }
+    });
+
+    let body = futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|item| (item, rx))
+    });
+
+    let file_stem: String = query.game.as_deref().unwrap_or("default")
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+        .collect();
+    let (content_type, extension) = match format {
+        ExportFormat::Csv => ("text/csv; charset=utf-8", "csv"),
+        ExportFormat::Ndjson => ("application/x-ndjson", "ndjson"),
+    };
+
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header((
+            "Content-Disposition",
+            format!("attachment; filename=\"leaderboard-{}.{}\"", file_stem, extension),
+        ))
+        .streaming(body)
+}
@@ -0,0 +1,3 @@
+//! The Dragonball simulation on its own, for the server, the headless simulator and the benchmarks
+pub mod dragonballgame;
+pub mod dragonball_bots;
@@ -8,23 +8,37 @@ use actix_web_httpauth::extractors::bearer::BearerAuth;
 // use async_oidc_jwt_validator::IMValidator; // Removed
 use jsonwebtoken::{decode, decode_header, DecodingKey, Validation, Algorithm};
 use serde_json::Value;
-mod dragonballgame;
+use app_template_backend::dragonballgame;
+mod dragonball_runs;
+mod dragonball_protocol;
+mod dragonball_presets;
+mod dragonball_rooms;
+mod dragonball_input;
 
 use actix_web::{get, web, App, HttpRequest, HttpServer, Responder, HttpResponse, Error};
-use actix_ws::Message;
-use std::time::Duration;
-use std::sync::{Arc, Mutex};
-use tokio::time::interval;
 
 mod xandzero;
+mod xandzero_ai;
+mod xandzero_powerups;
+mod xandzero_pvp;
+mod matchmaking;
 mod leaderboard;
+mod leaderboard_export;
+mod ratings;
+mod xandzero_records;
+mod wallet;
+mod spectate;
+mod achievements;
 
 /// Represents a single entry in the leaderboard (used in response JSON)
 #[derive(Serialize, Deserialize, Debug)]
 pub struct LeaderboardEntry {
+    pub user_id: String,
     pub username: String,
     pub score: i32,
-    pub rank: Option<i64>, // Real-time rank from Valkey/Redis
+    pub rank: Option<i64>,                         // Real-time rank from Valkey/Redis
+    pub achieved_at: Option<chrono::NaiveDateTime>, // When the current score was reached (tie-breaker)
+    pub games_played: i32,
 }
 
 /// Shared application state injected into Actix handlers
@@ -33,6 +47,7 @@ pub struct AppState {
     pub db: Pool<Postgres>,          // PostgreSQL connection pool
     pub redis_client: redis::Client, // Valkey/Redis client
     pub oidc_jwks_uri: String,       // Keycloak JWKS endpoint
+    pub dragonball_rooms: dragonball_rooms::Rooms, // Dragonball rooms simulated by this instance
 }
 
 #[derive(Serialize, Deserialize)]
@@ -48,6 +63,21 @@ pub struct Claims {
     pub exp: usize,                       // Expiration timestamp
     pub preferred_username: Option<String>, // Keycloak username
     pub name: Option<String>,             // User's full name
+    pub realm_access: Option<RealmAccess>, // Keycloak realm roles
+}
+
+/// Realm role list as issued by Keycloak in the `realm_access` claim
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RealmAccess {
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+impl Claims {
+    /// True if the user holds the Keycloak `admin` realm role
+    pub fn is_admin(&self) -> bool {
+        self.realm_access.as_ref().is_some_and(|ra| ra.roles.iter().any(|r| r == "admin"))
+    }
 }
 
 /// Fetches the JSON Web Key Set (JWKS) and returns the decoding key for a specific kid
@@ -141,7 +171,7 @@ async fn get_joke(
             let _: Result<(), redis::RedisError> = con.set_ex(&cache_key, &content, 60).await;
             HttpResponse::Ok().json(Joke {
                 id: random_id,
-                content: content,
+                content,
             })
         }
         Err(e) => HttpResponse::InternalServerError().body(format!("Database error: {}", e)),
@@ -166,128 +196,121 @@ pub async fn validate_token(token: &str, jwks_uri: &str) -> Result<Claims, HttpR
         .map_err(|e| HttpResponse::Unauthorized().body(format!("Invalid token: {:?}", e)))
 }
 
+/// Validates the OIDC token and additionally requires the `admin` realm role
+pub async fn validate_admin_token(token: &str, jwks_uri: &str) -> Result<Claims, HttpResponse> {
+    let claims = validate_token(token, jwks_uri).await?;
+    if !claims.is_admin() {
+        return Err(HttpResponse::Forbidden().body("Admin role required"));
+    }
+    Ok(claims)
+}
+
 
 #[derive(Deserialize)]
 struct WsQuery {
     token: String,
 }
 
-async fn dragon_socket(
-    req: HttpRequest, 
-    stream: web::Payload,
-    data: web::Data<AppState>,
-) -> Result<HttpResponse, Error> {
+/// Extra `/dragon_ws` query parameters
+#[derive(Deserialize)]
+struct DragonQuery {
+    tick_rate: Option<u32>, // Simulation steps per second, one of `TICK_RATES`
+    protocol: Option<u32>,  // One of `PROTOCOL_VERSIONS`
+    encoding: Option<dragonball_protocol::Encoding>, // Protocol 2 only
+    difficulty: Option<String>, // Built-in or custom preset name
+    room: Option<String>, // `new` creates a co-op room, a room id joins one
+    resume: Option<String>, // Session id to reconnect to, with `resume_token`
+    resume_token: Option<String>,
+}
+
+/// Authenticates a websocket upgrade from the `?token=` query parameter.
+/// Returns the user id and display name, or the response to send instead of upgrading.
+pub async fn authenticate_ws(req: &HttpRequest, data: &AppState) -> Result<(String, String), HttpResponse> {
     // 1. Manually Parse Query
     let query_string = req.query_string();
     let token = match serde_urlencoded::from_str::<WsQuery>(query_string) {
         Ok(q) => q.token,
-        Err(_) => return Ok(HttpResponse::Unauthorized().body("Missing or invalid token")),
+        Err(_) => return Err(HttpResponse::Unauthorized().body("Missing or invalid token")),
     };
 
     // 2. Validate Token
     let claims = match validate_token(&token, &data.oidc_jwks_uri).await {
         Ok(c) => c,
-        Err(_) => return Ok(HttpResponse::Unauthorized().finish()),
+        Err(_) => return Err(HttpResponse::Unauthorized().finish()),
     };
 
-    let user_id = claims.sub.clone();
     let username = claims.preferred_username.or(claims.name).unwrap_or_else(|| "Anonymous".to_string());
+    Ok((claims.sub, username))
+}
+
+async fn dragon_socket(
+    req: HttpRequest, 
+    stream: web::Payload,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let (user_id, username) = match authenticate_ws(&req, &data).await {
+        Ok(user) => user,
+        Err(res) => return Ok(res),
+    };
     
-    let (res, mut session, mut msg_stream) = actix_ws::handle(&req, stream)?;
-
-    let game_state = Arc::new(Mutex::new(dragonballgame::GameState::new()));
-    let game_state_clone = game_state.clone();
-    let mut session_clone = session.clone();
-    let db_pool = data.db.clone();
-    let redis_client = data.redis_client.clone();
-
-    // 2. Input Handler Task (Client -> Server)
-    actix_web::rt::spawn(async move {
-        while let Some(Ok(msg)) = futures_util::StreamExt::next(&mut msg_stream).await {
-            match msg {
-                Message::Text(text) => {
-                    if let Ok(player_input) = serde_json::from_str::<dragonballgame::Player>(&text) {
-                        if let Ok(mut state) = game_state.lock() {
-                            state.update_player_pos(player_input.y);
-                        }
-                    }
-                }
-                Message::Ping(bytes) => {
-                    if session.pong(&bytes).await.is_err() {
-                        break;
-                    }
-                }
-                Message::Close(_) => break,
-                _ => {}
-            }
-        }
-    });
-
-    // 3. Game Loop Task (Server -> Client)
-    actix_web::rt::spawn(async move {
-        let mut ticker = interval(Duration::from_millis(16)); // ~60 FPS
-        let mut score_saved = false;
-
-        loop {
-            ticker.tick().await;
-
-            let (state_json, game_over, score) = {
-                let mut state = game_state_clone.lock().unwrap();
-                state.tick();
-                (serde_json::to_string(&*state).unwrap(), state.game_over, state.score)
-            };
-
-            // Check for Game Over Persistence
-            if game_over && !score_saved {
-                // Save Score
-                score_saved = true;
-                
-                // Update DB
-                let _ = sqlx::query(
-                    "INSERT INTO leaderboard (user_id, game_name, username, score) 
-                     VALUES ($1, 'dragonball', $2, $3) 
-                     ON CONFLICT (user_id, game_name) DO UPDATE SET score = GREATEST(leaderboard.score, $3)"
-                )
-                .bind(&user_id)
-                .bind(&username)
-                .bind(score as i32)
-                .execute(&db_pool)
-                .await; // Note: In a real app, handle error logging
-
-                // Update Redis
-                if let Ok(mut con) = redis_client.get_async_connection().await {
-                   // We want to store the BEST score in leaderboard, usually. 
-                   // But ZADD just updates. So we should probably check if new score is higher?
-                   // Redis ZADD updates if score is different.
-                   // Logic: Fetch best from DB or Redis? 
-                   // For this simple game, let's just push the latest score if it depends on "high score" semantics.
-                   // But if I play twice and get lower score, I shouldn't overwrite high score in Redis?
-                   // The SQL query used GREATEST.
-                   // Let's use ZADD GT (Greater Than) if supported, or just trust the DB flow?
-                   // Let's re-fetch the max score from DB to be safe and consistent.
-                    let best_score: i32 = sqlx::query_scalar("SELECT score FROM leaderboard WHERE user_id = $1 AND game_name = 'dragonball'")
-                        .bind(&user_id)
-                        .fetch_one(&db_pool)
-                        .await
-                        .unwrap_or(score as i32);
-
-                    let _: Result<(), redis::RedisError> = con.zadd("leaderboard:dragonball", &username, best_score).await;
-                }
-            }
+    let query = match serde_urlencoded::from_str::<DragonQuery>(req.query_string()) {
+        Ok(query) => query,
+        Err(e) => return Ok(HttpResponse::BadRequest().body(format!("Invalid query: {}", e))),
+    };
+    let tick_rate = query.tick_rate.unwrap_or(dragonballgame::DEFAULT_TICK_RATE);
+    if !dragonballgame::TICK_RATES.contains(&tick_rate) {
+        return Ok(HttpResponse::BadRequest().body("tick_rate must be 30, 60 or 120"));
+    }
+    let protocol = query.protocol.unwrap_or(dragonball_protocol::DEFAULT_PROTOCOL_VERSION);
+    if !dragonball_protocol::PROTOCOL_VERSIONS.contains(&protocol) {
+        return Ok(HttpResponse::BadRequest().body("protocol must be 1 or 2"));
+    }
+    if protocol == 1 && query.encoding.is_some_and(|e| e != dragonball_protocol::Encoding::Json) {
+        return Ok(HttpResponse::BadRequest().body("Protocol 1 only supports JSON"));
+    }
+    let wire = dragonball_rooms::Wire { protocol, encoding: query.encoding.unwrap_or_default() };
+
+    // A reconnect picks its session up where it was paused
+    if let Some(session_id) = query.resume.as_deref() {
+        let Some(resume_token) = query.resume_token.as_deref() else {
+            return Ok(HttpResponse::BadRequest().body("resume needs resume_token"));
+        };
+        let (res, session, msg_stream) = actix_ws::handle(&req, stream)?;
+        let (room, conn_id) = match dragonball_rooms::resume(&data.dragonball_rooms, session_id, resume_token, &user_id, session.clone(), wire).await {
+            Ok(resumed) => resumed,
+            Err(e) => return Ok(e.response()),
+        };
+        actix_web::rt::spawn(dragonball_rooms::read_inputs(data.clone(), room, conn_id, session, msg_stream));
+        return Ok(res);
+    }
 
-            if session_clone.text(state_json).await.is_err() {
-                break;
-            }
-            
-            if game_over && score_saved {
-                 // Game is over and saved. We can stop the loop or keep sending end state?
-                 // Usually keep sending so client sees "Game Over" screen.
-                 // But we don't need to tick logic anymore.
-                 // Just continue to render static state or break if we want to close connection?
-                 // Let's continue so client can see the screen.
-            }
-        }
-    });
+    // Joiners play with the room's tick rate and difficulty
+    if let Some(room_id) = query.room.as_deref().filter(|room| *room != "new") {
+        let (res, session, msg_stream) = actix_ws::handle(&req, stream)?;
+        let (room, conn_id) = match dragonball_rooms::join(&data.dragonball_rooms, room_id, user_id, username, session.clone(), wire) {
+            Ok(joined) => joined,
+            Err(e) => return Ok(e.response()),
+        };
+        actix_web::rt::spawn(dragonball_rooms::open(data.clone(), room.clone(), conn_id.clone()));
+        actix_web::rt::spawn(dragonball_rooms::read_inputs(data.clone(), room, conn_id, session, msg_stream));
+        return Ok(res);
+    }
+
+    let difficulty = query.difficulty.unwrap_or_else(|| dragonballgame::DEFAULT_DIFFICULTY.to_string());
+    let config = match dragonball_presets::resolve(&data.db, &difficulty).await {
+        Ok(Some(config)) => config,
+        Ok(None) => return Ok(HttpResponse::BadRequest().body("Unknown difficulty")),
+        Err(e) => return Ok(HttpResponse::InternalServerError().body(format!("DB error: {}", e))),
+    };
+
+    let (res, session, msg_stream) = actix_ws::handle(&req, stream)?;
+
+    // A solo run is a room of one that starts right away
+    let coop = query.room.is_some();
+    let room = dragonball_rooms::Room::new(uuid::Uuid::new_v4().to_string(), coop, tick_rate, difficulty, config);
+    let (room, conn_id) = dragonball_rooms::create(&data.dragonball_rooms, room, user_id, username, session.clone(), wire);
+    actix_web::rt::spawn(dragonball_rooms::open(data.clone(), room.clone(), conn_id.clone()));
+    actix_web::rt::spawn(dragonball_rooms::read_inputs(data.clone(), room, conn_id, session, msg_stream));
 
     Ok(res)
 }
@@ -312,11 +335,17 @@ async fn main() -> std::io::Result<()> {
 
     let redis_client = redis::Client::open(redis_url).expect("Invalid Redis URL");
 
+    if let Err(e) = leaderboard::rebuild_cache(&pool, &redis_client).await {
+        println!("Leaderboard cache rebuild failed: {:?}", e);
+    }
+
     let app_state = AppState {
         db: pool,
         redis_client,
         oidc_jwks_uri: jwks_uri,
+        dragonball_rooms: Default::default(),
     };
+    actix_web::rt::spawn(xandzero_pvp::sweep_turn_timers(app_state.clone()));
 
     println!("Starting server at http://0.0.0.0:9876");
     HttpServer::new(move || {
@@ -327,8 +356,32 @@ async fn main() -> std::io::Result<()> {
             .service(leaderboard::submit_score)
             .service(leaderboard::get_leaderboard)
             .service(leaderboard::get_my_rank)
+            .service(leaderboard_export::export_leaderboard)
+            .service(ratings::get_rated_leaderboard)
+            .service(ratings::get_my_rating)
+            .service(xandzero::create_game)
+            .service(xandzero::get_game)
             .service(xandzero::xandzero_play)
+            .service(xandzero::xandzero_erase)
+            .service(xandzero::xandzero_power_up)
+            .service(xandzero_powerups::get_catalogue)
+            .service(xandzero_records::list_games)
+            .service(xandzero_records::get_replay)
+            .service(wallet::get_wallet)
+            .service(wallet::grant_points)
+            .service(spectate::get_live_games)
+            .service(dragonball_runs::list_runs)
+            .service(dragonball_runs::list_flagged_runs)
+            .service(dragonball_runs::verify_run)
+            .service(dragonball_presets::list_presets)
+            .service(dragonball_presets::put_preset)
+            .service(dragonball_presets::delete_preset)
+            .service(achievements::get_my_achievements)
             .route("/dragon_ws", web::get().to(dragon_socket))
+            .route("/dragon_ws/spectate/{game_id}", web::get().to(spectate::dragonball_spectate))
+            .route("/dragon_ws/replay/{run_id}", web::get().to(dragonball_runs::replay_socket))
+            .route("/xandzero_ws", web::get().to(xandzero_pvp::xandzero_socket))
+            .route("/xandzero_ws/spectate/{game_id}", web::get().to(spectate::xandzero_spectate))
     })
     .bind(("0.0.0.0", 9876))?
     .run()
@@ -0,0 +1,214 @@
+use serde::{Deserialize, Serialize};
+use redis::AsyncCommands;
+use crate::{AppState, ratings};
+use crate::xandzero::{self, BoardSpec, GameMode, Seat, XandZeroGame};
+use crate::xandzero_ai::Difficulty;
+
+/// Acceptable rating difference right after queueing
+const INITIAL_RATING_GAP: f64 = 50.0;
+/// How much the acceptable difference grows per second of waiting
+const RATING_GAP_GROWTH_PER_SEC: f64 = 10.0;
+/// Players still waiting after this long get a computer opponent instead
+const AI_FALLBACK_AFTER_MS: i64 = 30_000;
+/// Tickets of crashed instances disappear on their own
+const TICKET_TTL_SECS: i64 = 5 * 60;
+const MATCH_NOTICE_TTL_SECS: u64 = 60;
+
+/// Removes two players from the queue only if both are still in it, so a player is never matched twice
+const CLAIM_PAIR_SCRIPT: &str = r"
+if redis.call('ZSCORE', KEYS[1], ARGV[1]) and redis.call('ZSCORE', KEYS[1], ARGV[2]) then
+    redis.call('ZREM', KEYS[1], ARGV[1], ARGV[2])
+    return 1
+end
+return 0
+";
+
+/// A player waiting in a queue. Stored as JSON under `matchmaking:ticket:{user_id}`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Ticket {
+    pub seat: Seat,
+    pub game: String,
+    pub mode: GameMode,
+    pub rating: f64,
+    pub enqueued_at_ms: i64,
+}
+
+/// Result of a matchmaking poll
+pub enum MatchOutcome {
+    Waiting,
+    Matched { game_id: String, vs_computer: bool },
+}
+
+fn now_ms() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+fn queue_key(game: &str, mode: GameMode) -> String {
+    format!("matchmaking:{}:{}", game, mode.as_str())
+}
+
+fn ticket_key(user_id: &str) -> String {
+    format!("matchmaking:ticket:{}", user_id)
+}
+
+/// Set by whoever formed the match, read by the other player's connection
+fn match_notice_key(user_id: &str) -> String {
+    format!("matchmaking:match:{}", user_id)
+}
+
+/// Rating difference a ticket accepts after waiting `waited_ms`
+pub fn allowed_gap(waited_ms: i64) -> f64 {
+    INITIAL_RATING_GAP + RATING_GAP_GROWTH_PER_SEC * (waited_ms.max(0) as f64 / 1000.0)
+}
+
+/// Computer difficulty whose fixed rating is closest to the player's
+fn fallback_difficulty(rating: f64) -> Difficulty {
+    [Difficulty::Easy, Difficulty::Medium, Difficulty::Hard]
+        .into_iter()
+        .min_by(|a, b| {
+            (ratings::computer_rating(*a).rating - rating).abs()
+                .total_cmp(&(ratings::computer_rating(*b).rating - rating).abs())
+        })
+        .unwrap_or_default()
+}
+
+/// Puts a player in the queue for `game`/`mode`, replacing any earlier ticket
+pub async fn enqueue(data: &AppState, seat: Seat, game: &str, mode: GameMode) -> Result<Ticket, redis::RedisError> {
+    let rating = ratings::current_rating(&data.db, &seat.user_id, game, mode).await;
+    let ticket = Ticket { seat, game: game.to_string(), mode, rating, enqueued_at_ms: now_ms() };
+
+    let mut con = data.redis_client.get_async_connection().await?;
+    let json = serde_json::to_string(&ticket).unwrap_or_default();
+    redis::pipe()
+        .atomic()
+        .del(match_notice_key(&ticket.seat.user_id)).ignore()
+        .set_ex(ticket_key(&ticket.seat.user_id), json, TICKET_TTL_SECS as u64).ignore()
+        .zadd(queue_key(game, mode), &ticket.seat.user_id, rating).ignore()
+        .query_async::<_, ()>(&mut con)
+        .await?;
+    Ok(ticket)
+}
+
+/// Takes a player out of the queue
+pub async fn leave(data: &AppState, ticket: &Ticket) -> Result<(), redis::RedisError> {
+    let mut con = data.redis_client.get_async_connection().await?;
+    redis::pipe()
+        .zrem(queue_key(&ticket.game, ticket.mode), &ticket.seat.user_id).ignore()
+        .del(ticket_key(&ticket.seat.user_id)).ignore()
+        .query_async::<_, ()>(&mut con)
+        .await
+}
+
+/// One matchmaking step for a waiting player. Called periodically by the player's connection.
+/// Looks for the closest rating within the gap allowed by the wait so far, and falls back to
+/// a computer opponent once the wait exceeds the limit.
+pub async fn poll(data: &AppState, ticket: &Ticket) -> Result<MatchOutcome, redis::RedisError> {
+    let mut con = data.redis_client.get_async_connection().await?;
+    let queue = queue_key(&ticket.game, ticket.mode);
+    let me = &ticket.seat.user_id;
+
+    // Someone else may already have paired us
+    let still_queued: Option<f64> = con.zscore(&queue, me).await?;
+    if still_queued.is_none() {
+        let notice: Option<String> = con.get(match_notice_key(me)).await?;
+        return Ok(match notice {
+            Some(game_id) => {
+                let _: () = con.del(&[match_notice_key(me), ticket_key(me)]).await?;
+                MatchOutcome::Matched { game_id, vs_computer: false }
+            }
+            None => MatchOutcome::Waiting,
+        });
+    }
+
+    let waited = now_ms() - ticket.enqueued_at_ms;
+    let gap = allowed_gap(waited);
+    let candidates: Vec<(String, f64)> = con
+        .zrangebyscore_withscores(&queue, ticket.rating - gap, ticket.rating + gap)
+        .await?;
+
+    let mut candidates: Vec<(String, f64)> = candidates.into_iter().filter(|(id, _)| id != me).collect();
+    candidates.sort_by(|a, b| (a.1 - ticket.rating).abs().total_cmp(&(b.1 - ticket.rating).abs()));
+
+    for (other_id, _) in candidates {
+        let raw: Option<String> = con.get(ticket_key(&other_id)).await?;
+        let Some(other) = raw.and_then(|json| serde_json::from_str::<Ticket>(&json).ok()) else {
+            // Ticket expired (instance went away); drop the stale queue entry
+            let _: () = con.zrem(&queue, &other_id).await?;
+            continue;
+        };
+
+        let claimed: i32 = redis::Script::new(CLAIM_PAIR_SCRIPT)
+            .key(&queue)
+            .arg(me)
+            .arg(&other_id)
+            .invoke_async(&mut con)
+            .await?;
+        if claimed == 0 {
+            continue;
+        }
+
+        // The player who waited longer opens the game
+        let (x, o) = if other.enqueued_at_ms <= ticket.enqueued_at_ms {
+            (other.seat.clone(), ticket.seat.clone())
+        } else {
+            (ticket.seat.clone(), other.seat.clone())
+        };
+        let game = XandZeroGame::new_pvp(x, Some(o), BoardSpec::default(), ticket.mode);
+        xandzero::store_new_game(&data.redis_client, &game).await?;
+
+        let _: () = con.set_ex(match_notice_key(&other_id), &game.game_id, MATCH_NOTICE_TTL_SECS).await?;
+        let _: () = con.del(ticket_key(me)).await?;
+        return Ok(MatchOutcome::Matched { game_id: game.game_id, vs_computer: false });
+    }
+
+    if waited >= AI_FALLBACK_AFTER_MS {
+        let removed: i32 = con.zrem(&queue, me).await?;
+        if removed == 1 {
+            let _: () = con.del(ticket_key(me)).await?;
+            let game = XandZeroGame::new(
+                ticket.seat.user_id.clone(),
+                ticket.seat.username.clone(),
+                BoardSpec::default(),
+                ticket.mode,
+                fallback_difficulty(ticket.rating),
+            );
+            xandzero::store_new_game(&data.redis_client, &game).await?;
+            return Ok(MatchOutcome::Matched { game_id: game.game_id, vs_computer: true });
+        }
+    }
+
+    Ok(MatchOutcome::Waiting)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rating_gap_widens_with_waiting() {
+        assert_eq!(allowed_gap(0), INITIAL_RATING_GAP);
+        // A ticket from an instance whose clock runs ahead never narrows the gap
+        assert_eq!(allowed_gap(-5_000), INITIAL_RATING_GAP);
+        assert_eq!(allowed_gap(500), 55.0);
+        assert_eq!(allowed_gap(1_000), 60.0);
+        assert_eq!(allowed_gap(AI_FALLBACK_AFTER_MS - 1), 349.99);
+        assert_eq!(allowed_gap(AI_FALLBACK_AFTER_MS), 350.0);
+    }
+
+    #[test]
+    fn fallback_picks_the_closest_computer_rating() {
+        for (rating, difficulty) in [
+            (0.0, Difficulty::Easy),
+            (1100.0, Difficulty::Easy),
+            (1299.9, Difficulty::Easy),
+            (1300.0, Difficulty::Easy), // Ties go to the easier side
+            (1300.1, Difficulty::Medium),
+            (1500.0, Difficulty::Medium),
+            (1700.0, Difficulty::Medium),
+            (1700.1, Difficulty::Hard),
+            (3000.0, Difficulty::Hard),
+        ] {
+            assert_eq!(fallback_difficulty(rating), difficulty, "{}", rating);
+        }
+    }
+}
@@ -0,0 +1,420 @@
+use actix_web::{web, HttpResponse, Responder};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use crate::{AppState, validate_token};
+use crate::xandzero::{GameMode, Seat, XandZeroGame};
+use crate::xandzero_ai::Difficulty;
+
+const DEFAULT_RATING: f64 = 1500.0;
+const DEFAULT_DEVIATION: f64 = 350.0;
+const DEFAULT_VOLATILITY: f64 = 0.06;
+/// Glicko-2 system constant: how quickly volatility may change
+const TAU: f64 = 0.5;
+/// Conversion factor between the Glicko and Glicko-2 scales
+const GLICKO2_SCALE: f64 = 173.7178;
+const CONVERGENCE_TOLERANCE: f64 = 0.000_001;
+/// Ratings less certain than this are provisional and left off the ranked leaderboard
+const PROVISIONAL_DEVIATION: f64 = 110.0;
+const TOP_N: i64 = 10;
+const HISTORY_LIMIT: i64 = 500;
+
+/// Glicko-2 rating on the familiar Glicko scale (1500 ± deviation)
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Glicko {
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+}
+
+impl Default for Glicko {
+    fn default() -> Self {
+        Self { rating: DEFAULT_RATING, deviation: DEFAULT_DEVIATION, volatility: DEFAULT_VOLATILITY }
+    }
+}
+
+impl Glicko {
+    /// Rating after one game against `opponent`, treating the game as its own rating period.
+    /// `score` is 1.0 for a win, 0.5 for a draw and 0.0 for a loss.
+    pub fn update(self, opponent: Glicko, score: f64) -> Glicko {
+        self.update_period(&[(opponent, score)])
+    }
+
+    /// Rating after a rating period of `games`, each an opponent and the score against them.
+    /// A period without games keeps rating and volatility but grows the deviation (step 6 of the paper).
+    pub fn update_period(self, games: &[(Glicko, f64)]) -> Glicko {
+        if games.is_empty() {
+            let phi = self.deviation / GLICKO2_SCALE;
+            let phi_star = (phi * phi + self.volatility * self.volatility).sqrt();
+            return Glicko { deviation: (GLICKO2_SCALE * phi_star).min(DEFAULT_DEVIATION), ..self };
+        }
+        let mu = (self.rating - DEFAULT_RATING) / GLICKO2_SCALE;
+        let phi = self.deviation / GLICKO2_SCALE;
+
+        // g(phi_j), E(mu, mu_j, phi_j) and the score of every game
+        let results: Vec<(f64, f64, f64)> = games.iter().map(|(opponent, score)| {
+            let mu_j = (opponent.rating - DEFAULT_RATING) / GLICKO2_SCALE;
+            let phi_j = opponent.deviation / GLICKO2_SCALE;
+            let g = 1.0 / (1.0 + 3.0 * phi_j * phi_j / (std::f64::consts::PI * std::f64::consts::PI)).sqrt();
+            let expected = 1.0 / (1.0 + (-g * (mu - mu_j)).exp());
+            (g, expected, *score)
+        }).collect();
+        let v = 1.0 / results.iter().map(|(g, e, _)| g * g * e * (1.0 - e)).sum::<f64>();
+        let improvement: f64 = results.iter().map(|(g, e, score)| g * (score - e)).sum();
+        let delta = v * improvement;
+
+        // New volatility via the Illinois algorithm
+        let a = (self.volatility * self.volatility).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            ex * (delta * delta - phi * phi - v - ex) / (2.0 * (phi * phi + v + ex).powi(2)) - (x - a) / (TAU * TAU)
+        };
+        let mut big_a = a;
+        let mut big_b = if delta * delta > phi * phi + v {
+            (delta * delta - phi * phi - v).ln()
+        } else {
+            let mut k = 1.0;
+            while f(a - k * TAU) < 0.0 {
+                k += 1.0;
+            }
+            a - k * TAU
+        };
+        let (mut f_a, mut f_b) = (f(big_a), f(big_b));
+        while (big_b - big_a).abs() > CONVERGENCE_TOLERANCE {
+            let big_c = big_a + (big_a - big_b) * f_a / (f_b - f_a);
+            let f_c = f(big_c);
+            if f_c * f_b <= 0.0 {
+                big_a = big_b;
+                f_a = f_b;
+            } else {
+                f_a /= 2.0;
+            }
+            big_b = big_c;
+            f_b = f_c;
+        }
+        let volatility = (big_a / 2.0).exp();
+
+        let phi_star = (phi * phi + volatility * volatility).sqrt();
+        let new_phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+        let new_mu = mu + new_phi * new_phi * improvement;
+
+        Glicko {
+            rating: GLICKO2_SCALE * new_mu + DEFAULT_RATING,
+            deviation: (GLICKO2_SCALE * new_phi).min(DEFAULT_DEVIATION),
+            volatility,
+        }
+    }
+}
+
+/// The computer plays at a fixed, well-established rating per difficulty and is never updated
+pub fn computer_rating(difficulty: Difficulty) -> Glicko {
+    let rating = match difficulty {
+        Difficulty::Easy => 1100.0,
+        Difficulty::Medium => 1500.0,
+        Difficulty::Hard => 1900.0,
+    };
+    Glicko { rating, deviation: 50.0, volatility: DEFAULT_VOLATILITY }
+}
+
+#[derive(sqlx::FromRow)]
+struct RatingRow {
+    user_id: String,
+    username: Option<String>,
+    rating: f64,
+    deviation: f64,
+    volatility: f64,
+    games_played: i32,
+}
+
+impl RatingRow {
+    fn glicko(&self) -> Glicko {
+        Glicko { rating: self.rating, deviation: self.deviation, volatility: self.volatility }
+    }
+}
+
+/// Player's current rating for a game and mode, or the starting rating if they have none yet
+pub async fn current_rating(db: &Pool<Postgres>, user_id: &str, game: &str, mode: GameMode) -> f64 {
+    sqlx::query_scalar("SELECT rating FROM skill_ratings WHERE user_id = $1 AND game_name = $2 AND game_mode = $3")
+        .bind(user_id)
+        .bind(game)
+        .bind(mode.as_str())
+        .fetch_optional(db)
+        .await
+        .unwrap_or(None)
+        .unwrap_or(DEFAULT_RATING)
+}
+
+/// Rating of one seat before the game, read under a row lock
+async fn locked_rating(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    seat: &Seat,
+    mode: GameMode,
+) -> Result<Glicko, sqlx::Error> {
+    let row: Option<RatingRow> = sqlx::query_as(
+        "SELECT user_id, username, rating, deviation, volatility, games_played FROM skill_ratings
+         WHERE user_id = $1 AND game_name = 'xandzero' AND game_mode = $2
+         FOR UPDATE"
+    )
+    .bind(&seat.user_id)
+    .bind(mode.as_str())
+    .fetch_optional(&mut **tx)
+    .await?;
+    Ok(row.map(|r| r.glicko()).unwrap_or_default())
+}
+
+async fn store_rating(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    seat: &Seat,
+    game: &XandZeroGame,
+    rating: Glicko,
+    score: f64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO skill_ratings (user_id, game_name, game_mode, username, rating, deviation, volatility, games_played)
+         VALUES ($1, 'xandzero', $2, $3, $4, $5, $6, 1)
+         ON CONFLICT (user_id, game_name, game_mode) DO UPDATE
+         SET username = EXCLUDED.username,
+             rating = EXCLUDED.rating,
+             deviation = EXCLUDED.deviation,
+             volatility = EXCLUDED.volatility,
+             games_played = skill_ratings.games_played + 1,
+             updated_at = NOW()"
+    )
+    .bind(&seat.user_id)
+    .bind(game.game_mode.as_str())
+    .bind(&seat.username)
+    .bind(rating.rating)
+    .bind(rating.deviation)
+    .bind(rating.volatility)
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query(
+        "INSERT INTO rating_history (user_id, game_name, game_mode, game_id, rating, deviation, result)
+         VALUES ($1, 'xandzero', $2, $3, $4, $5, $6)"
+    )
+    .bind(&seat.user_id)
+    .bind(game.game_mode.as_str())
+    .bind(&game.game_id)
+    .bind(rating.rating)
+    .bind(rating.deviation)
+    .bind(score)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Score of `mark` in a finished game: 1 for a win, 0.5 for a draw, 0 for a loss
+fn result_for(game: &XandZeroGame, mark: &str) -> Option<f64> {
+    match game.winner.as_deref()? {
+        "Draw" => Some(0.5),
+        w if w == mark => Some(1.0),
+        _ => Some(0.0),
+    }
+}
+
+async fn update_ratings(data: &AppState, game: &XandZeroGame) -> Result<(), sqlx::Error> {
+    let (Some(x), Some(x_score)) = (game.seat("X"), result_for(game, "X")) else { return Ok(()) };
+    let mut tx = data.db.begin().await?;
+
+    match game.seat("O") {
+        Some(o) => {
+            // Lock both rows in a fixed order so two finishing games cannot deadlock
+            let (x_before, o_before) = if x.user_id <= o.user_id {
+                let x_before = locked_rating(&mut tx, &x, game.game_mode).await?;
+                (x_before, locked_rating(&mut tx, &o, game.game_mode).await?)
+            } else {
+                let o_before = locked_rating(&mut tx, &o, game.game_mode).await?;
+                (locked_rating(&mut tx, &x, game.game_mode).await?, o_before)
+            };
+            store_rating(&mut tx, &x, game, x_before.update(o_before, x_score), x_score).await?;
+            store_rating(&mut tx, &o, game, o_before.update(x_before, 1.0 - x_score), 1.0 - x_score).await?;
+        }
+        None => {
+            let before = locked_rating(&mut tx, &x, game.game_mode).await?;
+            let after = before.update(computer_rating(game.difficulty), x_score);
+            store_rating(&mut tx, &x, game, after, x_score).await?;
+        }
+    }
+
+    tx.commit().await
+}
+
+/// Updates the skill ratings of a finished game's human players.
+/// Ratings are updated as each game ends, so every game is its own one-game rating period; `update_period`
+/// rates longer periods but nothing batches games into them yet.
+/// Games against the computer rate the player against the difficulty's fixed rating.
+pub async fn record_game(data: &AppState, game: &XandZeroGame) {
+    if let Err(e) = update_ratings(data, game).await {
+        eprintln!("Failed to update ratings for game {}: {}", game.game_id, e);
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RatingQuery {
+    pub game: Option<String>,
+    pub mode: Option<GameMode>,
+}
+
+/// One row of the ranked leaderboard
+#[derive(Serialize)]
+pub struct RatedEntry {
+    pub rank: i64,
+    pub user_id: String,
+    pub username: String,
+    pub rating: f64,
+    pub deviation: f64,
+    pub games_played: i32,
+}
+
+/// Skill-based leaderboard, separate from the points leaderboard.
+/// Only established ratings are listed; new players appear once their deviation settles.
+#[actix_web::get("/ratings/leaderboard")]
+pub async fn get_rated_leaderboard(
+    data: web::Data<AppState>,
+    query: web::Query<RatingQuery>,
+) -> impl Responder {
+    let game = query.game.clone().unwrap_or_else(|| "xandzero".to_string());
+    let mode = query.mode.unwrap_or(GameMode::Normal);
+
+    let rows: Vec<RatingRow> = match sqlx::query_as(
+        "SELECT user_id, username, rating, deviation, volatility, games_played FROM skill_ratings
+         WHERE game_name = $1 AND game_mode = $2 AND deviation <= $3
+         ORDER BY rating DESC, user_id
+         LIMIT $4"
+    )
+    .bind(&game)
+    .bind(mode.as_str())
+    .bind(PROVISIONAL_DEVIATION)
+    .bind(TOP_N)
+    .fetch_all(&data.db)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("DB error: {}", e)),
+    };
+
+    let entries: Vec<RatedEntry> = rows
+        .into_iter()
+        .enumerate()
+        .map(|(i, row)| RatedEntry {
+            rank: i as i64 + 1,
+            user_id: row.user_id,
+            username: row.username.unwrap_or_else(|| "Anonymous".to_string()),
+            rating: row.rating.round(),
+            deviation: row.deviation.round(),
+            games_played: row.games_played,
+        })
+        .collect();
+
+    HttpResponse::Ok().json(entries)
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+struct HistoryPoint {
+    game_id: Option<String>,
+    rating: f64,
+    deviation: f64,
+    result: f64,
+    recorded_at: Option<chrono::NaiveDateTime>,
+}
+
+/// The authenticated player's rating and its history (oldest first) for graphs
+#[actix_web::get("/ratings/me")]
+pub async fn get_my_rating(
+    data: web::Data<AppState>,
+    auth: BearerAuth,
+    query: web::Query<RatingQuery>,
+) -> impl Responder {
+    let claims = match validate_token(auth.token(), &data.oidc_jwks_uri).await {
+        Ok(c) => c,
+        Err(r) => return r,
+    };
+    let game = query.game.clone().unwrap_or_else(|| "xandzero".to_string());
+    let mode = query.mode.unwrap_or(GameMode::Normal);
+
+    let current: Option<RatingRow> = match sqlx::query_as(
+        "SELECT user_id, username, rating, deviation, volatility, games_played FROM skill_ratings
+         WHERE user_id = $1 AND game_name = $2 AND game_mode = $3"
+    )
+    .bind(&claims.sub)
+    .bind(&game)
+    .bind(mode.as_str())
+    .fetch_optional(&data.db)
+    .await
+    {
+        Ok(row) => row,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("DB error: {}", e)),
+    };
+
+    let history: Vec<HistoryPoint> = match sqlx::query_as(
+        "SELECT game_id, rating, deviation, result, recorded_at FROM (
+             SELECT id, game_id, rating, deviation, result, recorded_at FROM rating_history
+             WHERE user_id = $1 AND game_name = $2 AND game_mode = $3
+             ORDER BY id DESC
+             LIMIT $4
+         ) recent ORDER BY id"
+    )
+    .bind(&claims.sub)
+    .bind(&game)
+    .bind(mode.as_str())
+    .bind(HISTORY_LIMIT)
+    .fetch_all(&data.db)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("DB error: {}", e)),
+    };
+
+    let rating = current.as_ref().map(|r| r.glicko()).unwrap_or_default();
+    HttpResponse::Ok().json(serde_json::json!({
+        "rating": rating.rating.round(),
+        "deviation": rating.deviation.round(),
+        "volatility": rating.volatility,
+        "games_played": current.map_or(0, |r| r.games_played),
+        "provisional": rating.deviation > PROVISIONAL_DEVIATION,
+        "history": history,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glicko(rating: f64, deviation: f64) -> Glicko {
+        Glicko { rating, deviation, volatility: DEFAULT_VOLATILITY }
+    }
+
+    #[test]
+    fn matches_the_worked_example_of_the_glicko2_paper() {
+        let player = glicko(1500.0, 200.0);
+        let rated = player.update_period(&[
+            (glicko(1400.0, 30.0), 1.0),
+            (glicko(1550.0, 100.0), 0.0),
+            (glicko(1700.0, 300.0), 0.0),
+        ]);
+        assert!((rated.rating - 1464.06).abs() < 0.01, "{:?}", rated);
+        assert!((rated.deviation - 151.52).abs() < 0.01, "{:?}", rated);
+        assert!((rated.volatility - 0.05999).abs() < 0.00001, "{:?}", rated);
+    }
+
+    #[test]
+    fn single_games_are_one_game_periods() {
+        let player = glicko(1500.0, 200.0);
+        let opponent = glicko(1400.0, 30.0);
+        assert_eq!(player.update(opponent, 1.0), player.update_period(&[(opponent, 1.0)]));
+    }
+
+    #[test]
+    fn idle_periods_only_grow_the_deviation() {
+        let player = glicko(1500.0, 200.0);
+        let idle = player.update_period(&[]);
+        assert_eq!((idle.rating, idle.volatility), (player.rating, player.volatility));
+        let expected = ((200.0_f64 / GLICKO2_SCALE).powi(2) + player.volatility.powi(2)).sqrt() * GLICKO2_SCALE;
+        assert!((idle.deviation - expected).abs() < 1e-9);
+        assert!(idle.deviation > player.deviation);
+
+        // Never less certain than a new player
+        assert_eq!(glicko(1500.0, DEFAULT_DEVIATION).update_period(&[]).deviation, DEFAULT_DEVIATION);
+    }
+}
@@ -0,0 +1,296 @@
+use actix_web::{web, Error, HttpRequest, HttpResponse, Responder};
+use actix_ws::Message;
+use futures_util::StreamExt;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use crate::{AppState, authenticate_ws, xandzero_pvp};
+use crate::xandzero::XandZeroGame;
+
+/// Further spectators of a game are turned away
+pub const MAX_SPECTATORS_PER_GAME: i64 = 50;
+/// Live entries must be refreshed within this time or they drop out of the list
+pub const DRAGONBALL_LIVE_TTL_SECS: u64 = 30;
+const XANDZERO_LIVE_TTL_SECS: u64 = 10 * 60;
+/// Counters left behind by crashed instances expire on their own
+const SPECTATOR_COUNT_TTL_SECS: i64 = 6 * 60 * 60;
+const LIVE_GAMES: [&str; 2] = ["dragonball", "xandzero"];
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LivePlayer {
+    pub user_id: String,
+    pub username: String,
+    pub score: i64, // Dragonball: the current run; X-and-Zero: leaderboard total
+}
+
+/// A game in progress that can be watched, stored under `live:{game}:{game_id}`
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LiveGame {
+    pub game: String,
+    pub game_id: String,
+    pub players: Vec<LivePlayer>,
+    pub started_at_ms: i64,
+    #[serde(default)]
+    pub spectators: i64,
+}
+
+impl LiveGame {
+    fn best_score(&self) -> i64 {
+        self.players.iter().map(|p| p.score).max().unwrap_or(0)
+    }
+}
+
+/// Set of the ids of a game's live entries
+fn index_key(game: &str) -> String {
+    format!("live:{}", game)
+}
+
+fn live_key(game: &str, game_id: &str) -> String {
+    format!("live:{}:{}", game, game_id)
+}
+
+fn spectators_key(game: &str, game_id: &str) -> String {
+    format!("spectators:{}:{}", game, game_id)
+}
+
+/// Valkey pub/sub channel carrying every serialized `GameState` of a dragonball run
+pub fn dragonball_channel(game_id: &str) -> String {
+    format!("dragonball:game:{}:events", game_id)
+}
+
+/// Lists a game as live, or refreshes its entry
+pub async fn publish_live(con: &mut redis::aio::Connection, entry: &LiveGame, ttl_secs: u64) -> Result<(), redis::RedisError> {
+    let json = serde_json::to_string(entry).unwrap_or_default();
+    redis::pipe()
+        .set_ex(live_key(&entry.game, &entry.game_id), json, ttl_secs)
+        .sadd(index_key(&entry.game), &entry.game_id)
+        .query_async(con)
+        .await
+}
+
+pub async fn remove_live(con: &mut redis::aio::Connection, game: &str, game_id: &str) -> Result<(), redis::RedisError> {
+    redis::pipe()
+        .del(live_key(game, game_id))
+        .srem(index_key(game), game_id)
+        .query_async(con)
+        .await
+}
+
+/// Keeps the live list in step with an X-and-Zero game: listed while it is running, removed once finished.
+/// Games against the computer and player-versus-player games still waiting for an opponent list one player.
+pub async fn track_xandzero(con: &mut redis::aio::Connection, game: &XandZeroGame) {
+    if game.winner.is_some() {
+        let _ = remove_live(con, "xandzero", &game.game_id).await;
+        return;
+    }
+
+    let mut players = Vec::new();
+    for mark in ["X", "O"] {
+        if let Some(seat) = game.seat(mark) {
+            let score: Option<i64> = con.zscore("leaderboard:xandzero", &seat.user_id).await.unwrap_or(None);
+            players.push(LivePlayer { user_id: seat.user_id, username: seat.username, score: score.unwrap_or(0) });
+        }
+    }
+    let entry = LiveGame {
+        game: "xandzero".to_string(),
+        game_id: game.game_id.clone(),
+        players,
+        started_at_ms: game.created_at_ms,
+        spectators: 0,
+    };
+    let _ = publish_live(con, &entry, XANDZERO_LIVE_TTL_SECS).await;
+}
+
+/// Ids in a game's live index, including entries whose refresh lapsed
+pub async fn live_ids(con: &mut redis::aio::Connection, game: &str) -> Result<Vec<String>, redis::RedisError> {
+    con.smembers(index_key(game)).await
+}
+
+/// Live games of one kind. Entries whose refresh lapsed are dropped from the index on the way.
+async fn list_live(con: &mut redis::aio::Connection, game: &str) -> Result<Vec<LiveGame>, redis::RedisError> {
+    let ids = live_ids(con, game).await?;
+    let mut games = Vec::new();
+    for id in ids {
+        let raw: Option<String> = con.get(live_key(game, &id)).await?;
+        match raw.and_then(|json| serde_json::from_str::<LiveGame>(&json).ok()) {
+            Some(mut entry) => {
+                entry.spectators = con.get::<_, Option<i64>>(spectators_key(game, &id)).await?.unwrap_or(0).max(0);
+                games.push(entry);
+            }
+            None => {
+                let _: () = con.srem(index_key(game), &id).await?;
+            }
+        }
+    }
+    Ok(games)
+}
+
+/// Takes a spectator slot. Returns false if the game is full.
+async fn take_slot(con: &mut redis::aio::Connection, game: &str, game_id: &str) -> Result<bool, redis::RedisError> {
+    let key = spectators_key(game, game_id);
+    let count: i64 = con.incr(&key, 1).await?;
+    let _: () = con.expire(&key, SPECTATOR_COUNT_TTL_SECS).await?;
+    if count > MAX_SPECTATORS_PER_GAME {
+        let _: i64 = con.decr(&key, 1).await?;
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+async fn release_slot(redis_client: &redis::Client, game: &str, game_id: &str) {
+    if let Ok(mut con) = redis_client.get_async_connection().await {
+        let _: Result<i64, redis::RedisError> = con.decr(spectators_key(game, game_id), 1).await;
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LiveQuery {
+    pub game: Option<String>,
+}
+
+/// Games that can be watched right now, the one with the highest scoring player first
+#[actix_web::get("/live")]
+pub async fn get_live_games(
+    data: web::Data<AppState>,
+    query: web::Query<LiveQuery>,
+) -> impl Responder {
+    let mut con = match data.redis_client.get_async_connection().await {
+        Ok(con) => con,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("Redis error: {}", e)),
+    };
+
+    let games: Vec<&str> = match query.game.as_deref() {
+        Some(game) if LIVE_GAMES.contains(&game) => vec![game],
+        Some(_) => return HttpResponse::BadRequest().body("Unknown game"),
+        None => LIVE_GAMES.to_vec(),
+    };
+
+    let mut live = Vec::new();
+    for game in games {
+        match list_live(&mut con, game).await {
+            Ok(entries) => live.extend(entries),
+            Err(e) => return HttpResponse::InternalServerError().body(format!("Redis error: {}", e)),
+        }
+    }
+    live.sort_by(|a, b| b.best_score().cmp(&a.best_score()).then_with(|| a.started_at_ms.cmp(&b.started_at_ms)));
+    HttpResponse::Ok().json(live)
+}
+
+/// Relays every message published on `channel` into the connection's channel
+pub fn relay_channel(redis_client: redis::Client, channel: String, tx: mpsc::Sender<String>) -> JoinHandle<()> {
+    actix_web::rt::spawn(async move {
+        let Ok(con) = redis_client.get_async_connection().await else { return };
+        let mut pubsub = con.into_pubsub();
+        if pubsub.subscribe(&channel).await.is_err() {
+            return;
+        }
+        let mut messages = pubsub.on_message();
+        while let Some(msg) = messages.next().await {
+            let Ok(payload) = msg.get_payload::<String>() else { continue };
+            if tx.send(payload).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// Read-only websocket for one live game. `relay` forwards the game's events into the given sender;
+/// anything the spectator sends other than ping and close is ignored.
+async fn spectate<F>(
+    req: &HttpRequest,
+    stream: web::Payload,
+    data: &AppState,
+    game: &'static str,
+    game_id: String,
+    relay: F,
+) -> Result<HttpResponse, Error>
+where
+    F: FnOnce(mpsc::Sender<String>) -> JoinHandle<()>,
+{
+    if let Err(res) = authenticate_ws(req, data).await {
+        return Ok(res);
+    }
+
+    let mut con = match data.redis_client.get_async_connection().await {
+        Ok(con) => con,
+        Err(e) => return Ok(HttpResponse::InternalServerError().body(format!("Redis error: {}", e))),
+    };
+    match con.exists::<_, bool>(live_key(game, &game_id)).await {
+        Ok(true) => {}
+        Ok(false) => return Ok(HttpResponse::NotFound().body("Game is not live")),
+        Err(e) => return Ok(HttpResponse::InternalServerError().body(format!("Redis error: {}", e))),
+    }
+    match take_slot(&mut con, game, &game_id).await {
+        Ok(true) => {}
+        Ok(false) => return Ok(HttpResponse::ServiceUnavailable().body("Too many spectators for this game")),
+        Err(e) => return Ok(HttpResponse::InternalServerError().body(format!("Redis error: {}", e))),
+    }
+
+    let (res, mut session, mut msg_stream) = match actix_ws::handle(req, stream) {
+        Ok(parts) => parts,
+        Err(e) => {
+            release_slot(&data.redis_client, game, &game_id).await;
+            return Err(e);
+        }
+    };
+
+    let (tx, mut rx) = mpsc::channel::<String>(32);
+    let relay = relay(tx);
+    let redis_client = data.redis_client.clone();
+
+    actix_web::rt::spawn(async move {
+        loop {
+            tokio::select! {
+                msg = msg_stream.next() => {
+                    match msg {
+                        Some(Ok(Message::Ping(bytes))) if session.pong(&bytes).await.is_err() => break,
+                        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                        _ => {}
+                    }
+                }
+                event = rx.recv() => {
+                    let Some(event) = event else { break };
+                    if session.text(event).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        relay.abort();
+        release_slot(&redis_client, game, &game_id).await;
+        let _ = session.close(None).await;
+    });
+
+    Ok(res)
+}
+
+/// Watch an X-and-Zero game: receives the same `state` events as its players
+pub async fn xandzero_spectate(
+    req: HttpRequest,
+    stream: web::Payload,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let game_id = path.into_inner();
+    let redis_client = data.redis_client.clone();
+    let relay_id = game_id.clone();
+    spectate(&req, stream, &data, "xandzero", game_id, move |tx| {
+        xandzero_pvp::subscribe_to_game(redis_client, relay_id, tx)
+    })
+    .await
+}
+
+/// Watch a dragonball run: receives every serialized `GameState` the player receives
+pub async fn dragonball_spectate(
+    req: HttpRequest,
+    stream: web::Payload,
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let game_id = path.into_inner();
+    let redis_client = data.redis_client.clone();
+    let channel = dragonball_channel(&game_id);
+    spectate(&req, stream, &data, "dragonball", game_id, move |tx| relay_channel(redis_client, channel, tx)).await
+}
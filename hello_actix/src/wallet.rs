@@ -0,0 +1,275 @@
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use crate::{AppState, validate_admin_token, validate_token};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+/// Longest accepted idempotency key. Keys are stored with a `spend:` or `refund:spend:` prefix in a 100 character column.
+pub const MAX_IDEMPOTENCY_KEY_LEN: usize = 64;
+
+/// Kind of a ledger entry. Earnings, refunds and grants add to the balance, spends take from it.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LedgerKind {
+    Earn,
+    Spend,
+    Refund,
+    Grant,
+}
+
+impl LedgerKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            LedgerKind::Earn => "earn",
+            LedgerKind::Spend => "spend",
+            LedgerKind::Refund => "refund",
+            LedgerKind::Grant => "grant",
+        }
+    }
+
+    fn sign(self) -> i32 {
+        if self == LedgerKind::Spend { -1 } else { 1 }
+    }
+}
+
+#[derive(Debug)]
+pub enum WalletError {
+    InsufficientFunds,
+    Database(sqlx::Error),
+}
+
+impl std::fmt::Display for WalletError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WalletError::InsufficientFunds => write!(f, "Insufficient points"),
+            WalletError::Database(e) => write!(f, "DB error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for WalletError {
+    fn from(e: sqlx::Error) -> Self {
+        WalletError::Database(e)
+    }
+}
+
+/// A ledger entry about to be written
+pub struct Entry<'a> {
+    pub user_id: &'a str,
+    pub kind: LedgerKind,
+    pub amount: i32, // Always positive; the kind decides the direction
+    pub reason: &'a str,
+    pub reference: Option<&'a str>, // e.g. the game the entry belongs to
+    pub idempotency_key: Option<&'a str>,
+}
+
+/// Balance after an entry. `replayed` is set when the idempotency key was already used
+/// and nothing new was written; `reference` is then the one of the entry that used it.
+pub struct Posted {
+    pub balance: i32,
+    pub replayed: bool,
+    pub reference: Option<String>,
+}
+
+/// Appends an entry to the ledger and moves the balance in one transaction.
+/// The wallet row is locked first, so the balance check, the idempotency check and the write
+/// cannot interleave with another request for the same user.
+pub async fn post(db: &Pool<Postgres>, entry: Entry<'_>) -> Result<Posted, WalletError> {
+    let mut tx = db.begin().await?;
+
+    sqlx::query("INSERT INTO wallets (user_id, balance) VALUES ($1, 0) ON CONFLICT (user_id) DO NOTHING")
+        .bind(entry.user_id)
+        .execute(&mut *tx)
+        .await?;
+    let balance: i32 = sqlx::query_scalar("SELECT balance FROM wallets WHERE user_id = $1 FOR UPDATE")
+        .bind(entry.user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    if let Some(key) = entry.idempotency_key {
+        let seen: Option<(i64, Option<String>)> = sqlx::query_as(
+            "SELECT id, reference FROM wallet_ledger WHERE user_id = $1 AND idempotency_key = $2"
+        )
+        .bind(entry.user_id)
+        .bind(key)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some((_, reference)) = seen {
+            tx.rollback().await?;
+            return Ok(Posted { balance, replayed: true, reference });
+        }
+    }
+
+    let new_balance = balance + entry.kind.sign() * entry.amount.max(0);
+    if new_balance < 0 {
+        tx.rollback().await?;
+        return Err(WalletError::InsufficientFunds);
+    }
+
+    sqlx::query("UPDATE wallets SET balance = $1, updated_at = NOW() WHERE user_id = $2")
+        .bind(new_balance)
+        .bind(entry.user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        "INSERT INTO wallet_ledger (user_id, kind, amount, balance_after, reason, reference, idempotency_key)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)"
+    )
+    .bind(entry.user_id)
+    .bind(entry.kind.as_str())
+    .bind(entry.kind.sign() * entry.amount.max(0))
+    .bind(new_balance)
+    .bind(entry.reason)
+    .bind(entry.reference)
+    .bind(entry.idempotency_key)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(Posted { balance: new_balance, replayed: false, reference: entry.reference.map(str::to_string) })
+}
+
+/// Reason of the entry written with an idempotency key, if there is one
+pub async fn reason_of(db: &Pool<Postgres>, user_id: &str, idempotency_key: &str) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT reason FROM wallet_ledger WHERE user_id = $1 AND idempotency_key = $2")
+        .bind(user_id)
+        .bind(idempotency_key)
+        .fetch_optional(db)
+        .await
+}
+
+pub fn valid_idempotency_key(key: &str) -> bool {
+    !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LEN
+}
+
+/// Value of the `Idempotency-Key` request header, if any. Empty or overlong keys are answered with 400.
+pub fn idempotency_key(req: &HttpRequest) -> Result<Option<String>, HttpResponse> {
+    match req.headers().get("Idempotency-Key").and_then(|v| v.to_str().ok()) {
+        Some(key) if !valid_idempotency_key(key) => Err(HttpResponse::BadRequest()
+            .body(format!("Idempotency-Key must be 1-{} characters", MAX_IDEMPOTENCY_KEY_LEN))),
+        key => Ok(key.map(str::to_string)),
+    }
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+struct LedgerRow {
+    id: i64,
+    kind: String,
+    amount: i32,
+    balance_after: i32,
+    reason: String,
+    reference: Option<String>,
+    created_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Deserialize)]
+pub struct WalletQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// The authenticated player's balance and transaction history, most recent first
+#[actix_web::get("/wallet")]
+pub async fn get_wallet(
+    data: web::Data<AppState>,
+    auth: BearerAuth,
+    query: web::Query<WalletQuery>,
+) -> impl Responder {
+    let claims = match validate_token(auth.token(), &data.oidc_jwks_uri).await {
+        Ok(c) => c,
+        Err(r) => return r,
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    let balance: i32 = match sqlx::query_scalar("SELECT balance FROM wallets WHERE user_id = $1")
+        .bind(&claims.sub)
+        .fetch_optional(&data.db)
+        .await
+    {
+        Ok(balance) => balance.unwrap_or(0),
+        Err(e) => return HttpResponse::InternalServerError().body(format!("DB error: {}", e)),
+    };
+
+    let transactions: Vec<LedgerRow> = match sqlx::query_as(
+        "SELECT id, kind, amount, balance_after, reason, reference, created_at FROM wallet_ledger
+         WHERE user_id = $1
+         ORDER BY id DESC
+         LIMIT $2 OFFSET $3"
+    )
+    .bind(&claims.sub)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(&data.db)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("DB error: {}", e)),
+    };
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "balance": balance,
+        "transactions": transactions,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct GrantRequest {
+    pub user_id: String,
+    pub amount: i32,
+    pub reason: Option<String>,
+}
+
+/// Admin endpoint adding points to a player's wallet. Honours `Idempotency-Key` like spends do.
+#[actix_web::post("/admin/wallet/grant")]
+pub async fn grant_points(
+    data: web::Data<AppState>,
+    auth: BearerAuth,
+    http_req: HttpRequest,
+    req: web::Json<GrantRequest>,
+) -> impl Responder {
+    if let Err(r) = validate_admin_token(auth.token(), &data.oidc_jwks_uri).await {
+        return r;
+    }
+    if req.amount <= 0 {
+        return HttpResponse::BadRequest().body("Amount must be positive");
+    }
+
+    let key = match idempotency_key(&http_req) {
+        Ok(key) => key,
+        Err(r) => return r,
+    };
+    let result = post(&data.db, Entry {
+        user_id: &req.user_id,
+        kind: LedgerKind::Grant,
+        amount: req.amount,
+        reason: req.reason.as_deref().unwrap_or("admin grant"),
+        reference: None,
+        idempotency_key: key.as_deref(),
+    })
+    .await;
+
+    match result {
+        Ok(posted) => HttpResponse::Ok().json(serde_json::json!({
+            "balance": posted.balance,
+            "replayed": posted.replayed,
+        })),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixed_keys_fit_the_ledger_column() {
+        let longest = format!("refund:spend:{}", "k".repeat(MAX_IDEMPOTENCY_KEY_LEN));
+        assert!(longest.len() <= 100);
+        assert!(valid_idempotency_key(&"k".repeat(MAX_IDEMPOTENCY_KEY_LEN)));
+        assert!(!valid_idempotency_key(&"k".repeat(MAX_IDEMPOTENCY_KEY_LEN + 1)));
+        assert!(!valid_idempotency_key(""));
+    }
+}
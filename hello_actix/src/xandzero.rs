@@ -1,255 +1,1243 @@
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
 use serde::{Deserialize, Serialize};
-use rand::Rng;
 use redis::AsyncCommands;
-use crate::{AppState, validate_token};
+use crate::{AppState, achievements, leaderboard, ratings, spectate, validate_token, xandzero_records};
+use crate::wallet::{self, Entry, LedgerKind, WalletError};
+use crate::xandzero_ai::{self, Difficulty};
+use crate::xandzero_powerups::{BlockedCell, PowerUp, PowerUpAction, PowerUpUse};
 
-/// Request structure from the frontend for playing a move
+/// Live games expire from Valkey if abandoned
+const GAME_TTL_SECS: u64 = 24 * 60 * 60;
+const MIN_BOARD_SIDE: usize = 3;
+const MAX_BOARD_SIDE: usize = 19;
+/// Time a player-versus-player participant has to move before forfeiting
+pub const TURN_TIME_MS: i64 = 30_000;
+/// Stands in for a cell blocked against the side choosing a move
+pub const BLOCKED_CELL: &str = "#";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum GameMode {
+    Normal,
+    SuddenDeath, // Each side keeps at most `win_length` marks; the oldest vanishes when one more is placed
+}
+
+impl GameMode {
+    /// Name used in storage keys and database rows
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GameMode::Normal => "normal",
+            GameMode::SuddenDeath => "sudden_death",
+        }
+    }
+}
+
+/// How a finished game ended
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    Line,
+    Draw,
+    Timeout,
+    Resign,
+}
+
+/// One step of a game, in the order it happened. Kept with the game and persisted for replays.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum GameAction {
+    Place { mark: String, index: usize },
+    /// Sudden death removed the side's oldest mark
+    Expire { mark: String, index: usize },
+    /// Power-ups, by a player or by the computer
+    Erase { mark: String, index: usize, by_computer: bool },
+    Swap { mark: String, index: usize, target: usize, by_computer: bool },
+    Block { mark: String, index: usize, by_computer: bool },
+    DoubleMove { mark: String, by_computer: bool },
+    Shield { mark: String, index: usize, by_computer: bool },
+    Resign { mark: String },
+    Timeout { mark: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ActionRecord {
+    pub at_ms: i64, // Unix millis
+    #[serde(flatten)]
+    pub action: GameAction,
+}
+
+/// A human participant
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Seat {
+    pub user_id: String,
+    pub username: String,
+}
+
+/// Request to create a new game against the computer. Board size defaults to classic 3x3.
 #[derive(Serialize, Deserialize)]
-pub struct XandZeroRequest {
-    pub board: Vec<String>,     // Length 9 array representing the 3x3 grid
-    pub move_history: Option<Vec<usize>>, // List of move indices in order (for Sudden Death)
-    pub game_mode: String,      // "normal" or "sudden_death"
-    pub used_power_up: bool,    // If true, user wants to use "Erase" power-up
-    pub erase_index: Option<usize>, // Index to erase if using power-up
+pub struct NewGameRequest {
+    pub game_mode: GameMode,
+    pub difficulty: Option<Difficulty>,
+    pub rows: Option<usize>,
+    pub cols: Option<usize>,
+    pub win_length: Option<usize>,
+}
+
+/// A single move: the only thing the client may send is the cell it wants
+#[derive(Serialize, Deserialize)]
+pub struct MoveRequest {
+    pub index: usize,
+}
+
+/// Server-held game state, stored in Valkey under `xandzero:game:{game_id}`
+#[derive(Serialize, Deserialize, Clone)]
+pub struct XandZeroGame {
+    pub game_id: String,
+    pub owner_id: String,             // Plays X
+    pub owner_name: String,
+    #[serde(default)]
+    pub opponent: Option<Seat>,       // Plays O in player-versus-player games; None while waiting or against the computer
+    #[serde(default)]
+    pub pvp: bool,
+    #[serde(default)]
+    pub board_spec: BoardSpec,
+    pub board: Vec<String>,           // Row-major, rows * cols cells
+    pub move_history: Vec<usize>,     // Cell indices of the marks on the board, oldest first
+    pub game_mode: GameMode,
+    #[serde(default)]
+    pub difficulty: Difficulty,
+    pub turn: String,                 // "X" or "O"
+    pub winner: Option<String>,       // "X", "O" or "Draw" once finished
+    #[serde(default)]
+    pub finish_reason: Option<FinishReason>,
+    #[serde(default)]
+    pub turn_deadline_ms: Option<i64>, // Unix millis; player-versus-player only
+    #[serde(default)]
+    pub rematch_offered_by: Option<String>,
+    #[serde(default)]
+    pub rematch_game_id: Option<String>,
+    #[serde(default)]
+    pub actions: Vec<ActionRecord>,
+    #[serde(default)]
+    pub created_at_ms: i64,
+    #[serde(default)]
+    pub power_up_uses: Vec<PowerUpUse>,
+    #[serde(default)]
+    pub shielded: Vec<usize>,          // Cells whose marks cannot be erased or swapped
+    #[serde(default)]
+    pub blocked: Option<BlockedCell>,
+    #[serde(default)]
+    pub double_move: Option<String>,   // Mark whose next placement keeps the turn
 }
 
 /// Response returned to the frontend after processing a turn
 #[derive(Serialize, Deserialize)]
 pub struct XandZeroResponse {
+    pub game_id: String,
+    pub players: Vec<Seat>, // X first, then O for player-versus-player games
+    pub pvp: bool,
+    pub board_spec: BoardSpec,
     pub board: Vec<String>,
     pub move_history: Vec<usize>,
+    pub game_mode: GameMode,
+    pub difficulty: Difficulty,
+    pub turn: String,
     pub winner: Option<String>,
+    pub finish_reason: Option<FinishReason>,
+    pub turn_deadline_ms: Option<i64>,
+    pub rematch_offered_by: Option<String>,
+    pub rematch_game_id: Option<String>,
+    pub power_up_uses: Vec<PowerUpUse>,
+    pub shielded: Vec<usize>,
+    pub blocked: Option<BlockedCell>,
+    pub double_move: Option<String>,
     pub score_increment: i32,
     pub power_up_used_by_ai: bool,
     pub ai_erase_index: Option<usize>,
+    pub ai_power_up: Option<PowerUpAction>,
+}
+
+impl XandZeroResponse {
+    pub fn from_game(game: &XandZeroGame) -> Self {
+        let mut players = vec![Seat { user_id: game.owner_id.clone(), username: game.owner_name.clone() }];
+        players.extend(game.opponent.clone());
+        Self {
+            game_id: game.game_id.clone(),
+            players,
+            pvp: game.pvp,
+            board_spec: game.board_spec,
+            board: game.board.clone(),
+            move_history: game.move_history.clone(),
+            game_mode: game.game_mode,
+            difficulty: game.difficulty,
+            turn: game.turn.clone(),
+            winner: game.winner.clone(),
+            finish_reason: game.finish_reason,
+            turn_deadline_ms: game.turn_deadline_ms,
+            rematch_offered_by: game.rematch_offered_by.clone(),
+            rematch_game_id: game.rematch_game_id.clone(),
+            power_up_uses: game.power_up_uses.clone(),
+            shielded: game.shielded.clone(),
+            blocked: game.blocked.clone(),
+            double_move: game.double_move.clone(),
+            score_increment: 0,
+            power_up_used_by_ai: false,
+            ai_erase_index: None,
+            ai_power_up: None,
+        }
+    }
+}
+
+/// Messages published on a game's Valkey channel and relayed to websocket clients
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GameEvent {
+    State { game: XandZeroResponse },
+}
+
+/// Errors from loading, validating or storing a game
+#[derive(Debug)]
+pub enum GameError {
+    NotFound,
+    Rule(&'static str),
+    InsufficientPoints,
+    Conflict,
+    Refunded(String),  // A paid power-up was rejected and its points returned; holds the refund's reason
+    RefundPending,     // A paid power-up was rejected but the refund could not be written yet
+    Computer(String), // The computer's turn failed to run
+    Redis(redis::RedisError),
+    Database(sqlx::Error),
+}
+
+impl std::fmt::Display for GameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GameError::NotFound => write!(f, "Game not found"),
+            GameError::Rule(msg) => write!(f, "{}", msg),
+            GameError::InsufficientPoints => write!(f, "Insufficient points for power-up"),
+            GameError::Conflict => write!(f, "Game was updated by another request, please retry"),
+            GameError::Refunded(reason) => write!(f, "{}; the points were refunded", reason),
+            GameError::RefundPending => write!(f, "Power-up was rejected and its refund is pending, retry with the same idempotency key"),
+            GameError::Computer(e) => write!(f, "Computer move failed: {}", e),
+            GameError::Redis(e) => write!(f, "Redis error: {}", e),
+            GameError::Database(e) => write!(f, "DB error: {}", e),
+        }
+    }
+}
+
+impl From<redis::RedisError> for GameError {
+    fn from(e: redis::RedisError) -> Self {
+        GameError::Redis(e)
+    }
+}
+
+impl From<WalletError> for GameError {
+    fn from(e: WalletError) -> Self {
+        match e {
+            WalletError::InsufficientFunds => GameError::InsufficientPoints,
+            WalletError::Database(e) => GameError::Database(e),
+        }
+    }
+}
+
+impl GameError {
+    pub fn into_response(self) -> HttpResponse {
+        match self {
+            GameError::NotFound => HttpResponse::NotFound().body(self.to_string()),
+            GameError::Rule(_) | GameError::InsufficientPoints | GameError::Refunded(_) => HttpResponse::BadRequest().body(self.to_string()),
+            GameError::Conflict => HttpResponse::Conflict().body(self.to_string()),
+            GameError::RefundPending => HttpResponse::ServiceUnavailable().body(self.to_string()),
+            GameError::Computer(_) | GameError::Redis(_) | GameError::Database(_) => HttpResponse::InternalServerError().body(self.to_string()),
+        }
+    }
+}
+
+fn now_ms() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+/// The opposing side's mark
+pub fn other_mark(mark: &str) -> &'static str {
+    if mark == "X" { "O" } else { "X" }
+}
+
+impl XandZeroGame {
+    /// New game against the computer; the player is X and moves first
+    pub fn new(owner_id: String, owner_name: String, board_spec: BoardSpec, game_mode: GameMode, difficulty: Difficulty) -> Self {
+        Self {
+            game_id: uuid::Uuid::new_v4().to_string(),
+            owner_id,
+            owner_name,
+            opponent: None,
+            pvp: false,
+            board_spec,
+            board: vec![String::new(); board_spec.cells()],
+            move_history: Vec::new(),
+            game_mode,
+            difficulty,
+            turn: "X".to_string(),
+            winner: None,
+            finish_reason: None,
+            turn_deadline_ms: None,
+            rematch_offered_by: None,
+            rematch_game_id: None,
+            actions: Vec::new(),
+            created_at_ms: now_ms(),
+            power_up_uses: Vec::new(),
+            shielded: Vec::new(),
+            blocked: None,
+            double_move: None,
+        }
+    }
+
+    /// New player-versus-player game. Without an opponent it waits for someone to join.
+    pub fn new_pvp(x: Seat, o: Option<Seat>, board_spec: BoardSpec, game_mode: GameMode) -> Self {
+        let mut game = Self::new(x.user_id, x.username, board_spec, game_mode, Difficulty::default());
+        game.pvp = true;
+        game.opponent = o;
+        game.start_turn_clock();
+        game
+    }
+
+    /// The mark a user plays in this game, if they are a participant
+    pub fn mark_of(&self, user_id: &str) -> Option<&'static str> {
+        if self.owner_id == user_id {
+            Some("X")
+        } else if self.opponent.as_ref().is_some_and(|o| o.user_id == user_id) {
+            Some("O")
+        } else {
+            None
+        }
+    }
+
+    /// Seat playing `mark`; None for the computer
+    pub fn seat(&self, mark: &str) -> Option<Seat> {
+        match mark {
+            "X" => Some(Seat { user_id: self.owner_id.clone(), username: self.owner_name.clone() }),
+            _ => self.opponent.clone(),
+        }
+    }
+
+    /// Second player takes the O seat of a waiting game
+    pub fn join(&mut self, seat: Seat) -> Result<(), &'static str> {
+        if !self.pvp || self.winner.is_some() {
+            return Err("Game cannot be joined");
+        }
+        if self.mark_of(&seat.user_id).is_some() {
+            return Ok(()); // Already seated (e.g. reconnect)
+        }
+        if self.opponent.is_some() {
+            return Err("Game is full");
+        }
+        self.opponent = Some(seat);
+        self.start_turn_clock();
+        Ok(())
+    }
+
+    fn start_turn_clock(&mut self) {
+        self.turn_deadline_ms = (self.pvp && self.opponent.is_some() && self.winner.is_none())
+            .then(|| now_ms() + TURN_TIME_MS);
+    }
+
+    /// Rejects actions on finished games and out-of-turn actions
+    fn check_turn(&self, mark: &str) -> Result<(), &'static str> {
+        if self.winner.is_some() {
+            return Err("Game is already finished");
+        }
+        if self.pvp && self.opponent.is_none() {
+            return Err("Waiting for an opponent");
+        }
+        if self.turn != mark {
+            return Err("Not your turn");
+        }
+        Ok(())
+    }
+
+    fn check_index(&self, index: usize) -> Result<(), &'static str> {
+        if index >= self.board.len() {
+            return Err("Cell index out of range");
+        }
+        Ok(())
+    }
+
+    /// Maximum marks per side in sudden death
+    pub fn mark_limit(&self) -> Option<usize> {
+        (self.game_mode == GameMode::SuddenDeath).then_some(self.board_spec.win_length)
+    }
+
+    fn record(&mut self, action: GameAction) {
+        self.actions.push(ActionRecord { at_ms: now_ms(), action });
+    }
+
+    fn finish(&mut self, winner: String, reason: FinishReason) {
+        self.winner = Some(winner);
+        self.finish_reason = Some(reason);
+        self.turn_deadline_ms = None;
+    }
+
+    /// Places `mark` on an empty cell, applies sudden-death expiry, passes the turn and checks for a winner
+    pub fn place_mark(&mut self, mark: &str, index: usize) -> Result<(), &'static str> {
+        self.check_turn(mark)?;
+        self.check_index(index)?;
+        if !self.board[index].is_empty() {
+            return Err("Cell is already taken");
+        }
+        if self.blocked.as_ref().is_some_and(|b| b.against == mark && b.index == index) {
+            return Err("Cell is blocked this turn");
+        }
+
+        self.board[index] = mark.to_string();
+        self.move_history.push(index);
+        self.record(GameAction::Place { mark: mark.to_string(), index });
+
+        // Sudden Death: one mark over the limit removes the side's oldest mark
+        if let Some(limit) = self.mark_limit() {
+            let own_moves: Vec<usize> = self.move_history.iter().cloned().filter(|&i| self.board[i] == mark).collect();
+            if own_moves.len() > limit {
+                self.remove_mark(own_moves[0]);
+                self.record(GameAction::Expire { mark: mark.to_string(), index: own_moves[0] });
+            }
+        }
+
+        if self.double_move.as_deref() == Some(mark) {
+            self.double_move = None;
+        } else {
+            self.pass_turn(mark);
+        }
+        match winner_after_move(&self.board, &self.board_spec, index) {
+            Some(w) if w == "Draw" => self.finish(w, FinishReason::Draw),
+            Some(w) => self.finish(w, FinishReason::Line),
+            None => self.start_turn_clock(),
+        }
+        Ok(())
+    }
+
+    /// Hands the turn to the other side. A block against `mark` has now been served.
+    fn pass_turn(&mut self, mark: &str) {
+        self.turn = other_mark(mark).to_string();
+        if self.blocked.as_ref().is_some_and(|b| b.against == mark) {
+            self.blocked = None;
+        }
+    }
+
+    fn remove_mark(&mut self, index: usize) {
+        self.board[index] = String::new();
+        self.move_history.retain(|&i| i != index);
+        self.shielded.retain(|&i| i != index);
+    }
+
+    /// Own marks placed so far; cooldowns are counted in these
+    fn placements_by(&self, mark: &str) -> usize {
+        self.actions.iter()
+            .filter(|a| matches!(&a.action, GameAction::Place { mark: m, .. } if m == mark))
+            .count()
+    }
+
+    /// Fails if the side playing `mark` has used up `power_up` for this game or it is still cooling down
+    pub fn check_power_up_available(&self, mark: &str, power_up: PowerUp) -> Result<(), &'static str> {
+        let spec = power_up.spec();
+        let uses: Vec<&PowerUpUse> = self.power_up_uses.iter()
+            .filter(|u| u.mark == mark && u.power_up == power_up)
+            .collect();
+        if uses.len() >= spec.max_per_game {
+            return Err("Power-up limit for this game reached");
+        }
+        if uses.last().is_some_and(|last| self.placements_by(mark) < last.placements + spec.cooldown_turns) {
+            return Err("Power-up is cooling down");
+        }
+        Ok(())
+    }
+
+    /// Uses a power-up on the side's own turn. None of them passes the turn.
+    pub fn use_power_up(&mut self, mark: &str, action: PowerUpAction, by_computer: bool) -> Result<(), &'static str> {
+        self.check_turn(mark)?;
+        self.check_power_up_available(mark, action.kind())?;
+        let other = other_mark(mark);
+
+        match action {
+            PowerUpAction::Erase { index } => {
+                self.check_index(index)?;
+                if self.board[index].is_empty() {
+                    return Err("Cell is already empty");
+                }
+                if self.shielded.contains(&index) {
+                    return Err("Mark is shielded");
+                }
+                self.remove_mark(index);
+                self.record(GameAction::Erase { mark: mark.to_string(), index, by_computer });
+            }
+            PowerUpAction::Swap { index, target } => {
+                self.check_index(index)?;
+                self.check_index(target)?;
+                if self.board[index] != mark || self.board[target] != other {
+                    return Err("Swap needs one of your marks and one of your opponent's");
+                }
+                if self.shielded.contains(&index) || self.shielded.contains(&target) {
+                    return Err("Mark is shielded");
+                }
+                // Marks keep their age, so sudden-death expiry follows them to their new cells
+                self.board.swap(index, target);
+                let position = |cell| self.move_history.iter().position(|&i| i == cell);
+                if let (Some(a), Some(b)) = (position(index), position(target)) {
+                    self.move_history.swap(a, b);
+                }
+                self.record(GameAction::Swap { mark: mark.to_string(), index, target, by_computer });
+                if self.board_spec.completes_line(&self.board, target) {
+                    self.finish(mark.to_string(), FinishReason::Line);
+                } else if self.board_spec.completes_line(&self.board, index) {
+                    self.finish(other.to_string(), FinishReason::Line);
+                }
+            }
+            PowerUpAction::Block { index } => {
+                self.check_index(index)?;
+                if !self.board[index].is_empty() {
+                    return Err("Only empty cells can be blocked");
+                }
+                if self.board.iter().filter(|s| s.is_empty()).count() < 2 {
+                    return Err("Cannot block the last empty cell");
+                }
+                self.blocked = Some(BlockedCell { index, against: other.to_string() });
+                self.record(GameAction::Block { mark: mark.to_string(), index, by_computer });
+            }
+            PowerUpAction::DoubleMove => {
+                if self.double_move.is_some() {
+                    return Err("Double move is already active");
+                }
+                self.double_move = Some(mark.to_string());
+                self.record(GameAction::DoubleMove { mark: mark.to_string(), by_computer });
+            }
+            PowerUpAction::Shield { index } => {
+                self.check_index(index)?;
+                if self.board[index] != mark {
+                    return Err("You can only shield your own marks");
+                }
+                if self.shielded.contains(&index) {
+                    return Err("Mark is already shielded");
+                }
+                self.shielded.push(index);
+                self.record(GameAction::Shield { mark: mark.to_string(), index, by_computer });
+            }
+        }
+
+        let placements = self.placements_by(mark);
+        self.power_up_uses.push(PowerUpUse { mark: mark.to_string(), power_up: action.kind(), placements });
+        Ok(())
+    }
+
+    /// The board as seen by `mark` choosing a cell: a cell blocked against it shows as `BLOCKED_CELL`
+    pub fn playable_board(&self, mark: &str) -> Vec<String> {
+        let mut board = self.board.clone();
+        if let Some(blocked) = self.blocked.as_ref().filter(|b| b.against == mark) {
+            board[blocked.index] = BLOCKED_CELL.to_string();
+        }
+        board
+    }
+
+    /// The side playing `mark` gives up
+    pub fn resign(&mut self, mark: &str) -> Result<(), &'static str> {
+        if self.winner.is_some() {
+            return Err("Game is already finished");
+        }
+        let other = other_mark(mark);
+        self.record(GameAction::Resign { mark: mark.to_string() });
+        self.finish(other.to_string(), FinishReason::Resign);
+        Ok(())
+    }
+
+    /// Forfeits the side to move if its turn timer ran out. Returns true if the game ended.
+    pub fn check_timeout(&mut self, now: i64) -> bool {
+        match self.turn_deadline_ms {
+            Some(deadline) if self.winner.is_none() && now >= deadline => {
+                let other = other_mark(&self.turn);
+                self.record(GameAction::Timeout { mark: self.turn.clone() });
+                self.finish(other.to_string(), FinishReason::Timeout);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Records a rematch offer. Returns true once both players have asked for one.
+    pub fn offer_rematch(&mut self, user_id: &str) -> Result<bool, &'static str> {
+        if !self.pvp || self.winner.is_none() {
+            return Err("Rematch is only available after a finished player-versus-player game");
+        }
+        if self.rematch_game_id.is_some() {
+            return Err("Rematch already started");
+        }
+        match &self.rematch_offered_by {
+            Some(by) if by != user_id => Ok(true),
+            _ => {
+                self.rematch_offered_by = Some(user_id.to_string());
+                Ok(false)
+            }
+        }
+    }
+
+    /// Fresh game between the same players with marks swapped, so the other side opens
+    pub fn rematch(&self) -> Option<XandZeroGame> {
+        let o = self.opponent.clone()?;
+        let x = Seat { user_id: self.owner_id.clone(), username: self.owner_name.clone() };
+        Some(XandZeroGame::new_pvp(o, Some(x), self.board_spec, self.game_mode))
+    }
+
+    /// Plays the computer's turn (O). Returns the power-up the AI used first, if any.
+    pub fn play_computer_turn(&mut self) -> Option<PowerUpAction> {
+        if self.pvp || self.winner.is_some() || self.turn != "O" {
+            return None;
+        }
+
+        // The computer follows the same power-up rules as players, it just does not pay
+        let power_up = xandzero_ai::choose_power_up(self)
+            .filter(|&action| self.use_power_up("O", action, true).is_ok());
+
+        // Loops twice after a double move
+        while self.winner.is_none() && self.turn == "O" {
+            let placed = xandzero_ai::choose_move(self).is_some_and(|mv| self.place_mark("O", mv).is_ok());
+            if !placed {
+                self.double_move = None;
+                self.pass_turn("O");
+            }
+        }
+        power_up
+    }
+
+    /// Points awarded to the side playing `mark` for the finished game, scaled by difficulty
+    pub fn score_for(&self, mark: &str) -> i32 {
+        let base = match self.winner.as_deref() {
+            Some("Draw") => 20,
+            Some(w) if w == mark => 100,
+            Some(_) => 10,
+            None => 0,
+        };
+        base * self.difficulty.reward_percent() / 100
+    }
+
+    /// Leaderboard points for every human participant of a finished game
+    pub fn rewards(&self) -> Vec<(Seat, i32)> {
+        ["X", "O"].iter()
+            .filter_map(|mark| self.seat(mark).map(|seat| (seat, self.score_for(mark))))
+            .collect()
+    }
 }
 
-/// Utility function to check if the current board state has a winner
-pub fn check_winner(board: &[String]) -> Option<String> {
-    let lines = [
-        [0, 1, 2], [3, 4, 5], [6, 7, 8], // Rows
-        [0, 3, 6], [1, 4, 7], [2, 5, 8], // Cols
-        [0, 4, 8], [2, 4, 6],           // Diagonals
-    ];
+/// Board dimensions and the number of equal marks in a row needed to win
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct BoardSpec {
+    pub rows: usize,
+    pub cols: usize,
+    pub win_length: usize,
+}
 
-    for line in lines {
-        if !board[line[0]].is_empty() && board[line[0]] == board[line[1]] && board[line[0]] == board[line[2]] {
-            return Some(board[line[0]].clone());
+impl Default for BoardSpec {
+    /// Classic 3x3, three in a row
+    fn default() -> Self {
+        Self { rows: 3, cols: 3, win_length: 3 }
+    }
+}
+
+impl BoardSpec {
+    pub fn new(rows: usize, cols: usize, win_length: usize) -> Result<Self, &'static str> {
+        if !(MIN_BOARD_SIDE..=MAX_BOARD_SIDE).contains(&rows) || !(MIN_BOARD_SIDE..=MAX_BOARD_SIDE).contains(&cols) {
+            return Err("Board sides must be between 3 and 19");
+        }
+        if win_length < MIN_BOARD_SIDE || win_length > rows.max(cols) {
+            return Err("Win length must be at least 3 and fit on the board");
         }
+        Ok(Self { rows, cols, win_length })
     }
 
+    /// Validated spec from optional request fields; missing fields fall back to the classic board
+    pub fn from_options(rows: Option<usize>, cols: Option<usize>, win_length: Option<usize>) -> Result<Self, &'static str> {
+        let default_spec = Self::default();
+        Self::new(
+            rows.unwrap_or(default_spec.rows),
+            cols.unwrap_or(default_spec.cols),
+            win_length.unwrap_or(default_spec.win_length),
+        )
+    }
+
+    pub fn cells(&self) -> usize {
+        self.rows * self.cols
+    }
+
+    pub fn center(&self) -> usize {
+        (self.rows / 2) * self.cols + self.cols / 2
+    }
+
+    /// Length of the longest straight run through `index` if that cell held `value`.
+    /// Only the four lines through `index` are walked, so this is O(win_length) rather than a full scan.
+    pub fn longest_run<T: PartialEq>(&self, cells: &[T], index: usize, value: &T) -> usize {
+        let (row, col) = ((index / self.cols) as isize, (index % self.cols) as isize);
+        let mut longest = 0;
+        for (dr, dc) in [(0isize, 1isize), (1, 0), (1, 1), (1, -1)] {
+            let mut count = 1;
+            for sign in [1, -1] {
+                let (mut r, mut c) = (row + dr * sign, col + dc * sign);
+                while r >= 0 && c >= 0 && r < self.rows as isize && c < self.cols as isize
+                    && cells[r as usize * self.cols + c as usize] == *value
+                {
+                    count += 1;
+                    r += dr * sign;
+                    c += dc * sign;
+                }
+            }
+            longest = longest.max(count);
+        }
+        longest
+    }
+
+    /// True if the mark at `index` is part of a winning line
+    pub fn completes_line<T: PartialEq>(&self, cells: &[T], index: usize) -> bool {
+        self.longest_run(cells, index, &cells[index]) >= self.win_length
+    }
+}
+
+/// Winner after a mark was placed at `index`: only lines through the last move can be new
+pub fn winner_after_move(board: &[String], spec: &BoardSpec, index: usize) -> Option<String> {
+    if !board[index].is_empty() && spec.completes_line(board, index) {
+        return Some(board[index].clone());
+    }
     if board.iter().all(|s| !s.is_empty()) {
         return Some("Draw".to_string());
     }
-
     None
 }
 
-/// Simple AI logic for the computer move (O)
-/// Priority: 1. Win if possible, 2. Block user if they are winning, 3. Take center, 4. Random available move
-pub fn get_computer_move(board: &[String]) -> Option<usize> {
-    // 1. Try to win
-    for i in 0..9 {
-        if board[i].is_empty() {
-            let mut test_board = board.to_vec();
-            test_board[i] = "O".to_string();
-            if check_winner(&test_board) == Some("O".to_string()) {
-                return Some(i);
+fn game_key(game_id: &str) -> String {
+    format!("xandzero:game:{}", game_id)
+}
+
+/// Valkey pub/sub channel carrying `GameEvent`s for a game
+pub fn events_channel(game_id: &str) -> String {
+    format!("xandzero:game:{}:events", game_id)
+}
+
+/// Stores a newly created game and lists it as live, so it can be watched before the first move
+pub async fn store_new_game(redis_client: &redis::Client, game: &XandZeroGame) -> Result<(), redis::RedisError> {
+    let mut con = redis_client.get_async_connection().await?;
+    let json = serde_json::to_string(game).unwrap_or_default();
+    let _: () = con.set_ex(game_key(&game.game_id), json, GAME_TTL_SECS).await?;
+    spectate::track_xandzero(&mut con, game).await;
+    Ok(())
+}
+
+/// Reads a game without locking it
+pub async fn fetch_game(redis_client: &redis::Client, game_id: &str) -> Result<Option<XandZeroGame>, redis::RedisError> {
+    let mut con = redis_client.get_async_connection().await?;
+    let raw: Option<String> = con.get(game_key(game_id)).await?;
+    Ok(raw.and_then(|json| serde_json::from_str(&json).ok()))
+}
+
+/// Loads a game and WATCHes its key so that the following `save_game` fails if another request changed it
+async fn load_game(con: &mut redis::aio::Connection, game_id: &str) -> Result<Option<XandZeroGame>, redis::RedisError> {
+    let key = game_key(game_id);
+    redis::cmd("WATCH").arg(&key).query_async::<_, ()>(con).await?;
+    let raw: Option<String> = con.get(&key).await?;
+    Ok(raw.and_then(|json| serde_json::from_str(&json).ok()))
+}
+
+/// Stores the game atomically. Returns false if the game was modified since `load_game` (concurrent move).
+async fn save_game(con: &mut redis::aio::Connection, game: &XandZeroGame) -> Result<bool, redis::RedisError> {
+    let json = serde_json::to_string(game).unwrap_or_default();
+    let committed: Option<(String,)> = redis::pipe()
+        .atomic()
+        .set_ex(game_key(&game.game_id), json, GAME_TTL_SECS)
+        .query_async(con)
+        .await?;
+    Ok(committed.is_some())
+}
+
+/// Broadcasts the current state to everyone subscribed to the game
+async fn publish_state(con: &mut redis::aio::Connection, game: &XandZeroGame) {
+    let event = GameEvent::State { game: XandZeroResponse::from_game(game) };
+    let json = serde_json::to_string(&event).unwrap_or_default();
+    let _: Result<(), redis::RedisError> = con.publish(events_channel(&game.game_id), json).await;
+}
+
+/// Adds the finished game's points to a player's leaderboard total and wallet
+async fn award_points(data: &AppState, game_id: &str, seat: &Seat, score_increment: i32) {
+    let _ = sqlx::query(
+        "INSERT INTO leaderboard (user_id, game_name, username, score, games_played)
+         VALUES ($1, 'xandzero', $2, $3, 1)
+         ON CONFLICT (user_id, game_name) DO UPDATE
+         SET score = leaderboard.score + $3, games_played = leaderboard.games_played + 1, updated_at = NOW()"
+    )
+    .bind(&seat.user_id)
+    .bind(&seat.username)
+    .bind(score_increment)
+    .execute(&data.db)
+    .await;
+
+    if let Err(e) = leaderboard::record_scored_run(&data.db, "xandzero", &seat.user_id, &seat.username, score_increment).await {
+        eprintln!("Failed to record xandzero score of game {}: {}", game_id, e);
+    }
+
+    // Update Valkey
+    leaderboard::refresh_cached_score(&data.db, &data.redis_client, "xandzero", &seat.user_id).await;
+    achievements::record_rank(data, "xandzero", &seat.user_id).await;
+
+    // The key makes sure a game pays into the wallet at most once
+    let key = format!("earn:{}", game_id);
+    let earned = wallet::post(&data.db, Entry {
+        user_id: &seat.user_id,
+        kind: LedgerKind::Earn,
+        amount: score_increment,
+        reason: "xandzero game",
+        reference: Some(game_id),
+        idempotency_key: Some(&key),
+    })
+    .await;
+    if let Err(e) = earned {
+        eprintln!("Failed to credit wallet for game {}: {}", game_id, e);
+    }
+}
+
+/// Loads a game, runs `action` on it and stores the result atomically, then publishes the new state.
+/// Points are awarded only by the caller whose write finished the game, so each game pays out exactly once.
+pub async fn apply_to_game<F, T>(data: &AppState, game_id: &str, action: F) -> Result<(XandZeroGame, T), GameError>
+where
+    F: FnOnce(&mut XandZeroGame) -> Result<T, GameError>,
+{
+    update_game(data, game_id, action, false).await.map(|(game, result, _)| (game, result))
+}
+
+/// Like `apply_to_game`, then plays the computer's reply in the same write. The reply is searched on the
+/// blocking pool, so a hard move on a large board does not hold up the worker. Returns the power-up the computer used, if any.
+pub async fn apply_with_computer_reply<F, T>(data: &AppState, game_id: &str, action: F) -> Result<(XandZeroGame, T, Option<PowerUpAction>), GameError>
+where
+    F: FnOnce(&mut XandZeroGame) -> Result<T, GameError>,
+{
+    update_game(data, game_id, action, true).await
+}
+
+async fn update_game<F, T>(data: &AppState, game_id: &str, action: F, computer_replies: bool) -> Result<(XandZeroGame, T, Option<PowerUpAction>), GameError>
+where
+    F: FnOnce(&mut XandZeroGame) -> Result<T, GameError>,
+{
+    let mut con = data.redis_client.get_async_connection().await?;
+
+    let mut game = load_game(&mut con, game_id).await?.ok_or(GameError::NotFound)?;
+    let was_finished = game.winner.is_some();
+
+    let result = match action(&mut game) {
+        Ok(r) => r,
+        Err(e) => {
+            let _ = redis::cmd("UNWATCH").query_async::<_, ()>(&mut con).await;
+            return Err(e);
+        }
+    };
+
+    let mut ai_power_up = None;
+    if computer_replies {
+        match tokio::task::spawn_blocking(move || {
+            let power_up = game.play_computer_turn();
+            (game, power_up)
+        })
+        .await
+        {
+            Ok((played, power_up)) => {
+                game = played;
+                ai_power_up = power_up;
+            }
+            Err(e) => {
+                let _ = redis::cmd("UNWATCH").query_async::<_, ()>(&mut con).await;
+                return Err(GameError::Computer(e.to_string()));
             }
         }
     }
 
-    // 2. Block player
-    for i in 0..9 {
-        if board[i].is_empty() {
-            let mut test_board = board.to_vec();
-            test_board[i] = "X".to_string();
-            if check_winner(&test_board) == Some("X".to_string()) {
-                return Some(i);
+    if !save_game(&mut con, &game).await? {
+        return Err(GameError::Conflict);
+    }
+    publish_state(&mut con, &game).await;
+    spectate::track_xandzero(&mut con, &game).await;
+
+    if !was_finished && game.winner.is_some() {
+        for (seat, points) in game.rewards() {
+            award_points(data, &game.game_id, &seat, points).await;
+        }
+        ratings::record_game(data, &game).await;
+        xandzero_records::save_record(data, &game).await;
+        for mark in ["X", "O"] {
+            if let Some(seat) = game.seat(mark) {
+                achievements::record(data, &seat.user_id, &achievements::xandzero_progress(&game, mark)).await;
             }
         }
     }
 
-    // 3. Take center
-    if board[4].is_empty() {
-        return Some(4);
+    Ok((game, result, ai_power_up))
+}
+
+/// Charges the power-up to the player's wallet, then uses it. A rejected power-up is refunded.
+/// Retrying with the same idempotency key neither charges nor applies it again: the retry gets the current game,
+/// or the rejection if the spend was refunded. If the refund itself failed, the retry writes it.
+/// Client keys are kept apart from the server's own `earn:`/`refund:` keys and only replay for the same game.
+pub async fn paid_power_up(
+    data: &AppState,
+    user_id: &str,
+    game_id: &str,
+    action: PowerUpAction,
+    idempotency_key: Option<String>,
+) -> Result<XandZeroGame, GameError> {
+    if idempotency_key.as_deref().is_some_and(|k| !wallet::valid_idempotency_key(k)) {
+        return Err(GameError::Rule("Idempotency keys must be 1-64 characters"));
+    }
+    let spec = action.kind().spec();
+    let key = format!("spend:{}", idempotency_key.unwrap_or_else(|| uuid::Uuid::new_v4().to_string()));
+    let refund_key = format!("refund:{}", key);
+    let charge = wallet::post(&data.db, Entry {
+        user_id,
+        kind: LedgerKind::Spend,
+        amount: spec.cost,
+        reason: &format!("{} power-up", spec.power_up.as_str()),
+        reference: Some(game_id),
+        idempotency_key: Some(&key),
+    })
+    .await?;
+    if charge.replayed {
+        if charge.reference.as_deref() != Some(game_id) {
+            return Err(GameError::Rule("Idempotency key was already used for another purchase"));
+        }
+        if let Some(reason) = wallet::reason_of(&data.db, user_id, &refund_key).await.map_err(GameError::Database)? {
+            return Err(GameError::Refunded(reason));
+        }
+        let game = fetch_game(&data.redis_client, game_id).await?.ok_or(GameError::NotFound)?;
+        let mark = game.mark_of(user_id).ok_or(GameError::NotFound)?;
+        // Every spend on the game that was not refunded bought one of the player's uses; one more means
+        // an earlier attempt was rejected and its refund never written
+        let uses = game.power_up_uses.iter().filter(|u| u.mark == mark).count() as i64;
+        if unrefunded_spends(data, user_id, game_id).await.map_err(GameError::Database)? > uses {
+            let reason = format!("{} power-up rejected", spec.power_up.as_str());
+            refund_power_up(data, user_id, game_id, spec.cost, &reason, &refund_key).await?;
+            return Err(GameError::Refunded(reason));
+        }
+        return Ok(game);
     }
 
-    // 4. Random available
-    let available: Vec<usize> = board.iter().enumerate()
-        .filter(|(_, s)| s.is_empty())
-        .map(|(i, _)| i)
-        .collect();
-    
-    if available.is_empty() {
-        return None;
+    let result = apply_to_game(data, game_id, |game| {
+        let mark = game.mark_of(user_id).ok_or(GameError::NotFound)?;
+        game.use_power_up(mark, action, false).map_err(GameError::Rule)
+    })
+    .await;
+
+    match result {
+        Ok((game, _)) => Ok(game),
+        Err(e) => {
+            // The reason is what a retry with the same key is answered with
+            let reason: String = format!("{} power-up rejected: {}", spec.power_up.as_str(), e).chars().take(100).collect();
+            refund_power_up(data, user_id, game_id, spec.cost, &reason, &refund_key).await?;
+            Err(e)
+        }
     }
+}
+
+/// Returns the points of a rejected power-up. A failed refund is logged and reported as retryable.
+async fn refund_power_up(data: &AppState, user_id: &str, game_id: &str, cost: i32, reason: &str, refund_key: &str) -> Result<(), GameError> {
+    let refund = wallet::post(&data.db, Entry {
+        user_id,
+        kind: LedgerKind::Refund,
+        amount: cost,
+        reason,
+        reference: Some(game_id),
+        idempotency_key: Some(refund_key),
+    })
+    .await;
+    refund.map(|_| ()).map_err(|e| {
+        eprintln!("Failed to refund {} of {} on game {}: {}", refund_key, user_id, game_id, e);
+        GameError::RefundPending
+    })
+}
 
-    let mut rng = rand::thread_rng();
-    Some(available[rng.gen_range(0..available.len())])
+/// Power-up spends of a player on a game that were not refunded
+async fn unrefunded_spends(data: &AppState, user_id: &str, game_id: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT COUNT(*) FROM wallet_ledger spend
+         WHERE spend.user_id = $1 AND spend.reference = $2 AND spend.kind = 'spend'
+           AND NOT EXISTS (SELECT 1 FROM wallet_ledger refund
+                           WHERE refund.user_id = spend.user_id AND refund.idempotency_key = 'refund:' || spend.idempotency_key)"
+    )
+    .bind(user_id)
+    .bind(game_id)
+    .fetch_one(&data.db)
+    .await
 }
 
-/// Main game move endpoint. Handles user move, computer move, win detection, and leaderboard updates.
-#[actix_web::post("/xandzero/play")]
-pub async fn xandzero_play(
+/// Creates a new server-held game against the computer. The player (X) always moves first.
+#[actix_web::post("/xandzero/games")]
+pub async fn create_game(
     data: web::Data<AppState>,
     auth: BearerAuth,
-    req: web::Json<XandZeroRequest>,
+    req: web::Json<NewGameRequest>,
 ) -> impl Responder {
     let claims = match validate_token(auth.token(), &data.oidc_jwks_uri).await {
         Ok(c) => c,
         Err(r) => return r,
     };
 
-    let user_id = claims.sub;
     let username = claims.preferred_username.or(claims.name).unwrap_or_else(|| "Anonymous".to_string());
-    let mut board = req.board.clone();
-    let mut history = req.move_history.clone().unwrap_or_default();
-    let mut ai_used_power_up = false;
-    let mut ai_erase_idx = None;
-    // --- POWER-UP HANDLING (USER) ---
-    if req.used_power_up {
-        if let Some(idx) = req.erase_index {
-            // Check points (Cost: 50)
-            let user_points: i32 = sqlx::query_scalar("SELECT score FROM leaderboard WHERE user_id = $1 AND game_name = 'xandzero'")
-                .bind(&user_id)
-                .fetch_one(&data.db)
-                .await
-                .unwrap_or(0);
-
-            if user_points >= 50 {
-                // Erase mark and deduct points
-                if !board[idx].is_empty() {
-                    board[idx] = "".to_string();
-                    history.retain(|&i| i != idx);
-
-                    // Update DB
-                    let new_total = user_points - 50;
-                    let _ = sqlx::query("UPDATE leaderboard SET score = $1 WHERE user_id = $2 AND game_name = 'xandzero'")
-                        .bind(new_total)
-                        .bind(&user_id)
-                        .execute(&data.db)
-                        .await;
-                    
-                    // Update Redis (Valkey) immediately so leaderboard UI reflects deduction
-                    if let Ok(mut con) = data.redis_client.get_async_connection().await {
-                        let _: Result<(), redis::RedisError> = con.zadd("leaderboard:xandzero", &username, new_total).await;
-                    }
-                }
-            } else {
-                return HttpResponse::BadRequest().body("Insufficient points for power-up");
-            }
-
-            // Return immediately after power-up usage so user sees the board change
-            // and the AI doesn't immediately counter-move into the same spot.
-            let current_winner = check_winner(&board);
-            return HttpResponse::Ok().json(XandZeroResponse {
-                board,
-                move_history: history,
-                winner: current_winner,
-                score_increment: 0,
-                power_up_used_by_ai: false,
-                ai_erase_index: None,
-            });
-        }
-    }
-
-    // --- SUDDEN DEATH: MOVE REMOVAL (USER) ---
-    // If Sudden Death and User had > 3 moves just now
-    if req.game_mode == "sudden_death" {
-        let x_moves: Vec<usize> = history.iter().cloned().filter(|&i| board[i] == "X").collect();
-        if x_moves.len() > 3 {
-            let oldest = x_moves[0];
-            board[oldest] = "".to_string();
-            history.retain(|&i| i != oldest);
-        }
-    }
-
-    // --- AI TURN ---
-    let mut winner = check_winner(&board);
-    let mut score_increment = 0;
-
-    if winner.is_none() {
-        // AI Power-up check (20% chance if player has > 2 marks)
-        let player_marks = board.iter().filter(|&s| s == "X").count();
-        if player_marks >= 2 && rand::thread_rng().gen_bool(0.2) {
-            // AI erases a random player mark
-            let x_moves: Vec<usize> = board.iter().enumerate()
-                .filter(|(_, s)| s == &"X")
-                .map(|(i, _)| i)
-                .collect();
-            
-            if !x_moves.is_empty() {
-                let erase_idx = x_moves[rand::thread_rng().gen_range(0..x_moves.len())];
-                board[erase_idx] = "".to_string();
-                history.retain(|&i| i != erase_idx);
-                ai_used_power_up = true;
-                ai_erase_idx = Some(erase_idx);
-            }
-        }
-
-        // Computer move (O)
-        let computer_move = get_computer_move(&board);
-
-        if let Some(mv) = computer_move {
-            board[mv] = "O".to_string();
-            history.push(mv);
-            
-            // Sudden Death: Move removal (AI)
-            if req.game_mode == "sudden_death" {
-                let o_moves: Vec<usize> = history.iter().cloned().filter(|&i| board[i] == "O").collect();
-                if o_moves.len() > 3 {
-                    let oldest = o_moves[0];
-                    board[oldest] = "".to_string();
-                    history.retain(|&i| i != oldest);
+    let board_spec = match BoardSpec::from_options(req.rows, req.cols, req.win_length) {
+        Ok(spec) => spec,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let game = XandZeroGame::new(claims.sub, username, board_spec, req.game_mode, req.difficulty.unwrap_or_default());
+
+    if let Err(e) = store_new_game(&data.redis_client, &game).await {
+        return HttpResponse::InternalServerError().body(format!("Redis error: {}", e));
+    }
+
+    HttpResponse::Created().json(XandZeroResponse::from_game(&game))
+}
+
+/// Returns the current state of one of the player's games
+#[actix_web::get("/xandzero/games/{game_id}")]
+pub async fn get_game(
+    data: web::Data<AppState>,
+    auth: BearerAuth,
+    path: web::Path<String>,
+) -> impl Responder {
+    let claims = match validate_token(auth.token(), &data.oidc_jwks_uri).await {
+        Ok(c) => c,
+        Err(r) => return r,
+    };
+
+    match fetch_game(&data.redis_client, &path).await {
+        Ok(Some(game)) if game.mark_of(&claims.sub).is_some() => HttpResponse::Ok().json(XandZeroResponse::from_game(&game)),
+        Ok(_) => HttpResponse::NotFound().body("Game not found"),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Redis error: {}", e)),
+    }
+}
+
+/// Main game move endpoint. Validates the player's move, plays the computer's reply, detects the winner and awards points.
+#[actix_web::post("/xandzero/games/{game_id}/move")]
+pub async fn xandzero_play(
+    data: web::Data<AppState>,
+    auth: BearerAuth,
+    path: web::Path<String>,
+    req: web::Json<MoveRequest>,
+) -> impl Responder {
+    let claims = match validate_token(auth.token(), &data.oidc_jwks_uri).await {
+        Ok(c) => c,
+        Err(r) => return r,
+    };
+
+    // The computer replies in the same write
+    let result = apply_with_computer_reply(&data, &path, |game| {
+        let mark = game.mark_of(&claims.sub).ok_or(GameError::NotFound)?;
+        game.place_mark(mark, req.index).map_err(GameError::Rule)?;
+        Ok(mark)
+    })
+    .await;
+
+    match result {
+        Ok((game, mark, ai_power_up)) => {
+            let mut response = XandZeroResponse::from_game(&game);
+            response.score_increment = game.score_for(mark);
+            response.power_up_used_by_ai = ai_power_up.is_some();
+            response.ai_erase_index = match ai_power_up {
+                Some(PowerUpAction::Erase { index }) => Some(index),
+                _ => None,
+            };
+            response.ai_power_up = ai_power_up;
+            HttpResponse::Ok().json(response)
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Power-up endpoint. Costs wallet points according to the catalogue; the player keeps the turn.
+/// Send an `Idempotency-Key` header so a retried request is not charged twice.
+#[actix_web::post("/xandzero/games/{game_id}/power-up")]
+pub async fn xandzero_power_up(
+    data: web::Data<AppState>,
+    auth: BearerAuth,
+    http_req: HttpRequest,
+    path: web::Path<String>,
+    req: web::Json<PowerUpAction>,
+) -> impl Responder {
+    let claims = match validate_token(auth.token(), &data.oidc_jwks_uri).await {
+        Ok(c) => c,
+        Err(r) => return r,
+    };
+
+    let idempotency_key = match wallet::idempotency_key(&http_req) {
+        Ok(key) => key,
+        Err(r) => return r,
+    };
+    match paid_power_up(&data, &claims.sub, &path, *req, idempotency_key).await {
+        Ok(game) => HttpResponse::Ok().json(XandZeroResponse::from_game(&game)),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Erase power-up endpoint, kept for existing clients. Same as the power-up endpoint with `erase`.
+#[actix_web::post("/xandzero/games/{game_id}/erase")]
+pub async fn xandzero_erase(
+    data: web::Data<AppState>,
+    auth: BearerAuth,
+    http_req: HttpRequest,
+    path: web::Path<String>,
+    req: web::Json<MoveRequest>,
+) -> impl Responder {
+    let claims = match validate_token(auth.token(), &data.oidc_jwks_uri).await {
+        Ok(c) => c,
+        Err(r) => return r,
+    };
+
+    let action = PowerUpAction::Erase { index: req.index };
+    let idempotency_key = match wallet::idempotency_key(&http_req) {
+        Ok(key) => key,
+        Err(r) => return r,
+    };
+    match paid_power_up(&data, &claims.sub, &path, action, idempotency_key).await {
+        Ok(game) => HttpResponse::Ok().json(XandZeroResponse::from_game(&game)),
+        Err(e) => e.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The `len` cells of a straight line from (row, col) in direction (dr, dc)
+    fn line(spec: &BoardSpec, (row, col): (isize, isize), (dr, dc): (isize, isize), len: usize) -> Vec<usize> {
+        (0..len as isize).map(|i| ((row + dr * i) as usize) * spec.cols + (col + dc * i) as usize).collect()
+    }
+
+    fn board_with(spec: &BoardSpec, cells: &[usize]) -> Vec<String> {
+        let mut board = vec![String::new(); spec.cells()];
+        for &i in cells {
+            board[i] = "X".to_string();
+        }
+        board
+    }
+
+    #[test]
+    fn board_spec_rejects_bad_sizes_and_win_lengths() {
+        for (rows, cols, k) in [(2, 3, 3), (3, 2, 3), (20, 3, 3), (3, 20, 3), (3, 3, 2), (3, 3, 4), (3, 7, 8), (19, 19, 20)] {
+            assert!(BoardSpec::new(rows, cols, k).is_err(), "{}x{} k={}", rows, cols, k);
+        }
+        for (rows, cols, k) in [(3, 3, 3), (3, 7, 7), (7, 3, 7), (15, 15, 5), (19, 19, 19)] {
+            assert_eq!(BoardSpec::new(rows, cols, k), Ok(BoardSpec { rows, cols, win_length: k }));
+        }
+        assert_eq!(BoardSpec::from_options(None, None, None), Ok(BoardSpec::default()));
+    }
+
+    #[test]
+    fn wins_along_edges_and_both_diagonals() {
+        for spec in [BoardSpec::new(15, 15, 5).unwrap(), BoardSpec::new(3, 7, 3).unwrap()] {
+            let (last_row, last_col, k) = (spec.rows as isize - 1, spec.cols as isize - 1, spec.win_length);
+            let reach = k as isize - 1;
+            let lines = [
+                ((0, last_col - reach), (0, 1)),                // Top edge, ending in the corner
+                ((last_row, 0), (0, 1)),                        // Bottom edge
+                ((last_row - reach, 0), (1, 0)),                // Left edge, ending in the corner
+                ((0, last_col), (1, 0)),                        // Right edge
+                ((last_row - reach, last_col - reach), (1, 1)), // Diagonal into the bottom-right corner
+                ((0, last_col), (1, -1)),                       // Anti-diagonal from the top-right corner
+                ((last_row - reach, reach), (1, -1)),           // Anti-diagonal into the bottom-left corner
+            ];
+            for (start, dir) in lines {
+                let cells = line(&spec, start, dir, k);
+                let board = board_with(&spec, &cells);
+                for &i in &cells {
+                    assert_eq!(winner_after_move(&board, &spec, i).as_deref(), Some("X"), "{:?} {:?} {:?}", spec, start, dir);
                 }
+                let short = board_with(&spec, &cells[1..]);
+                assert_eq!(winner_after_move(&short, &spec, cells[1]), None, "{:?} {:?} {:?}", spec, start, dir);
             }
-            winner = check_winner(&board);
         }
     }
 
-    // --- SCORING & FINALIZATION ---
-    if let Some(w) = &winner {
-        if w == "X" {
-            score_increment = 100;
-        } else if w == "O" {
-            score_increment = 10;
-        } else if w == "Draw" {
-            score_increment = 20;
+    #[test]
+    fn runs_do_not_wrap_across_rows() {
+        for spec in [BoardSpec::new(15, 15, 5).unwrap(), BoardSpec::new(3, 7, 3).unwrap()] {
+            // Consecutive indices through the end of row 0 into row 1
+            let start = spec.cols - spec.win_length / 2 - 1;
+            let wrapped: Vec<usize> = (start..start + spec.win_length).collect();
+            let board = board_with(&spec, &wrapped);
+            for &i in &wrapped {
+                assert_eq!(winner_after_move(&board, &spec, i), None, "{:?} {:?}", spec, wrapped);
+            }
         }
 
-        // Update Leaderboard (Increment)
-        let _ = sqlx::query(
-            "INSERT INTO leaderboard (user_id, game_name, username, score) 
-             VALUES ($1, 'xandzero', $2, $3) 
-             ON CONFLICT (user_id, game_name) DO UPDATE SET score = leaderboard.score + $3"
-        )
-        .bind(&user_id)
-        .bind(&username)
-        .bind(score_increment)
-        .execute(&data.db)
-        .await;
-
-        // Update Valkey
-        let total_score: i32 = sqlx::query_scalar("SELECT score FROM leaderboard WHERE user_id = $1 AND game_name = 'xandzero'")
-            .bind(&user_id)
-            .fetch_one(&data.db)
-            .await
-            .unwrap_or(0);
-
-        if let Ok(mut con) = data.redis_client.get_async_connection().await {
-            let _: Result<(), redis::RedisError> = con.zadd("leaderboard:xandzero", &username, total_score).await;
-        }
-    }
-
-    HttpResponse::Ok().json(XandZeroResponse {
-        board,
-        move_history: history,
-        winner,
-        score_increment,
-        power_up_used_by_ai: ai_used_power_up,
-        ai_erase_index: ai_erase_idx,
-    })
+        // Diagonal steps of cols + 1 running off the right edge into column 0
+        let spec = BoardSpec::new(15, 15, 5).unwrap();
+        let diagonal: Vec<usize> = (0..spec.win_length).map(|i| spec.cols - 2 + i * (spec.cols + 1)).collect();
+        let board = board_with(&spec, &diagonal);
+        for &i in &diagonal {
+            assert_eq!(winner_after_move(&board, &spec, i), None, "{:?}", diagonal);
+        }
+    }
+
+    fn classic(difficulty: Difficulty) -> XandZeroGame {
+        XandZeroGame::new("u1".to_string(), "one".to_string(), BoardSpec::default(), GameMode::Normal, difficulty)
+    }
+
+    #[test]
+    fn turns_alternate_starting_with_x() {
+        let mut game = classic(Difficulty::Medium);
+        assert_eq!(game.place_mark("O", 0), Err("Not your turn"));
+        game.place_mark("X", 0).unwrap();
+        assert_eq!(game.turn, "O");
+        assert_eq!(game.place_mark("X", 1), Err("Not your turn"));
+        game.place_mark("O", 1).unwrap();
+        assert_eq!(game.turn, "X");
+        assert_eq!(game.move_history, vec![0, 1]);
+    }
+
+    #[test]
+    fn out_of_range_and_taken_cells_are_rejected() {
+        let mut game = classic(Difficulty::Medium);
+        assert_eq!(game.place_mark("X", 9), Err("Cell index out of range"));
+        game.place_mark("X", 4).unwrap();
+        assert_eq!(game.place_mark("O", 4), Err("Cell is already taken"));
+        assert_eq!(game.board[4], "X");
+        assert_eq!(game.turn, "O");
+    }
+
+    #[test]
+    fn finished_games_reject_further_actions() {
+        let mut game = classic(Difficulty::Medium);
+        for (x, o) in [(0, 3), (1, 4)] {
+            game.place_mark("X", x).unwrap();
+            game.place_mark("O", o).unwrap();
+        }
+        game.place_mark("X", 2).unwrap();
+        assert_eq!(game.winner.as_deref(), Some("X"));
+        assert_eq!(game.finish_reason, Some(FinishReason::Line));
+
+        let board = game.board.clone();
+        assert_eq!(game.place_mark("O", 5), Err("Game is already finished"));
+        assert_eq!(game.use_power_up("O", PowerUpAction::Erase { index: 0 }, true), Err("Game is already finished"));
+        assert_eq!(game.resign("O"), Err("Game is already finished"));
+        assert_eq!(game.play_computer_turn(), None);
+        assert!(!game.check_timeout(i64::MAX));
+        assert_eq!(game.board, board);
+        assert_eq!(game.winner.as_deref(), Some("X"));
+    }
+
+    #[test]
+    fn points_are_awarded_once_per_finished_game() {
+        // `apply_to_game` pays out only on the write that moves a game from unfinished to finished;
+        // every later action fails before changing it, so that transition happens once
+        let mut game = classic(Difficulty::Hard);
+        assert!(game.rewards().iter().all(|(_, points)| *points == 0));
+        let mut payouts = 0;
+        for (mark, index) in [("X", 0), ("O", 3), ("X", 1), ("O", 4), ("X", 2), ("O", 5), ("X", 6)] {
+            let was_finished = game.winner.is_some();
+            let _ = game.place_mark(mark, index);
+            if !was_finished && game.winner.is_some() {
+                payouts += 1;
+            }
+        }
+        assert_eq!(payouts, 1);
+        assert_eq!(game.rewards(), vec![(Seat { user_id: "u1".to_string(), username: "one".to_string() }, 200)]);
+
+        let mut lost = classic(Difficulty::Easy);
+        lost.resign("X").unwrap();
+        assert_eq!(lost.score_for("X"), 5);
+        assert_eq!(lost.score_for("O"), 50);
+    }
 }
@@ -0,0 +1,475 @@
+use serde::{Deserialize, Serialize};
+use rand::Rng;
+use crate::xandzero::{BoardSpec, XandZeroGame};
+use crate::xandzero_powerups::{PowerUp, PowerUpAction};
+
+/// Sudden-death games never fill the board, so the search needs a horizon
+const SUDDEN_DEATH_SEARCH_DEPTH: u32 = 10;
+/// Boards larger than 3x3 are searched to a fixed depth over the most promising candidates only
+const LARGE_BOARD_SEARCH_DEPTH: u32 = 4;
+const LARGE_BOARD_BEAM_WIDTH: usize = 8;
+const WIN_SCORE: i64 = 1_000_000_000_000_000;
+
+/// Computer opponent strength, chosen when the game is created
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Difficulty {
+    Easy,   // Random empty cell
+    #[default]
+    Medium, // Win / block / center / random heuristic
+    Hard,   // Minimax with alpha-beta pruning
+}
+
+impl Difficulty {
+    /// Percentage applied to the base reward of a finished game
+    pub fn reward_percent(self) -> i32 {
+        match self {
+            Difficulty::Easy => 50,
+            Difficulty::Medium => 100,
+            Difficulty::Hard => 200,
+        }
+    }
+}
+
+const EMPTY: u8 = 0;
+const X: u8 = 1;
+const O: u8 = 2;
+const BLOCKED: u8 = 3;
+
+/// Compact copy of the board used by the search
+#[derive(Clone)]
+struct SearchState {
+    spec: BoardSpec,
+    cells: Vec<u8>,
+    history: Vec<usize>,       // Marks on the board, oldest first (drives sudden-death expiry)
+    mark_limit: Option<usize>, // Sudden death: marks kept per side
+    last_move: Option<usize>,
+}
+
+impl SearchState {
+    /// A blocked cell stays unavailable for the whole search, whichever side it was blocked against
+    fn from_game(game: &XandZeroGame) -> Self {
+        let mut cells: Vec<u8> = game.board.iter().map(|s| match s.as_str() {
+            "X" => X,
+            "O" => O,
+            _ => EMPTY,
+        }).collect();
+        if let Some(blocked) = &game.blocked {
+            cells[blocked.index] = BLOCKED;
+        }
+        Self {
+            spec: game.board_spec,
+            cells,
+            history: game.move_history.clone(),
+            mark_limit: game.mark_limit(),
+            last_move: None,
+        }
+    }
+
+    /// Exhaustive search is only affordable on the classic board
+    fn is_small(&self) -> bool {
+        self.spec.cells() <= 9
+    }
+
+    /// Mirrors `XandZeroGame::place_mark`, including the removal of the side's oldest mark in sudden death
+    fn play(&mut self, index: usize, mark: u8) {
+        self.cells[index] = mark;
+        self.history.push(index);
+        if let Some(limit) = self.mark_limit {
+            let own: Vec<usize> = self.history.iter().cloned().filter(|&i| self.cells[i] == mark).collect();
+            if own.len() > limit {
+                self.cells[own[0]] = EMPTY;
+                self.history.retain(|&i| i != own[0]);
+            }
+        }
+        self.last_move = Some(index);
+    }
+
+    /// Only the last move can have completed a line
+    fn winner(&self) -> Option<u8> {
+        self.last_move
+            .filter(|&i| self.spec.completes_line(&self.cells, i))
+            .map(|i| self.cells[i])
+    }
+
+    /// Candidate moves in search order.
+    /// Small boards: every empty cell, nearest to the center first.
+    /// Large boards: empty cells next to existing marks, ranked by the longest run they make or block, cut to the beam width.
+    fn moves(&self) -> Vec<usize> {
+        let empty = (0..self.cells.len()).filter(|&i| self.cells[i] == EMPTY);
+        let center = self.spec.center();
+        let distance = |i: usize| {
+            let (r, c) = (i / self.spec.cols, i % self.spec.cols);
+            let (cr, cc) = (center / self.spec.cols, center % self.spec.cols);
+            r.abs_diff(cr).max(c.abs_diff(cc))
+        };
+
+        if self.is_small() {
+            let mut moves: Vec<usize> = empty.collect();
+            moves.sort_by_key(|&i| distance(i));
+            return moves;
+        }
+
+        if self.cells.iter().all(|&c| c == EMPTY) {
+            return vec![center];
+        }
+
+        let mut ranked: Vec<(usize, usize)> = empty
+            .filter(|&i| self.has_neighbour(i))
+            .map(|i| {
+                let run = self.spec.longest_run(&self.cells, i, &O).max(self.spec.longest_run(&self.cells, i, &X));
+                (i, run)
+            })
+            .collect();
+        ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| distance(a.0).cmp(&distance(b.0))));
+        ranked.into_iter().take(LARGE_BOARD_BEAM_WIDTH).map(|(i, _)| i).collect()
+    }
+
+    fn has_neighbour(&self, index: usize) -> bool {
+        let (row, col) = ((index / self.spec.cols) as isize, (index % self.spec.cols) as isize);
+        (-1..=1).any(|dr| (-1..=1).any(|dc| {
+            let (r, c) = (row + dr, col + dc);
+            (dr, dc) != (0, 0) && r >= 0 && c >= 0 && r < self.spec.rows as isize && c < self.spec.cols as isize
+                && self.cells[r as usize * self.spec.cols + c as usize] != EMPTY
+        }))
+    }
+
+    /// Static evaluation from O's point of view: every window of `win_length` cells holding
+    /// marks of only one side counts for that side, weighted by how full it is.
+    fn evaluate(&self) -> i64 {
+        let spec = &self.spec;
+        let k = spec.win_length as isize;
+        let mut score = 0i64;
+        for start in 0..self.cells.len() {
+            let (row, col) = ((start / spec.cols) as isize, (start % spec.cols) as isize);
+            for (dr, dc) in [(0isize, 1isize), (1, 0), (1, 1), (1, -1)] {
+                let (end_r, end_c) = (row + dr * (k - 1), col + dc * (k - 1));
+                if end_r < 0 || end_c < 0 || end_r >= spec.rows as isize || end_c >= spec.cols as isize {
+                    continue;
+                }
+                let (mut xs, mut os) = (0usize, 0usize);
+                for step in 0..k {
+                    match self.cells[((row + dr * step) * spec.cols as isize + col + dc * step) as usize] {
+                        X => xs += 1,
+                        O => os += 1,
+                        _ => {}
+                    }
+                }
+                let weight = |n: usize| 1i64 << (3 * n.min(10));
+                match (xs, os) {
+                    (0, 0) => {}
+                    (0, n) => score += weight(n),
+                    (n, 0) => score -= weight(n),
+                    _ => {}
+                }
+            }
+        }
+        score
+    }
+}
+
+/// Scores the position from O's point of view. Faster wins and slower losses score higher.
+/// `nodes` counts the positions visited, so the cost of a search can be checked without timing it
+fn minimax(state: &SearchState, to_move: u8, depth: u32, max_depth: u32, mut alpha: i64, mut beta: i64, nodes: &mut u64) -> i64 {
+    *nodes += 1;
+    if let Some(w) = state.winner() {
+        let score = WIN_SCORE - depth as i64;
+        return if w == O { score } else { -score };
+    }
+    let moves = state.moves();
+    if moves.is_empty() {
+        return 0;
+    }
+    if depth >= max_depth {
+        return state.evaluate();
+    }
+
+    let next = if to_move == O { X } else { O };
+    if to_move == O {
+        let mut best = i64::MIN;
+        for mv in moves {
+            let mut child = state.clone();
+            child.play(mv, O);
+            best = best.max(minimax(&child, next, depth + 1, max_depth, alpha, beta, nodes));
+            alpha = alpha.max(best);
+            if alpha >= beta {
+                break;
+            }
+        }
+        best
+    } else {
+        let mut best = i64::MAX;
+        for mv in moves {
+            let mut child = state.clone();
+            child.play(mv, X);
+            best = best.min(minimax(&child, next, depth + 1, max_depth, alpha, beta, nodes));
+            beta = beta.min(best);
+            if alpha >= beta {
+                break;
+            }
+        }
+        best
+    }
+}
+
+/// Best move for O according to an alpha-beta search.
+/// Exhaustive on a classic 3x3 board; horizon-limited in sudden death and on larger boards.
+pub fn minimax_move(game: &XandZeroGame) -> Option<usize> {
+    search(game).0
+}
+
+/// The minimax move and the number of positions searched for it
+fn search(game: &XandZeroGame) -> (Option<usize>, u64) {
+    let state = SearchState::from_game(game);
+    let max_depth = match (state.is_small(), state.mark_limit.is_some()) {
+        (true, false) => u32::MAX,
+        (true, true) => SUDDEN_DEATH_SEARCH_DEPTH,
+        (false, _) => LARGE_BOARD_SEARCH_DEPTH,
+    };
+
+    let mut best: Option<(usize, i64)> = None;
+    let mut nodes = 0;
+    for mv in state.moves() {
+        let mut child = state.clone();
+        child.play(mv, O);
+        let score = minimax(&child, X, 1, max_depth, best.map_or(i64::MIN, |(_, s)| s), i64::MAX, &mut nodes);
+        if best.is_none_or(|(_, s)| score > s) {
+            best = Some((mv, score));
+        }
+    }
+    (best.map(|(mv, _)| mv), nodes)
+}
+
+/// Random empty cell
+pub fn random_move(board: &[String]) -> Option<usize> {
+    let available: Vec<usize> = board.iter().enumerate()
+        .filter(|(_, s)| s.is_empty())
+        .map(|(i, _)| i)
+        .collect();
+
+    if available.is_empty() {
+        return None;
+    }
+
+    let mut rng = rand::thread_rng();
+    Some(available[rng.gen_range(0..available.len())])
+}
+
+/// Empty cells where `mark` would complete a line, in board order
+fn winning_cells(board: &[String], spec: &BoardSpec, mark: &str) -> Vec<usize> {
+    let mark = mark.to_string();
+    (0..board.len())
+        .filter(|&i| board[i].is_empty() && spec.longest_run(board, i, &mark) >= spec.win_length)
+        .collect()
+}
+
+/// Simple AI logic for the computer move (O)
+/// Priority: 1. Win if possible, 2. Block user if they are winning, 3. Take center, 4. Random available move
+pub fn get_computer_move(board: &[String], spec: &BoardSpec) -> Option<usize> {
+    // 1. Try to win
+    if let Some(&i) = winning_cells(board, spec, "O").first() {
+        return Some(i);
+    }
+
+    // 2. Block player
+    if let Some(&i) = winning_cells(board, spec, "X").first() {
+        return Some(i);
+    }
+
+    // 3. Take center
+    if board[spec.center()].is_empty() {
+        return Some(spec.center());
+    }
+
+    // 4. Random available
+    random_move(board)
+}
+
+/// Picks the computer's move for the game's difficulty, never on a cell blocked against it
+pub fn choose_move(game: &XandZeroGame) -> Option<usize> {
+    let board = game.playable_board("O");
+    match game.difficulty {
+        Difficulty::Easy => random_move(&board),
+        Difficulty::Medium => get_computer_move(&board, &game.board_spec),
+        Difficulty::Hard => minimax_move(game),
+    }
+}
+
+/// Unshielded X mark whose removal leaves the player the fewest winning cells,
+/// if that is fewer than `threats`
+fn best_erase(game: &XandZeroGame, threats: usize) -> Option<usize> {
+    (0..game.board.len())
+        .filter(|&i| game.board[i] == "X" && !game.shielded.contains(&i))
+        .map(|i| {
+            let mut board = game.board.clone();
+            board[i] = String::new();
+            (i, winning_cells(&board, &game.board_spec, "X").len())
+        })
+        .filter(|&(_, left)| left < threats)
+        .min_by_key(|&(_, left)| left)
+        .map(|(i, _)| i)
+}
+
+/// Swap that completes an O line at the X mark's cell
+fn winning_swap(game: &XandZeroGame) -> Option<(usize, usize)> {
+    let spec = &game.board_spec;
+    let unshielded = |mark: &'static str| {
+        (0..game.board.len()).filter(move |&i| game.board[i] == mark && !game.shielded.contains(&i))
+    };
+    for target in unshielded("X").filter(|&t| spec.longest_run(&game.board, t, &"O".to_string()) >= spec.win_length) {
+        for index in unshielded("O") {
+            let mut board = game.board.clone();
+            board.swap(index, target);
+            if spec.completes_line(&board, target) {
+                return Some((index, target));
+            }
+        }
+    }
+    None
+}
+
+/// Power-up the computer uses before placing, under the same limits and cooldowns as players.
+/// Medium only defends against more threats than one placement can stop; hard also swaps into
+/// a win and shields a mark of a line one short of winning.
+pub fn choose_power_up(game: &XandZeroGame) -> Option<PowerUpAction> {
+    if game.difficulty == Difficulty::Easy {
+        return None;
+    }
+    let available = |p: PowerUp| game.check_power_up_available("O", p).is_ok();
+    let spec = &game.board_spec;
+
+    // A plain placement already wins
+    if !winning_cells(&game.playable_board("O"), spec, "O").is_empty() {
+        return None;
+    }
+
+    let threats = winning_cells(&game.board, spec, "X");
+    if threats.len() >= 2 {
+        if available(PowerUp::Erase) {
+            if let Some(index) = best_erase(game, threats.len()) {
+                return Some(PowerUpAction::Erase { index });
+            }
+        }
+        if threats.len() == 2 && available(PowerUp::DoubleMove) {
+            return Some(PowerUpAction::DoubleMove);
+        }
+        // The move heuristics take the first threat, so the block covers the second
+        if threats.len() == 2 && available(PowerUp::Block) {
+            return Some(PowerUpAction::Block { index: threats[1] });
+        }
+    }
+
+    if game.difficulty != Difficulty::Hard {
+        return None;
+    }
+
+    if available(PowerUp::Swap) {
+        if let Some((index, target)) = winning_swap(game) {
+            return Some(PowerUpAction::Swap { index, target });
+        }
+    }
+
+    if available(PowerUp::Shield) {
+        let o = "O".to_string();
+        let strongest = (0..game.board.len())
+            .filter(|&i| game.board[i] == "O" && !game.shielded.contains(&i))
+            .map(|i| (i, spec.longest_run(&game.board, i, &o)))
+            .max_by_key(|&(_, run)| run);
+        if let Some((index, run)) = strongest {
+            if run + 1 >= spec.win_length {
+                return Some(PowerUpAction::Shield { index });
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xandzero::GameMode;
+    use crate::xandzero_powerups::PowerUpUse;
+
+    #[test]
+    fn hard_move_on_the_largest_board_is_bounded() {
+        let spec = BoardSpec::new(19, 19, 5).unwrap();
+        let mut game = XandZeroGame::new("u".to_string(), "u".to_string(), spec, GameMode::Normal, Difficulty::Hard);
+        for (x, o) in [(180, 181), (200, 162), (160, 142), (220, 240)] {
+            game.place_mark("X", x).unwrap();
+            game.place_mark("O", o).unwrap();
+        }
+        game.place_mark("X", 199).unwrap();
+
+        // At most the beam width of children per position, down to the search depth
+        let bound: u64 = (1..=LARGE_BOARD_SEARCH_DEPTH).map(|d| (LARGE_BOARD_BEAM_WIDTH as u64).pow(d)).sum();
+        let (mv, nodes) = search(&game);
+        assert!(nodes <= bound, "searched {} positions", nodes);
+        assert!(game.board[mv.expect("a move")].is_empty());
+    }
+
+    /// O to move on a 3x3 board laid out as `cells`, row by row
+    fn position(cells: [&str; 9], difficulty: Difficulty) -> XandZeroGame {
+        let mut game = XandZeroGame::new("u".to_string(), "u".to_string(), BoardSpec::default(), GameMode::Normal, difficulty);
+        game.board = cells.iter().map(|s| s.to_string()).collect();
+        game.move_history = (0..9).filter(|&i| !cells[i].is_empty()).collect();
+        game.turn = "O".to_string();
+        game
+    }
+
+    fn use_up(game: &mut XandZeroGame, power_up: PowerUp) {
+        for _ in 0..power_up.spec().max_per_game {
+            game.power_up_uses.push(PowerUpUse { mark: "O".to_string(), power_up, placements: 0 });
+        }
+    }
+
+    #[test]
+    fn computer_defends_against_a_double_threat() {
+        // X threatens 2 and 6 through the mark at 0
+        let cells = ["X", "X", "", "X", "O", "O", "", "", ""];
+        assert_eq!(choose_power_up(&position(cells, Difficulty::Easy)), None);
+
+        let mut game = position(cells, Difficulty::Medium);
+        assert_eq!(choose_power_up(&game), Some(PowerUpAction::Erase { index: 0 }));
+        game.shielded.push(0);
+        assert_eq!(choose_power_up(&game), Some(PowerUpAction::Erase { index: 1 }));
+        use_up(&mut game, PowerUp::Erase);
+        assert_eq!(choose_power_up(&game), Some(PowerUpAction::DoubleMove));
+        use_up(&mut game, PowerUp::DoubleMove);
+        assert_eq!(choose_power_up(&game), Some(PowerUpAction::Block { index: 6 }));
+        use_up(&mut game, PowerUp::Block);
+        assert_eq!(choose_power_up(&game), None);
+    }
+
+    #[test]
+    fn computer_skips_power_ups_when_a_placement_wins() {
+        let game = position(["X", "X", "", "X", "O", "", "O", "", ""], Difficulty::Hard);
+        assert_eq!(choose_power_up(&game), None);
+    }
+
+    #[test]
+    fn hard_computer_swaps_into_a_win_and_shields_near_lines() {
+        let swap = ["O", "O", "X", "", "X", "", "", "O", ""];
+        assert_eq!(choose_power_up(&position(swap, Difficulty::Medium)), None);
+        let mut game = position(swap, Difficulty::Hard);
+        assert_eq!(choose_power_up(&game), Some(PowerUpAction::Swap { index: 7, target: 2 }));
+        game.shielded.extend([2, 4]);
+        assert_eq!(choose_power_up(&game), Some(PowerUpAction::Shield { index: 1 }));
+
+        let mut game = position(["O", "O", "X", "", "X", "", "", "", ""], Difficulty::Hard);
+        assert_eq!(choose_power_up(&game), Some(PowerUpAction::Shield { index: 1 }));
+        use_up(&mut game, PowerUp::Shield);
+        assert_eq!(choose_power_up(&game), None);
+    }
+
+    #[test]
+    fn computer_never_places_on_a_cell_blocked_against_it() {
+        let mut game = position(["X", "X", "", "", "O", "", "", "", ""], Difficulty::Medium);
+        game.blocked = Some(crate::xandzero_powerups::BlockedCell { index: 2, against: "O".to_string() });
+        for difficulty in [Difficulty::Easy, Difficulty::Medium, Difficulty::Hard] {
+            game.difficulty = difficulty;
+            for _ in 0..10 {
+                assert_ne!(choose_move(&game), Some(2), "{:?}", difficulty);
+            }
+        }
+    }
+}
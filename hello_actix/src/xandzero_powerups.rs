@@ -0,0 +1,219 @@
+use actix_web::{HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+
+/// Abilities a player (or the computer) may use during an X-and-Zero game
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerUp {
+    Erase,      // Clear any unshielded mark
+    Swap,       // Exchange an own mark with an unshielded opponent mark
+    Block,      // Keep the opponent off an empty cell for their next turn
+    DoubleMove, // The next placement does not pass the turn
+    Shield,     // Protect an own mark from erase and swap
+}
+
+pub const ALL_POWER_UPS: [PowerUp; 5] = [PowerUp::Erase, PowerUp::Swap, PowerUp::Block, PowerUp::DoubleMove, PowerUp::Shield];
+
+/// Price and limits of one power-up
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct PowerUpSpec {
+    pub power_up: PowerUp,
+    pub cost: i32,             // Wallet points; the computer does not pay
+    pub cooldown_turns: usize, // Own placements required before the same power-up can be used again
+    pub max_per_game: usize,   // Uses per side per game
+    pub description: &'static str,
+}
+
+impl PowerUp {
+    pub fn spec(self) -> PowerUpSpec {
+        let (cost, cooldown_turns, max_per_game, description) = match self {
+            PowerUp::Erase => (50, 2, 3, "Clear any unshielded mark. You keep the turn."),
+            PowerUp::Swap => (80, 3, 1, "Swap one of your marks with an unshielded opponent mark. You keep the turn."),
+            PowerUp::Block => (30, 2, 3, "Your opponent cannot play an empty cell on their next turn."),
+            PowerUp::DoubleMove => (100, 4, 1, "Your next placement does not pass the turn."),
+            PowerUp::Shield => (40, 2, 2, "Protect one of your marks from erase and swap while it stays on the board."),
+        };
+        PowerUpSpec { power_up: self, cost, cooldown_turns, max_per_game, description }
+    }
+
+    /// Human readable name for ledger entries, e.g. `double_move`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PowerUp::Erase => "erase",
+            PowerUp::Swap => "swap",
+            PowerUp::Block => "block",
+            PowerUp::DoubleMove => "double_move",
+            PowerUp::Shield => "shield",
+        }
+    }
+}
+
+/// A power-up with its target cells, as sent by clients
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "power_up", rename_all = "snake_case")]
+pub enum PowerUpAction {
+    Erase { index: usize },
+    Swap { index: usize, target: usize }, // `index` holds the user's own mark
+    Block { index: usize },
+    DoubleMove,
+    Shield { index: usize },
+}
+
+impl PowerUpAction {
+    pub fn kind(&self) -> PowerUp {
+        match self {
+            PowerUpAction::Erase { .. } => PowerUp::Erase,
+            PowerUpAction::Swap { .. } => PowerUp::Swap,
+            PowerUpAction::Block { .. } => PowerUp::Block,
+            PowerUpAction::DoubleMove => PowerUp::DoubleMove,
+            PowerUpAction::Shield { .. } => PowerUp::Shield,
+        }
+    }
+}
+
+/// One power-up used during a game, kept to enforce cooldowns and usage limits
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PowerUpUse {
+    pub mark: String,
+    pub power_up: PowerUp,
+    pub placements: usize, // Marks the side had placed when it used the power-up
+}
+
+/// A cell the side playing `against` may not take on its next turn
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BlockedCell {
+    pub index: usize,
+    pub against: String,
+}
+
+/// Lists every power-up with its cost, cooldown and per-game limit
+#[actix_web::get("/xandzero/power-ups")]
+pub async fn get_catalogue() -> impl Responder {
+    let catalogue: Vec<PowerUpSpec> = ALL_POWER_UPS.iter().map(|p| p.spec()).collect();
+    HttpResponse::Ok().json(catalogue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xandzero::{BoardSpec, GameMode, XandZeroGame, BLOCKED_CELL};
+    use crate::xandzero_ai::Difficulty;
+
+    fn new_game(rows: usize, cols: usize, win_length: usize, game_mode: GameMode) -> XandZeroGame {
+        let spec = BoardSpec::new(rows, cols, win_length).unwrap();
+        XandZeroGame::new("u1".to_string(), "one".to_string(), spec, game_mode, Difficulty::Medium)
+    }
+
+    #[test]
+    fn catalogue_prices_and_limits() {
+        let specs: Vec<(PowerUp, i32, usize, usize)> = ALL_POWER_UPS.iter()
+            .map(|p| p.spec())
+            .map(|s| (s.power_up, s.cost, s.cooldown_turns, s.max_per_game))
+            .collect();
+        assert_eq!(specs, vec![
+            (PowerUp::Erase, 50, 2, 3),
+            (PowerUp::Swap, 80, 3, 1),
+            (PowerUp::Block, 30, 2, 3),
+            (PowerUp::DoubleMove, 100, 4, 1),
+            (PowerUp::Shield, 40, 2, 2),
+        ]);
+    }
+
+    #[test]
+    fn cooldowns_count_own_placements_and_limits_are_per_side() {
+        let mut game = new_game(5, 5, 5, GameMode::Normal);
+        for power_up in ALL_POWER_UPS {
+            let spec = power_up.spec();
+            game.power_up_uses = vec![PowerUpUse { mark: "X".to_string(), power_up, placements: 0 }];
+            let cooling = spec.max_per_game > 1;
+            if cooling {
+                assert_eq!(game.check_power_up_available("X", power_up), Err("Power-up is cooling down"), "{:?}", power_up);
+            }
+            game.power_up_uses = (0..spec.max_per_game)
+                .map(|_| PowerUpUse { mark: "X".to_string(), power_up, placements: 0 })
+                .collect();
+            assert_eq!(game.check_power_up_available("X", power_up), Err("Power-up limit for this game reached"), "{:?}", power_up);
+            assert_eq!(game.check_power_up_available("O", power_up), Ok(()), "{:?}", power_up);
+        }
+
+        // Block cools down for two of X's own placements; O's placements do not count
+        let mut game = new_game(5, 5, 5, GameMode::Normal);
+        game.use_power_up("X", PowerUpAction::Block { index: 24 }, false).unwrap();
+        assert_eq!(game.use_power_up("X", PowerUpAction::Block { index: 23 }, false), Err("Power-up is cooling down"));
+        game.place_mark("X", 0).unwrap();
+        game.place_mark("O", 10).unwrap();
+        assert_eq!(game.use_power_up("X", PowerUpAction::Block { index: 23 }, false), Err("Power-up is cooling down"));
+        game.place_mark("X", 1).unwrap();
+        game.place_mark("O", 11).unwrap();
+        assert_eq!(game.use_power_up("X", PowerUpAction::Block { index: 23 }, false), Ok(()));
+    }
+
+    #[test]
+    fn power_ups_keep_the_turn_and_double_move_places_twice() {
+        let mut game = new_game(5, 5, 5, GameMode::Normal);
+        game.use_power_up("X", PowerUpAction::DoubleMove, false).unwrap();
+        assert_eq!(game.turn, "X");
+        game.place_mark("X", 0).unwrap();
+        assert_eq!(game.turn, "X");
+        game.place_mark("X", 1).unwrap();
+        assert_eq!(game.turn, "O");
+        assert_eq!(game.double_move, None);
+    }
+
+    #[test]
+    fn shielded_marks_resist_erase_and_swap() {
+        let mut game = new_game(3, 3, 3, GameMode::Normal);
+        game.place_mark("X", 0).unwrap();
+        game.place_mark("O", 4).unwrap();
+        assert_eq!(game.use_power_up("X", PowerUpAction::Shield { index: 4 }, false), Err("You can only shield your own marks"));
+        game.use_power_up("X", PowerUpAction::Shield { index: 0 }, false).unwrap();
+        game.place_mark("X", 8).unwrap();
+
+        assert_eq!(game.use_power_up("O", PowerUpAction::Erase { index: 0 }, false), Err("Mark is shielded"));
+        assert_eq!(game.use_power_up("O", PowerUpAction::Swap { index: 4, target: 0 }, false), Err("Mark is shielded"));
+        game.use_power_up("O", PowerUpAction::Erase { index: 8 }, false).unwrap();
+        assert_eq!(game.board[8], "");
+        assert_eq!(game.board[0], "X");
+    }
+
+    #[test]
+    fn shields_leave_with_their_mark() {
+        let mut game = new_game(3, 3, 3, GameMode::SuddenDeath);
+        for (x, o) in [(0, 4), (1, 3)] {
+            game.place_mark("X", x).unwrap();
+            game.place_mark("O", o).unwrap();
+        }
+        game.place_mark("X", 8).unwrap();
+        game.use_power_up("O", PowerUpAction::Shield { index: 4 }, false).unwrap();
+        game.place_mark("O", 6).unwrap();
+        game.place_mark("X", 7).unwrap(); // Fourth X mark: X's oldest (0) expires
+        assert_eq!(game.board[0], "");
+        game.place_mark("O", 2).unwrap(); // Fourth O mark: O's oldest, the shielded 4, expires
+        assert_eq!(game.board[4], "");
+        assert!(game.shielded.is_empty());
+    }
+
+    #[test]
+    fn blocks_last_one_opponent_turn() {
+        let mut game = new_game(3, 3, 3, GameMode::Normal);
+        game.place_mark("X", 0).unwrap();
+        assert_eq!(game.use_power_up("O", PowerUpAction::Block { index: 0 }, false), Err("Only empty cells can be blocked"));
+        game.use_power_up("O", PowerUpAction::Block { index: 4 }, false).unwrap();
+        game.place_mark("O", 8).unwrap();
+
+        assert_eq!(game.playable_board("X")[4], BLOCKED_CELL);
+        assert_eq!(game.playable_board("O")[4], "");
+        assert_eq!(game.place_mark("X", 4), Err("Cell is blocked this turn"));
+        game.place_mark("X", 1).unwrap();
+        assert_eq!(game.blocked, None);
+        game.place_mark("O", 2).unwrap();
+        game.place_mark("X", 4).unwrap();
+    }
+
+    #[test]
+    fn the_last_empty_cell_cannot_be_blocked() {
+        let mut game = new_game(3, 3, 3, GameMode::Normal);
+        game.board = ["X", "O", "X", "X", "O", "O", "O", "X", ""].iter().map(|s| s.to_string()).collect();
+        assert_eq!(game.use_power_up("X", PowerUpAction::Block { index: 8 }, false), Err("Cannot block the last empty cell"));
+    }
+}
@@ -0,0 +1,329 @@
+use actix_web::{web, HttpRequest, HttpResponse, Error};
+use actix_ws::Message;
+use futures_util::StreamExt;
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use crate::{AppState, achievements, authenticate_ws, spectate};
+use crate::matchmaking::{self, MatchOutcome, Ticket};
+use crate::xandzero_powerups::PowerUpAction;
+use crate::xandzero::{
+    self, apply_to_game, BoardSpec, GameError, GameEvent, GameMode, Seat, XandZeroGame, XandZeroResponse,
+};
+
+/// How often the server checks the turn timers of live games
+const TURN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How often a queued connection polls matchmaking
+const MATCH_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Messages a player sends over `/xandzero_ws`
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    /// Open a new game and wait for an opponent to join with its id
+    Create {
+        game_mode: GameMode,
+        rows: Option<usize>,
+        cols: Option<usize>,
+        win_length: Option<usize>,
+    },
+    Join { game_id: String },
+    Move { index: usize },
+    /// Paid from the wallet; resending with the same `idempotency_key` is not charged twice
+    Erase { index: usize, idempotency_key: Option<String> },
+    /// Any catalogue power-up, e.g. `{ "type": "power_up", "power_up": "swap", "index": 0, "target": 4 }`
+    PowerUp {
+        #[serde(flatten)]
+        action: PowerUpAction,
+        idempotency_key: Option<String>,
+    },
+    Resign,
+    /// Offer a rematch, or accept the opponent's offer
+    Rematch,
+    /// Wait for an opponent of similar rating on a classic board
+    Queue { game_mode: GameMode },
+    LeaveQueue,
+}
+
+fn error_message(message: &str) -> String {
+    serde_json::json!({ "type": "error", "message": message }).to_string()
+}
+
+/// Relays a game's published events into the connection's channel.
+/// The current state is sent once the subscription is live, so no update can slip in between.
+pub fn subscribe_to_game(redis_client: redis::Client, game_id: String, tx: mpsc::Sender<String>) -> JoinHandle<()> {
+    actix_web::rt::spawn(async move {
+        let Ok(con) = redis_client.get_async_connection().await else { return };
+        let mut pubsub = con.into_pubsub();
+        if pubsub.subscribe(xandzero::events_channel(&game_id)).await.is_err() {
+            return;
+        }
+
+        if let Ok(Some(game)) = xandzero::fetch_game(&redis_client, &game_id).await {
+            let snapshot = GameEvent::State { game: XandZeroResponse::from_game(&game) };
+            if tx.send(serde_json::to_string(&snapshot).unwrap_or_default()).await.is_err() {
+                return;
+            }
+        }
+
+        let mut messages = pubsub.on_message();
+        while let Some(msg) = messages.next().await {
+            let Ok(payload) = msg.get_payload::<String>() else { continue };
+            if tx.send(payload).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// The game a connection is currently attached to, with its event relay
+struct Attachment {
+    game_id: String,
+    relay: JoinHandle<()>,
+}
+
+fn attach(current: &mut Option<Attachment>, data: &AppState, game_id: String, tx: &mpsc::Sender<String>) {
+    if let Some(old) = current.take() {
+        old.relay.abort();
+    }
+    let relay = subscribe_to_game(data.redis_client.clone(), game_id.clone(), tx.clone());
+    *current = Some(Attachment { game_id, relay });
+}
+
+/// Applies a client message. Returns the game to attach to, if it changed.
+async fn handle_client_message(
+    data: &AppState,
+    seat: &Seat,
+    current: Option<&str>,
+    msg: ClientMessage,
+) -> Result<Option<String>, GameError> {
+    let current_game = || current.map(str::to_string).ok_or(GameError::Rule("Not in a game"));
+
+    match msg {
+        ClientMessage::Create { game_mode, rows, cols, win_length } => {
+            let spec = BoardSpec::from_options(rows, cols, win_length).map_err(GameError::Rule)?;
+            let game = XandZeroGame::new_pvp(seat.clone(), None, spec, game_mode);
+            xandzero::store_new_game(&data.redis_client, &game).await?;
+            Ok(Some(game.game_id))
+        }
+        ClientMessage::Join { game_id } => {
+            apply_to_game(data, &game_id, |game| game.join(seat.clone()).map_err(GameError::Rule)).await?;
+            Ok(Some(game_id))
+        }
+        ClientMessage::Move { index } => {
+            // Games created by the matchmaking fallback are against the computer
+            xandzero::apply_with_computer_reply(data, &current_game()?, |game| {
+                let mark = game.mark_of(&seat.user_id).ok_or(GameError::NotFound)?;
+                game.place_mark(mark, index).map_err(GameError::Rule)
+            })
+            .await?;
+            Ok(None)
+        }
+        ClientMessage::Erase { index, idempotency_key } => {
+            let action = PowerUpAction::Erase { index };
+            xandzero::paid_power_up(data, &seat.user_id, &current_game()?, action, idempotency_key).await?;
+            Ok(None)
+        }
+        ClientMessage::PowerUp { action, idempotency_key } => {
+            xandzero::paid_power_up(data, &seat.user_id, &current_game()?, action, idempotency_key).await?;
+            Ok(None)
+        }
+        ClientMessage::Resign => {
+            apply_to_game(data, &current_game()?, |game| {
+                let mark = game.mark_of(&seat.user_id).ok_or(GameError::NotFound)?;
+                game.resign(mark).map_err(GameError::Rule)
+            })
+            .await?;
+            Ok(None)
+        }
+        ClientMessage::Rematch => {
+            let game_id = current_game()?;
+            // When accepting, the follow-up game is stored before the old one points at it,
+            // so players switching over never look up a missing game.
+            let game = xandzero::fetch_game(&data.redis_client, &game_id).await?.ok_or(GameError::NotFound)?;
+            let candidate = match &game.rematch_offered_by {
+                Some(by) if by != &seat.user_id => game.rematch(),
+                _ => None,
+            };
+            if let Some(candidate) = &candidate {
+                xandzero::store_new_game(&data.redis_client, candidate).await?;
+            }
+
+            let accepted = apply_to_game(data, &game_id, |game| {
+                game.mark_of(&seat.user_id).ok_or(GameError::NotFound)?;
+                if game.offer_rematch(&seat.user_id).map_err(GameError::Rule)? {
+                    // The offer arrived after our read; let the client retry
+                    let candidate = candidate.as_ref().ok_or(GameError::Conflict)?;
+                    game.rematch_game_id = Some(candidate.game_id.clone());
+                }
+                Ok(())
+            })
+            .await;
+            if let (Err(_), Some(candidate)) = (&accepted, &candidate) {
+                // Nobody will play it, so it must not be listed or timed out
+                let mut con = data.redis_client.get_async_connection().await?;
+                spectate::remove_live(&mut con, "xandzero", &candidate.game_id).await?;
+            }
+            accepted?;
+            Ok(candidate.map(|c| c.game_id))
+        }
+        // Queueing changes connection state and is handled by the socket loop
+        ClientMessage::Queue { .. } | ClientMessage::LeaveQueue => Ok(None),
+    }
+}
+
+fn matched_message(game_id: &str, vs_computer: bool) -> String {
+    serde_json::json!({ "type": "matched", "game_id": game_id, "vs_computer": vs_computer }).to_string()
+}
+
+/// Forfeits the side to move if its timer ran out. Every instance sweeps the live games;
+/// the optimistic write in `apply_to_game` makes sure only one of them ends the game.
+async fn enforce_turn_timer(data: &AppState, game_id: &str) {
+    let now = chrono::Utc::now().timestamp_millis();
+    let expired = matches!(
+        xandzero::fetch_game(&data.redis_client, game_id).await,
+        Ok(Some(game)) if game.winner.is_none() && game.turn_deadline_ms.is_some_and(|d| now >= d)
+    );
+    if expired {
+        let _ = apply_to_game(data, game_id, |game| {
+            if game.check_timeout(now) { Ok(()) } else { Err(GameError::Rule("Turn has not expired")) }
+        })
+        .await;
+    }
+}
+
+/// Enforces the turn timers of every live X-and-Zero game, whether or not its players are still connected.
+/// Runs for the lifetime of the server.
+pub async fn sweep_turn_timers(data: AppState) {
+    let mut ticker = tokio::time::interval(TURN_CHECK_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let game_ids = match data.redis_client.get_async_connection().await {
+            Ok(mut con) => spectate::live_ids(&mut con, "xandzero").await,
+            Err(e) => Err(e),
+        };
+        match game_ids {
+            Ok(game_ids) => {
+                for game_id in game_ids {
+                    enforce_turn_timer(&data, &game_id).await;
+                }
+            }
+            Err(e) => eprintln!("Turn timer sweep failed: {}", e),
+        }
+    }
+}
+
+/// Player-versus-player X-and-Zero over a websocket.
+/// Game state lives in Valkey and updates fan out through pub/sub, so the two players may be
+/// connected to different backend instances.
+pub async fn xandzero_socket(
+    req: HttpRequest,
+    stream: web::Payload,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let (user_id, username) = match authenticate_ws(&req, &data).await {
+        Ok(user) => user,
+        Err(res) => return Ok(res),
+    };
+    let seat = Seat { user_id, username };
+
+    let (res, mut session, mut msg_stream) = actix_ws::handle(&req, stream)?;
+
+    actix_web::rt::spawn(async move {
+        let (event_tx, mut event_rx) = mpsc::channel::<String>(32);
+        let mut current: Option<Attachment> = None;
+        let mut queued: Option<Ticket> = None;
+        let mut ticker = tokio::time::interval(MATCH_POLL_INTERVAL);
+        // Achievement unlocks arrive with the game events
+        let notifications = achievements::relay(data.redis_client.clone(), &seat.user_id, event_tx.clone());
+
+        loop {
+            tokio::select! {
+                msg = msg_stream.next() => {
+                    match msg {
+                        Some(Ok(Message::Text(text))) => {
+                            let reply = match serde_json::from_str::<ClientMessage>(&text) {
+                                Ok(ClientMessage::Queue { game_mode }) => {
+                                    if let Some(ticket) = queued.take() {
+                                        let _ = matchmaking::leave(&data, &ticket).await;
+                                    }
+                                    match matchmaking::enqueue(&data, seat.clone(), "xandzero", game_mode).await {
+                                        Ok(ticket) => {
+                                            let status = serde_json::json!({ "type": "queued", "rating": ticket.rating });
+                                            queued = Some(ticket);
+                                            Some(status.to_string())
+                                        }
+                                        Err(e) => Some(error_message(&GameError::from(e).to_string())),
+                                    }
+                                }
+                                Ok(ClientMessage::LeaveQueue) => {
+                                    if let Some(ticket) = queued.take() {
+                                        let _ = matchmaking::leave(&data, &ticket).await;
+                                    }
+                                    None
+                                }
+                                Ok(client_msg) => {
+                                    let game_id = current.as_ref().map(|a| a.game_id.as_str());
+                                    match handle_client_message(&data, &seat, game_id, client_msg).await {
+                                        Ok(Some(next)) => {
+                                            attach(&mut current, &data, next, &event_tx);
+                                            None
+                                        }
+                                        Ok(None) => None,
+                                        Err(e) => Some(error_message(&e.to_string())),
+                                    }
+                                }
+                                Err(_) => Some(error_message("Invalid message")),
+                            };
+                            if let Some(reply) = reply {
+                                if session.text(reply).await.is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                        Some(Ok(Message::Ping(bytes))) if session.pong(&bytes).await.is_err() => break,
+                        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                        _ => {}
+                    }
+                }
+                Some(event) = event_rx.recv() => {
+                    // Follow an accepted rematch to the new game
+                    let next = match serde_json::from_str::<GameEvent>(&event) {
+                        Ok(GameEvent::State { game }) => game.rematch_game_id
+                            .filter(|_| current.as_ref().is_some_and(|a| a.game_id == game.game_id)),
+                        _ => None,
+                    };
+                    if session.text(event).await.is_err() {
+                        break;
+                    }
+                    if let Some(next) = next {
+                        attach(&mut current, &data, next, &event_tx);
+                    }
+                }
+                _ = ticker.tick() => {
+                    if let Some(ticket) = &queued {
+                        if let Ok(MatchOutcome::Matched { game_id, vs_computer }) = matchmaking::poll(&data, ticket).await {
+                            queued = None;
+                            if session.text(matched_message(&game_id, vs_computer)).await.is_err() {
+                                break;
+                            }
+                            attach(&mut current, &data, game_id, &event_tx);
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(attachment) = current {
+            attachment.relay.abort();
+        }
+        notifications.abort();
+        if let Some(ticket) = queued {
+            let _ = matchmaking::leave(&data, &ticket).await;
+        }
+        let _ = session.close(None).await;
+    });
+
+    Ok(res)
+}
@@ -0,0 +1,235 @@
+use actix_web::{web, HttpResponse, Responder};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use crate::{AppState, validate_token};
+use crate::xandzero::{ActionRecord, BoardSpec, GameAction, XandZeroGame};
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+/// serde name of a unit enum value, e.g. `sudden_death`
+fn enum_name<T: Serialize>(value: &T) -> Option<String> {
+    serde_json::to_value(value).ok()?.as_str().map(str::to_string)
+}
+
+fn timestamp(ms: i64) -> chrono::NaiveDateTime {
+    chrono::DateTime::from_timestamp_millis(ms)
+        .filter(|_| ms > 0)
+        .unwrap_or_else(chrono::Utc::now)
+        .naive_utc()
+}
+
+/// Stores a finished game with its full action log. Called once, by whoever finished the game.
+pub async fn save_record(data: &AppState, game: &XandZeroGame) {
+    let Some(winner) = &game.winner else { return };
+    let opponent = game.opponent.as_ref();
+
+    let result = sqlx::query(
+        "INSERT INTO xandzero_games
+             (game_id, x_user_id, x_username, o_user_id, o_username, pvp, game_mode, difficulty,
+              board_rows, board_cols, win_length, winner, finish_reason, actions, started_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)
+         ON CONFLICT (game_id) DO NOTHING"
+    )
+    .bind(&game.game_id)
+    .bind(&game.owner_id)
+    .bind(&game.owner_name)
+    .bind(opponent.map(|o| &o.user_id))
+    .bind(opponent.map(|o| &o.username))
+    .bind(game.pvp)
+    .bind(game.game_mode.as_str())
+    .bind((!game.pvp).then(|| enum_name(&game.difficulty)).flatten())
+    .bind(game.board_spec.rows as i32)
+    .bind(game.board_spec.cols as i32)
+    .bind(game.board_spec.win_length as i32)
+    .bind(winner)
+    .bind(game.finish_reason.and_then(|r| enum_name(&r)))
+    .bind(Json(&game.actions))
+    .bind(timestamp(game.created_at_ms))
+    .execute(&data.db)
+    .await;
+
+    if let Err(e) = result {
+        eprintln!("Failed to store record of game {}: {}", game.game_id, e);
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct GameRecordRow {
+    game_id: String,
+    x_user_id: String,
+    x_username: Option<String>,
+    o_user_id: Option<String>,
+    o_username: Option<String>,
+    pvp: bool,
+    game_mode: String,
+    difficulty: Option<String>,
+    board_rows: i32,
+    board_cols: i32,
+    win_length: i32,
+    winner: String,
+    finish_reason: Option<String>,
+    actions: Json<Vec<ActionRecord>>,
+    started_at: chrono::NaiveDateTime,
+    finished_at: chrono::NaiveDateTime,
+}
+
+impl GameRecordRow {
+    fn mark_of(&self, user_id: &str) -> &'static str {
+        if self.x_user_id == user_id { "X" } else { "O" }
+    }
+
+    fn board_spec(&self) -> BoardSpec {
+        BoardSpec { rows: self.board_rows as usize, cols: self.board_cols as usize, win_length: self.win_length as usize }
+    }
+
+    /// The game from one participant's point of view, without the action log
+    fn summary(&self, user_id: &str) -> GameSummary {
+        let mark = self.mark_of(user_id);
+        let opponent = if mark == "X" { self.o_username.clone() } else { self.x_username.clone() };
+        GameSummary {
+            game_id: self.game_id.clone(),
+            pvp: self.pvp,
+            game_mode: self.game_mode.clone(),
+            difficulty: self.difficulty.clone(),
+            board_spec: self.board_spec(),
+            your_mark: mark,
+            opponent: opponent.unwrap_or_else(|| if self.pvp { "Anonymous" } else { "Computer" }.to_string()),
+            result: match self.winner.as_str() {
+                "Draw" => "draw",
+                w if w == mark => "win",
+                _ => "loss",
+            },
+            finish_reason: self.finish_reason.clone(),
+            move_count: self.actions.iter().filter(|a| matches!(a.action, GameAction::Place { .. })).count(),
+            started_at: self.started_at,
+            finished_at: self.finished_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct GameSummary {
+    pub game_id: String,
+    pub pvp: bool,
+    pub game_mode: String,
+    pub difficulty: Option<String>,
+    pub board_spec: BoardSpec,
+    pub your_mark: &'static str,
+    pub opponent: String,
+    pub result: &'static str, // "win", "loss" or "draw"
+    pub finish_reason: Option<String>,
+    pub move_count: usize,
+    pub started_at: chrono::NaiveDateTime,
+    pub finished_at: chrono::NaiveDateTime,
+}
+
+/// One replay step: the action and the board right after it
+#[derive(Serialize)]
+pub struct ReplayStep {
+    #[serde(flatten)]
+    pub record: ActionRecord,
+    pub board: Vec<String>,
+}
+
+/// Re-applies the action log to an empty board
+fn replay_steps(spec: &BoardSpec, actions: &[ActionRecord]) -> Vec<ReplayStep> {
+    let mut board = vec![String::new(); spec.cells()];
+    actions
+        .iter()
+        .map(|record| {
+            match &record.action {
+                GameAction::Place { mark, index } => board[*index] = mark.clone(),
+                GameAction::Expire { index, .. } | GameAction::Erase { index, .. } => board[*index] = String::new(),
+                GameAction::Swap { index, target, .. } => board.swap(*index, *target),
+                GameAction::Block { .. } | GameAction::DoubleMove { .. } | GameAction::Shield { .. }
+                | GameAction::Resign { .. } | GameAction::Timeout { .. } => {}
+            }
+            ReplayStep { record: record.clone(), board: board.clone() }
+        })
+        .collect()
+}
+
+const RECORD_COLUMNS: &str = "game_id, x_user_id, x_username, o_user_id, o_username, pvp, game_mode, difficulty,
+     board_rows, board_cols, win_length, winner, finish_reason, actions, started_at, finished_at";
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+/// The authenticated player's finished games, most recent first
+#[actix_web::get("/xandzero/history")]
+pub async fn list_games(
+    data: web::Data<AppState>,
+    auth: BearerAuth,
+    query: web::Query<HistoryQuery>,
+) -> impl Responder {
+    let claims = match validate_token(auth.token(), &data.oidc_jwks_uri).await {
+        Ok(c) => c,
+        Err(r) => return r,
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let offset = query.offset.unwrap_or(0).max(0);
+
+    let rows: Vec<GameRecordRow> = match sqlx::query_as(&format!(
+        "SELECT {} FROM xandzero_games
+         WHERE x_user_id = $1 OR o_user_id = $1
+         ORDER BY finished_at DESC, game_id
+         LIMIT $2 OFFSET $3",
+        RECORD_COLUMNS
+    ))
+    .bind(&claims.sub)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(&data.db)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("DB error: {}", e)),
+    };
+
+    let games: Vec<GameSummary> = rows.iter().map(|row| row.summary(&claims.sub)).collect();
+    HttpResponse::Ok().json(games)
+}
+
+/// Full record of one of the player's finished games, with the board after every step
+#[actix_web::get("/xandzero/history/{game_id}")]
+pub async fn get_replay(
+    data: web::Data<AppState>,
+    auth: BearerAuth,
+    path: web::Path<String>,
+) -> impl Responder {
+    let claims = match validate_token(auth.token(), &data.oidc_jwks_uri).await {
+        Ok(c) => c,
+        Err(r) => return r,
+    };
+
+    let row: Option<GameRecordRow> = match sqlx::query_as(&format!(
+        "SELECT {} FROM xandzero_games WHERE game_id = $1 AND (x_user_id = $2 OR o_user_id = $2)",
+        RECORD_COLUMNS
+    ))
+    .bind(path.as_str())
+    .bind(&claims.sub)
+    .fetch_optional(&data.db)
+    .await
+    {
+        Ok(row) => row,
+        Err(e) => return HttpResponse::InternalServerError().body(format!("DB error: {}", e)),
+    };
+    let Some(row) = row else {
+        return HttpResponse::NotFound().body("Game not found");
+    };
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "game": row.summary(&claims.sub),
+        "players": {
+            "x": { "user_id": row.x_user_id, "username": row.x_username },
+            "o": row.o_user_id.as_ref().map(|id| serde_json::json!({ "user_id": id, "username": row.o_username })),
+        },
+        "winner": row.winner,
+        "steps": replay_steps(&row.board_spec(), &row.actions),
+    }))
+}
@@ -1,12 +1,25 @@
 import { useEffect, useRef, useState } from 'react';
 
+const POWER_UP_ICONS = {
+    wide_spray: '↔️',
+    long_range: '🔭',
+    slow_motion: '🐢',
+    shield: '🛡️',
+    bomb: '💣',
+};
+
 const GameCanvas = ({ auth, onExit }) => {
     const canvasRef = useRef(null);
     const wsRef = useRef(null);
+    const lastMoveSentRef = useRef(0);
     const [gameState, setGameState] = useState(null);
     const [gameOver, setGameOver] = useState(false);
     const [score, setScore] = useState(0);
     const [gameKey, setGameKey] = useState(0); // To reset game
+    const [paused, setPaused] = useState(false);
+    const [result, setResult] = useState(null); // The server's game_over message
+    const [unlocked, setUnlocked] = useState(null); // Achievement to announce
+    const sessionRef = useRef(null); // { session_id, resume_token } for reconnecting
 
     // Assets
     const dragonImg = useRef(new Image());
@@ -25,34 +38,68 @@ const GameCanvas = ({ auth, onExit }) => {
         // Note: Using window.location.host to handle dynamic ports/hosts
         const token = auth?.user?.access_token || '';
         const protocol = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
-        const wsUrl = `${protocol}//${window.location.host}/api/dragon_ws?token=${token}`;
-
-        const ws = new WebSocket(wsUrl);
-        wsRef.current = ws;
-
-        ws.onopen = () => {
-            console.log('Connected to Game Server');
-            setGameOver(false);
+        let leaving = false;
+        let reconnectTimer;
+
+        const connect = () => {
+            // A dropped connection resumes its paused session
+            const session = sessionRef.current;
+            const resume = session ? `&resume=${session.session_id}&resume_token=${session.resume_token}` : '';
+            const ws = new WebSocket(`${protocol}//${window.location.host}/api/dragon_ws?token=${token}${resume}`);
+            wsRef.current = ws;
+            let opened = false;
+
+            ws.onopen = () => {
+                console.log('Connected to Game Server');
+                opened = true;
+            };
+
+            ws.onmessage = (event) => {
+                try {
+                    const msg = JSON.parse(event.data);
+                    if (msg.type === 'session') {
+                        sessionRef.current = { session_id: msg.session_id, resume_token: msg.resume_token };
+                    } else if (msg.type === 'paused') {
+                        setPaused(true);
+                    } else if (msg.type === 'resumed') {
+                        setPaused(false);
+                    } else if (msg.type === 'game_over') {
+                        setResult(msg);
+                    } else if (msg.type === 'achievement_unlocked') {
+                        setUnlocked(msg.achievement);
+                        setTimeout(() => setUnlocked(null), 4000);
+                    } else if (!msg.type) {
+                        setGameState(msg);
+                        setScore(msg.score);
+                        setGameOver(msg.game_over);
+                        if (!msg.game_over) setResult(null);
+                    }
+                } catch (e) {
+                    console.error('Failed to parse game state', e);
+                }
+            };
+
+            ws.onclose = (event) => {
+                console.log('Disconnected from Game Server');
+                // A resume the server refused leaves nothing to go back to, and neither does a finished game
+                if (!opened || event.code === 1000) sessionRef.current = null;
+                if (!leaving && sessionRef.current) {
+                    reconnectTimer = setTimeout(connect, 1000);
+                }
+            };
         };
 
-        ws.onmessage = (event) => {
-            try {
-                const state = JSON.parse(event.data);
-                setGameState(state);
-                setScore(state.score);
-                setGameOver(state.game_over);
-            } catch (e) {
-                console.error('Failed to parse game state', e);
-            }
-        };
-
-        ws.onclose = () => {
-            console.log('Disconnected from Game Server');
-        };
+        sessionRef.current = null;
+        setGameOver(false);
+        setPaused(false);
+        setResult(null);
+        connect();
 
         return () => {
-            if (ws.readyState === WebSocket.OPEN) {
-                ws.close();
+            leaving = true;
+            clearTimeout(reconnectTimer);
+            if (wsRef.current && wsRef.current.readyState === WebSocket.OPEN) {
+                wsRef.current.close();
             }
         };
     }, [gameKey]); // Reconnect when gameKey changes
@@ -64,13 +111,38 @@ const GameCanvas = ({ auth, onExit }) => {
         const rect = canvasRef.current.getBoundingClientRect();
         const y = e.clientY - rect.top;
 
-        // Send Y to server
+        // Send Y to server, staying under its limit of 120 messages a second
+        const now = performance.now();
+        if (now - lastMoveSentRef.current < 10) return;
         if (wsRef.current && wsRef.current.readyState === WebSocket.OPEN) {
+            lastMoveSentRef.current = now;
             wsRef.current.send(JSON.stringify({ y }));
         }
     };
 
+    // Keys 1-3 activate the stored power-ups, P pauses and resumes
+    useEffect(() => {
+        const handleKeyDown = (e) => {
+            if (e.key === 'p' && !gameOver && wsRef.current && wsRef.current.readyState === WebSocket.OPEN) {
+                wsRef.current.send(JSON.stringify({ type: paused ? 'resume' : 'pause' }));
+                return;
+            }
+            const slot = Number(e.key) - 1;
+            const powerUp = gameState?.power_ups?.[slot];
+            if (powerUp && wsRef.current && wsRef.current.readyState === WebSocket.OPEN) {
+                wsRef.current.send(JSON.stringify({ type: 'use_power_up', power_up: powerUp }));
+            }
+        };
+        window.addEventListener('keydown', handleKeyDown);
+        return () => window.removeEventListener('keydown', handleKeyDown);
+    }, [gameState, gameOver, paused]);
+
+    // Restarts on the open connection; once the server closed it, on a new one
     const handlePlayAgain = () => {
+        if (wsRef.current && wsRef.current.readyState === WebSocket.OPEN) {
+            wsRef.current.send(JSON.stringify({ type: 'play_again' }));
+            return;
+        }
         setGameKey(prev => prev + 1);
         setGameState(null);
         setGameOver(false);
@@ -100,25 +172,43 @@ const GameCanvas = ({ auth, onExit }) => {
             ctx.drawImage(dragonImg.current, 0, 100, 150, 150);
 
             if (gameState) {
-                // Draw Player (Water Source)
-                const playerY = gameState.player.y;
-
-                // Draw Water Spray (Animated Sprite)
+                // Draw Players (Water Sources), one per defender in co-op rooms
                 const time = Date.now() / 200;
-                const sprayOffset = Math.sin(time) * 0.1;
+                gameState.players.forEach((player, seat) => {
+                    // Draw Water Spray (Animated Sprite)
+                    const sprayOffset = Math.sin(time + seat) * 0.1;
+
+                    ctx.save();
+                    ctx.translate(canvas.width - 50, player.y);
+                    // Point Left (-PI/2) + slight oscillation
+                    ctx.rotate(-Math.PI / 2 + sprayOffset);
+                    ctx.drawImage(waterImg.current, -50, -100, 100, 100);
+                    ctx.restore();
+                });
 
-                ctx.save();
-                ctx.translate(canvas.width - 50, playerY);
-                // Point Left (-PI/2) + slight oscillation
-                ctx.rotate(-Math.PI / 2 + sprayOffset);
-                ctx.drawImage(waterImg.current, -50, -100, 100, 100);
-                ctx.restore();
+                // Draw Boss with its health bar
+                if (gameState.boss) {
+                    const boss = gameState.boss;
+                    ctx.drawImage(dragonImg.current, boss.x - 60, boss.y - 100, 200, 200);
+                    ctx.fillStyle = '#e2e8f0';
+                    ctx.fillRect(canvas.width / 2 - 150, 20, 300, 14);
+                    ctx.fillStyle = '#dc2626';
+                    ctx.fillRect(canvas.width / 2 - 150, 20, 300 * Math.max(boss.health, 0) / boss.max_health, 14);
+                }
+
+                // Draw Pickups
+                ctx.font = '28px Arial';
+                gameState.pickups.forEach(p => {
+                    ctx.fillText(POWER_UP_ICONS[p.kind], p.x - 14, p.y + 10);
+                });
 
                 // Draw Fireballs
                 gameState.fireballs.forEach(fb => {
                     const scale = fb.extinguish_timer || 1.0;
                     ctx.save();
                     if (fb.state === 'Extinguishing') ctx.globalAlpha = scale;
+                    if (fb.kind === 'Splitting') ctx.filter = 'hue-rotate(200deg)';
+                    if (fb.kind === 'Curving') ctx.filter = 'hue-rotate(90deg)';
                     ctx.drawImage(
                         fireballImg.current,
                         fb.x - 20 * scale, fb.y - 20 * scale,
@@ -132,6 +222,47 @@ const GameCanvas = ({ auth, onExit }) => {
             ctx.fillStyle = '#0f172a';
             ctx.font = 'bold 24px Arial';
             ctx.fillText(`Score: ${score}`, 20, 40);
+            if (gameState) {
+                ctx.fillText(`Wave: ${gameState.wave}`, 20, 70);
+                // Hearts blink while invulnerable
+                const blink = gameState.invulnerable_for > 0 && Math.floor(Date.now() / 150) % 2 === 0;
+                if (!blink) ctx.fillText('❤'.repeat(gameState.lives), 20, 100);
+                if (gameState.multiplier > 1) ctx.fillText(`Combo ${gameState.combo} ×${gameState.multiplier}`, 20, 130);
+
+                // Stored power-ups with their keys, then running effects with seconds left
+                ctx.font = '20px Arial';
+                gameState.power_ups.forEach((kind, i) => {
+                    ctx.fillText(`${i + 1}: ${POWER_UP_ICONS[kind]}`, 20 + i * 70, canvas.height - 20);
+                });
+                gameState.effects.forEach((effect, i) => {
+                    ctx.fillText(`${POWER_UP_ICONS[effect.kind]} ${effect.remaining.toFixed(1)}s`, canvas.width - 200, 40 + i * 28);
+                });
+            }
+
+            if (paused && !gameOver) {
+                ctx.fillStyle = 'rgba(0,0,0,0.5)';
+                ctx.fillRect(0, 0, canvas.width, canvas.height);
+                ctx.fillStyle = '#ffffff';
+                ctx.font = 'bold 48px Arial';
+                ctx.textAlign = 'center';
+                ctx.fillText('PAUSED', canvas.width / 2, canvas.height / 2);
+                ctx.font = '20px Arial';
+                ctx.fillText('Press P to resume', canvas.width / 2, canvas.height / 2 + 40);
+                ctx.textAlign = 'start';
+            }
+
+            if (unlocked) {
+                ctx.fillStyle = 'rgba(15, 23, 42, 0.85)';
+                ctx.fillRect(canvas.width / 2 - 200, 20, 400, 56);
+                ctx.fillStyle = '#facc15';
+                ctx.font = 'bold 18px Arial';
+                ctx.textAlign = 'center';
+                ctx.fillText(`🏆 ${unlocked.name}`, canvas.width / 2, 44);
+                ctx.fillStyle = '#ffffff';
+                ctx.font = '14px Arial';
+                ctx.fillText(unlocked.description, canvas.width / 2, 66);
+                ctx.textAlign = 'start';
+            }
 
             if (gameOver) {
                 ctx.fillStyle = 'rgba(0,0,0,0.7)';
@@ -144,6 +275,12 @@ const GameCanvas = ({ auth, onExit }) => {
 
                 ctx.font = '24px Arial';
                 ctx.fillText(`Final Score: ${score}`, canvas.width / 2, canvas.height / 2 + 50);
+                if (gameState) ctx.fillText(`Reached wave ${gameState.wave}`, canvas.width / 2, canvas.height / 2 + 85);
+                if (result && result.personal_best != null) {
+                    const best = result.new_best ? `New best: ${result.personal_best}!` : `Best: ${result.personal_best}`;
+                    ctx.fillText(`${best}  Rank #${result.rank}`, canvas.width / 2, canvas.height / 2 - 60);
+                }
+                ctx.textAlign = 'start';
             }
 
             animationFrameId = requestAnimationFrame(render);
@@ -152,7 +289,7 @@ const GameCanvas = ({ auth, onExit }) => {
         render();
 
         return () => cancelAnimationFrame(animationFrameId);
-    }, [gameState, gameOver, score]);
+    }, [gameState, gameOver, score, paused, result, unlocked]);
 
     return (
         <div style={{ display: 'flex', flexDirection: 'column', alignItems: 'center', gap: '1rem', padding: '2rem' }}>
@@ -236,7 +373,7 @@ const GameCanvas = ({ auth, onExit }) => {
                 )}
             </div>
 
-            <p style={{ color: '#94a3b8' }}>Move your mouse to control the water spray!</p>
+            <p style={{ color: '#94a3b8' }}>Move your mouse to control the water spray! Press P to pause.</p>
         </div>
     );
 };
@@ -1,5 +1,10 @@
 import React, { useState } from 'react';
 
+const BOARD_SIZES = [
+    { label: 'Classic 3×3', rows: 3, cols: 3, win_length: 3 },
+    { label: 'Gomoku 15×15', rows: 15, cols: 15, win_length: 5 },
+];
+
 const XandZeroGame = ({ auth, onGameEnd }) => {
     const [gameMode, setGameMode] = useState(null); // 'normal' or 'sudden_death'
     const [board, setBoard] = useState(Array(9).fill(""));
@@ -10,7 +15,12 @@ const XandZeroGame = ({ auth, onGameEnd }) => {
     const [aiAction, setAiAction] = useState(null);
     const [powerUpMode, setPowerUpMode] = useState(null); // null or 'erase'
 
-    const handleModeSelect = (mode) => {
+    const [gameId, setGameId] = useState(null);
+    const [difficulty, setDifficulty] = useState('medium'); // 'easy', 'medium' or 'hard'
+    const [boardSize, setBoardSize] = useState(BOARD_SIZES[0]);
+    const [boardSpec, setBoardSpec] = useState(BOARD_SIZES[0]);
+
+    const handleModeSelect = async (mode) => {
         setGameMode(mode);
         setBoard(Array(9).fill(""));
         setHistory([]);
@@ -18,12 +28,43 @@ const XandZeroGame = ({ auth, onGameEnd }) => {
         setScoreEarned(0);
         setAiAction(null);
         setPowerUpMode(null);
+        setGameId(null);
+
+        try {
+            const response = await fetch('/api/xandzero/games', {
+                method: 'POST',
+                headers: {
+                    'Content-Type': 'application/json',
+                    'Authorization': `Bearer ${auth.user.access_token}`
+                },
+                body: JSON.stringify({
+                    game_mode: mode,
+                    difficulty,
+                    rows: boardSize.rows,
+                    cols: boardSize.cols,
+                    win_length: boardSize.win_length
+                })
+            });
+
+            if (response.ok) {
+                const data = await response.json();
+                setGameId(data.game_id);
+                setBoardSpec(data.board_spec);
+                setBoard(data.board);
+                setHistory(data.move_history);
+            } else {
+                const error = await response.text();
+                console.error("Game API Error:", error);
+            }
+        } catch (err) {
+            console.error("Fetch error:", err);
+        }
     };
 
     const executeErase = async (index) => {
         if (board[index] === "") return;
 
-        if (!confirm(`Using "Erase" on this square costs 50 points. Proceed?`)) {
+        if (!confirm(`Using "Erase" on this square costs 50 wallet points. Proceed?`)) {
             setPowerUpMode(null);
             return;
         }
@@ -37,19 +78,14 @@ const XandZeroGame = ({ auth, onGameEnd }) => {
         setBoard(newBoard);
 
         try {
-            const response = await fetch('/api/xandzero/play', {
+            const response = await fetch(`/api/xandzero/games/${gameId}/erase`, {
                 method: 'POST',
                 headers: {
                     'Content-Type': 'application/json',
-                    'Authorization': `Bearer ${auth.user.access_token}`
+                    'Authorization': `Bearer ${auth.user.access_token}`,
+                    'Idempotency-Key': crypto.randomUUID()
                 },
-                body: JSON.stringify({
-                    board: board,
-                    move_history: history,
-                    game_mode: gameMode,
-                    used_power_up: true,
-                    erase_index: index
-                })
+                body: JSON.stringify({ index })
             });
 
             if (response.ok) {
@@ -71,7 +107,7 @@ const XandZeroGame = ({ auth, onGameEnd }) => {
     };
 
     const handleClick = async (index) => {
-        if (winner || loading || !gameMode) return;
+        if (winner || loading || !gameMode || !gameId) return;
 
         if (powerUpMode === 'erase') {
             executeErase(index);
@@ -90,18 +126,13 @@ const XandZeroGame = ({ auth, onGameEnd }) => {
         setAiAction(null);
 
         try {
-            const response = await fetch('/api/xandzero/play', {
+            const response = await fetch(`/api/xandzero/games/${gameId}/move`, {
                 method: 'POST',
                 headers: {
                     'Content-Type': 'application/json',
                     'Authorization': `Bearer ${auth.user.access_token}`
                 },
-                body: JSON.stringify({
-                    board: newBoard,
-                    move_history: newHistory,
-                    game_mode: gameMode,
-                    used_power_up: false
-                })
+                body: JSON.stringify({ index })
             });
 
             if (response.ok) {
@@ -113,12 +144,23 @@ const XandZeroGame = ({ auth, onGameEnd }) => {
                     setScoreEarned(data.score_increment);
                     if (onGameEnd) onGameEnd();
                 }
-                if (data.power_up_used_by_ai) {
-                    setAiAction(`AI used Erase on square ${data.ai_erase_index}!`);
+                if (data.ai_power_up) {
+                    const p = data.ai_power_up;
+                    const messages = {
+                        erase: `AI used Erase on square ${p.index}!`,
+                        swap: `AI swapped squares ${p.index} and ${p.target}!`,
+                        block: `AI blocked square ${p.index} for your next turn!`,
+                        double_move: 'AI used Double Move!',
+                        shield: `AI shielded square ${p.index}!`,
+                    };
+                    setAiAction(messages[p.power_up]);
                 }
             } else {
                 const error = await response.text();
                 console.error("Game API Error:", error);
+                // Server rejected the move; roll back the optimistic update
+                setBoard(board);
+                setHistory(history);
             }
         } catch (err) {
             console.error("Fetch error:", err);
@@ -129,6 +171,7 @@ const XandZeroGame = ({ auth, onGameEnd }) => {
 
     const resetGame = () => {
         setGameMode(null);
+        setGameId(null);
         setBoard(Array(9).fill(""));
         setHistory([]);
         setWinner(null);
@@ -141,6 +184,43 @@ const XandZeroGame = ({ auth, onGameEnd }) => {
         return (
             <div style={{ padding: '2rem', textAlign: 'center', background: 'rgba(255, 255, 255, 0.05)', borderRadius: '15px', border: '1px solid rgba(255, 255, 255, 0.1)' }}>
                 <h2 style={{ color: '#6366f1', marginBottom: '1.5rem' }}>Select Game Mode</h2>
+                <div style={{ display: 'flex', gap: '0.5rem', justifyContent: 'center', marginBottom: '1rem' }}>
+                    {BOARD_SIZES.map(size => (
+                        <button
+                            key={size.label}
+                            onClick={() => setBoardSize(size)}
+                            style={{
+                                padding: '0.4rem 1rem',
+                                cursor: 'pointer',
+                                borderRadius: '8px',
+                                border: '1px solid #a855f7',
+                                background: boardSize === size ? '#a855f7' : 'transparent',
+                                color: 'white'
+                            }}
+                        >
+                            {size.label}
+                        </button>
+                    ))}
+                </div>
+                <div style={{ display: 'flex', gap: '0.5rem', justifyContent: 'center', marginBottom: '1.5rem' }}>
+                    {['easy', 'medium', 'hard'].map(level => (
+                        <button
+                            key={level}
+                            onClick={() => setDifficulty(level)}
+                            style={{
+                                padding: '0.4rem 1rem',
+                                cursor: 'pointer',
+                                borderRadius: '8px',
+                                border: '1px solid #6366f1',
+                                background: difficulty === level ? '#6366f1' : 'transparent',
+                                color: 'white',
+                                textTransform: 'capitalize'
+                            }}
+                        >
+                            {level}
+                        </button>
+                    ))}
+                </div>
                 <div style={{ display: 'flex', gap: '1rem', justifyContent: 'center' }}>
                     <button
                         onClick={() => handleModeSelect('normal')}
@@ -174,17 +254,21 @@ const XandZeroGame = ({ auth, onGameEnd }) => {
                     </button>
                 </div>
                 <p style={{ marginTop: '1rem', color: '#94a3b8', fontSize: '0.9rem' }}>
-                    In Sudden Death, placing a 4th mark erases your 1st mark!
+                    In Sudden Death, placing one mark more than the win length erases your oldest mark!
+                </p>
+                <p style={{ color: '#94a3b8', fontSize: '0.9rem' }}>
+                    Rewards: Easy ×0.5, Medium ×1, Hard ×2
                 </p>
             </div>
         );
     }
 
     const xMoves = history.filter(i => board[i] === "X");
-    const oldestX = xMoves.length >= 3 ? xMoves[0] : null;
+    const oldestX = xMoves.length >= boardSpec.win_length ? xMoves[0] : null;
+    const compact = boardSpec.cols > 3;
 
     return (
-        <div className="game-container" style={{ padding: '2rem', borderRadius: '24px', background: 'rgba(15, 23, 42, 0.8)', backdropFilter: 'blur(10px)', border: '1px solid rgba(255, 255, 255, 0.1)', maxWidth: '420px', margin: '0 auto' }}>
+        <div className="game-container" style={{ padding: '2rem', borderRadius: '24px', background: 'rgba(15, 23, 42, 0.8)', backdropFilter: 'blur(10px)', border: '1px solid rgba(255, 255, 255, 0.1)', maxWidth: compact ? '720px' : '420px', margin: '0 auto' }}>
             <div style={{ display: 'flex', justifyContent: 'space-between', alignItems: 'center', marginBottom: '1rem' }}>
                 <h2 style={{ margin: 0, color: gameMode === 'sudden_death' ? '#ef4444' : '#6366f1' }}>
                     {gameMode === 'sudden_death' ? '💀 Sudden Death' : '🎮 X and Zero'}
@@ -216,21 +300,21 @@ const XandZeroGame = ({ auth, onGameEnd }) => {
             {aiAction && <div style={{ color: '#f59e0b', fontSize: '0.9rem', marginBottom: '0.8rem', textAlign: 'center', fontWeight: 'bold' }}>{aiAction}</div>}
             {powerUpMode === 'erase' && <div style={{ color: '#eab308', fontSize: '0.9rem', marginBottom: '0.8rem', textAlign: 'center', animation: 'pulse 1.5s infinite' }}>Select a square to erase!</div>}
 
-            <div className="board" style={{ display: 'grid', gridTemplateColumns: 'repeat(3, 1fr)', gap: '12px' }}>
+            <div className="board" style={{ display: 'grid', gridTemplateColumns: `repeat(${boardSpec.cols}, 1fr)`, gap: compact ? '2px' : '12px' }}>
                 {board.map((cell, i) => (
                     <div
                         key={i}
                         onClick={() => handleClick(i)}
                         style={{
-                            height: '110px',
+                            height: compact ? '40px' : '110px',
                             background: 'rgba(30, 41, 59, 0.6)',
                             display: 'flex',
                             alignItems: 'center',
                             justifyContent: 'center',
-                            fontSize: '2.8rem',
+                            fontSize: compact ? '1.2rem' : '2.8rem',
                             fontWeight: 'bold',
                             cursor: (winner || loading) ? 'default' : (powerUpMode === 'erase' ? (cell !== "" ? 'crosshair' : 'default') : (cell === "" ? 'pointer' : 'default')),
-                            borderRadius: '16px',
+                            borderRadius: compact ? '4px' : '16px',
                             transition: 'all 0.2s ease',
                             border: powerUpMode === 'erase' && cell !== "" ? '2px solid #eab308' : (i === oldestX && gameMode === 'sudden_death' ? '2px dashed #ef4444' : '1px solid rgba(255, 255, 255, 0.1)'),
                             color: cell === "X" ? '#60a5fa' : '#f472b6',
@@ -240,7 +324,7 @@ const XandZeroGame = ({ auth, onGameEnd }) => {
                         }}
                     >
                         {cell}
-                        {i === oldestX && gameMode === 'sudden_death' && !powerUpMode && (
+                        {i === oldestX && gameMode === 'sudden_death' && !powerUpMode && !compact && (
                             <span style={{ position: 'absolute', top: '4px', right: '8px', fontSize: '0.6rem', color: '#ef4444', fontWeight: 'normal' }}>VANISHING</span>
                         )}
                     </div>
@@ -22,7 +22,181 @@ CREATE TABLE IF NOT EXISTS leaderboard (
     game_name VARCHAR(50) DEFAULT 'default',
     username VARCHAR(255),
     score INT NOT NULL,
+    games_played INT NOT NULL DEFAULT 0,
     updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
     PRIMARY KEY (user_id, game_name)
 );
 
+-- Boards created before games were counted
+ALTER TABLE leaderboard ADD COLUMN IF NOT EXISTS games_played INT NOT NULL DEFAULT 0;
+
+-- Ranking order: highest score first, ties go to whoever reached the score first
+CREATE INDEX IF NOT EXISTS idx_leaderboard_ranking ON leaderboard (game_name, score DESC, updated_at ASC);
+
+-- Every score that reached a leaderboard with when it was made, so seasons rank what was played in them
+CREATE TABLE IF NOT EXISTS leaderboard_scores (
+    id BIGSERIAL PRIMARY KEY,
+    user_id VARCHAR(255) NOT NULL,
+    game_name VARCHAR(50) NOT NULL,
+    username VARCHAR(255),
+    score INT NOT NULL,
+    scored_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_leaderboard_scores_window ON leaderboard_scores (game_name, scored_at);
+
+-- Named time windows (e.g. monthly seasons) used to slice leaderboard exports
+CREATE TABLE IF NOT EXISTS seasons (
+    id SERIAL PRIMARY KEY,
+    game_name VARCHAR(50) NOT NULL,
+    name VARCHAR(100) NOT NULL,
+    starts_at TIMESTAMP NOT NULL,
+    ends_at TIMESTAMP NOT NULL,
+    UNIQUE (game_name, name)
+);
+
+-- Glicko-2 skill ratings per game and mode, kept separate from accumulated points
+CREATE TABLE IF NOT EXISTS skill_ratings (
+    user_id VARCHAR(255),
+    game_name VARCHAR(50),
+    game_mode VARCHAR(20),
+    username VARCHAR(255),
+    rating DOUBLE PRECISION NOT NULL,
+    deviation DOUBLE PRECISION NOT NULL,
+    volatility DOUBLE PRECISION NOT NULL,
+    games_played INT NOT NULL DEFAULT 0,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, game_name, game_mode)
+);
+
+CREATE INDEX IF NOT EXISTS idx_skill_ratings_ranking ON skill_ratings (game_name, game_mode, rating DESC);
+
+-- Rating after every rated game, for graphs
+CREATE TABLE IF NOT EXISTS rating_history (
+    id BIGSERIAL PRIMARY KEY,
+    user_id VARCHAR(255) NOT NULL,
+    game_name VARCHAR(50) NOT NULL,
+    game_mode VARCHAR(20) NOT NULL,
+    game_id VARCHAR(64),
+    rating DOUBLE PRECISION NOT NULL,
+    deviation DOUBLE PRECISION NOT NULL,
+    result DOUBLE PRECISION NOT NULL,
+    recorded_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_rating_history_user ON rating_history (user_id, game_name, game_mode, id);
+
+-- Finished X and Zero games with their full action log, for history and replays
+CREATE TABLE IF NOT EXISTS xandzero_games (
+    game_id VARCHAR(64) PRIMARY KEY,
+    x_user_id VARCHAR(255) NOT NULL,
+    x_username VARCHAR(255),
+    o_user_id VARCHAR(255), -- NULL against the computer
+    o_username VARCHAR(255),
+    pvp BOOLEAN NOT NULL,
+    game_mode VARCHAR(20) NOT NULL,
+    difficulty VARCHAR(10), -- Computer games only
+    board_rows INT NOT NULL,
+    board_cols INT NOT NULL,
+    win_length INT NOT NULL,
+    winner VARCHAR(4) NOT NULL, -- 'X', 'O' or 'Draw'
+    finish_reason VARCHAR(10),
+    actions JSONB NOT NULL,
+    started_at TIMESTAMP NOT NULL,
+    finished_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_xandzero_games_x ON xandzero_games (x_user_id, finished_at DESC);
+CREATE INDEX IF NOT EXISTS idx_xandzero_games_o ON xandzero_games (o_user_id, finished_at DESC);
+
+-- Points available for power-ups; only changed together with a ledger entry
+CREATE TABLE IF NOT EXISTS wallets (
+    user_id VARCHAR(255) PRIMARY KEY,
+    balance INT NOT NULL DEFAULT 0 CHECK (balance >= 0),
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Append-only history of every wallet change
+CREATE TABLE IF NOT EXISTS wallet_ledger (
+    id BIGSERIAL PRIMARY KEY,
+    user_id VARCHAR(255) NOT NULL,
+    kind VARCHAR(10) NOT NULL CHECK (kind IN ('earn', 'spend', 'refund', 'grant')),
+    amount INT NOT NULL, -- Signed: spends are negative
+    balance_after INT NOT NULL,
+    reason VARCHAR(100) NOT NULL,
+    reference VARCHAR(100),
+    idempotency_key VARCHAR(100),
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (user_id, idempotency_key)
+);
+
+CREATE INDEX IF NOT EXISTS idx_wallet_ledger_user ON wallet_ledger (user_id, id DESC);
+
+-- Every dragonball run with what is needed to re-simulate it
+CREATE TABLE IF NOT EXISTS dragonball_runs (
+    run_id VARCHAR(36) PRIMARY KEY,
+    user_id VARCHAR(255) NOT NULL,
+    username VARCHAR(255),
+    room_id VARCHAR(36), -- Co-op room; every defender of a co-op run gets their own row
+    seat INT NOT NULL DEFAULT 0, -- Defender this row's player controlled
+    players INT NOT NULL DEFAULT 1,
+    seed BIGINT NOT NULL, -- u64 seed stored bit for bit
+    sim_version INT NOT NULL DEFAULT 1, -- Simulation version the run replays under; 1 is the StdRng era
+    tick_rate INT NOT NULL DEFAULT 60, -- Simulation steps per second; part of what makes the run reproducible
+    difficulty VARCHAR(32) NOT NULL DEFAULT 'normal', -- Preset name
+    config JSONB, -- Gameplay values the run was played with; NULL means the normal preset
+    inputs JSONB NOT NULL, -- [{ "tick": ..., "y": ... }]
+    score INT NOT NULL, -- Points of this row's defender
+    team_score INT, -- Points of the whole run; NULL for runs recorded before co-op, where it equals score
+    wave INT NOT NULL DEFAULT 1, -- Wave the run ended in
+    ticks BIGINT NOT NULL,
+    game_over_tick BIGINT, -- NULL if the player left mid-run
+    verified BOOLEAN NOT NULL DEFAULT FALSE,
+    flagged BOOLEAN NOT NULL DEFAULT FALSE, -- Re-simulation disagreed with the recorded result
+    dropped_frames INT NOT NULL DEFAULT 0, -- Client frames dropped as oversized, malformed or over the rate limit
+    disconnect_reason VARCHAR(32), -- message_flood, malformed_frames or oversized_frames if the player was cut off
+    started_at TIMESTAMP NOT NULL,
+    finished_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Columns added since the table was first created; existing runs get the values they were played with
+ALTER TABLE dragonball_runs ADD COLUMN IF NOT EXISTS tick_rate INT NOT NULL DEFAULT 60;
+ALTER TABLE dragonball_runs ADD COLUMN IF NOT EXISTS room_id VARCHAR(36);
+ALTER TABLE dragonball_runs ADD COLUMN IF NOT EXISTS seat INT NOT NULL DEFAULT 0;
+ALTER TABLE dragonball_runs ADD COLUMN IF NOT EXISTS players INT NOT NULL DEFAULT 1;
+ALTER TABLE dragonball_runs ADD COLUMN IF NOT EXISTS team_score INT;
+ALTER TABLE dragonball_runs ADD COLUMN IF NOT EXISTS difficulty VARCHAR(32) NOT NULL DEFAULT 'normal';
+ALTER TABLE dragonball_runs ADD COLUMN IF NOT EXISTS config JSONB;
+ALTER TABLE dragonball_runs ADD COLUMN IF NOT EXISTS wave INT NOT NULL DEFAULT 1;
+ALTER TABLE dragonball_runs ADD COLUMN IF NOT EXISTS dropped_frames INT NOT NULL DEFAULT 0;
+ALTER TABLE dragonball_runs ADD COLUMN IF NOT EXISTS disconnect_reason VARCHAR(32);
+ALTER TABLE dragonball_runs ADD COLUMN IF NOT EXISTS sim_version INT NOT NULL DEFAULT 1;
+
+CREATE INDEX IF NOT EXISTS idx_dragonball_runs_user ON dragonball_runs (user_id, finished_at DESC);
+CREATE INDEX IF NOT EXISTS idx_dragonball_runs_room ON dragonball_runs (room_id) WHERE room_id IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_dragonball_runs_flagged ON dragonball_runs (finished_at DESC) WHERE flagged OR disconnect_reason IS NOT NULL;
+
+-- Admin-defined dragonball difficulty presets next to the built-in easy/normal/hard/insane
+CREATE TABLE IF NOT EXISTS dragonball_presets (
+    name VARCHAR(32) PRIMARY KEY,
+    config JSONB NOT NULL, -- GameConfig
+    updated_by VARCHAR(255),
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Achievement progress per player; counters either add up or keep the best value seen
+CREATE TABLE IF NOT EXISTS achievement_progress (
+    user_id VARCHAR(255) NOT NULL,
+    counter VARCHAR(64) NOT NULL, -- e.g. fireballs_extinguished, xandzero_wins
+    value BIGINT NOT NULL DEFAULT 0,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, counter)
+);
+
+-- Unlocked achievements; the catalogue itself lives in the backend
+CREATE TABLE IF NOT EXISTS achievements (
+    user_id VARCHAR(255) NOT NULL,
+    achievement_id VARCHAR(64) NOT NULL,
+    unlocked_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, achievement_id)
+);